@@ -0,0 +1,181 @@
+use crate::common::{git_dir, is_bare};
+use crate::error::Context;
+use std::{fs, path::Path};
+
+/// The state of an attribute for a path, if any line of the attribute files
+/// mentions it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeValue {
+    /// `attr`
+    Set,
+    /// `-attr`
+    Unset,
+    /// `attr=value`
+    Value(String),
+}
+
+/// One line of an attribute file: a pattern and the attributes it assigns.
+#[derive(Debug)]
+struct Rule {
+    /// Directory of the attribute file relative to the working tree, empty
+    /// or ending with `/`.
+    base: String,
+    pattern: String,
+    attributes: Vec<(String, AttributeValue)>,
+}
+
+/// The attributes assigned to paths by the `.gitattributes` files of the
+/// working tree and `info/attributes`.
+#[derive(Debug, Default)]
+pub struct Attributes {
+    rules: Vec<Rule>,
+}
+
+impl Attributes {
+    /// Read the attribute files that apply to `path`, relative to the
+    /// working tree: the `.gitattributes` in each of its parent directories
+    /// and `info/attributes`, which take precedence in this order.
+    pub fn read(path: &str) -> crate::Result<Attributes> {
+        let mut attributes = Attributes::default();
+        let git_dir = git_dir();
+        if !is_bare(&git_dir) {
+            let work_tree = git_dir.parent().unwrap_or(Path::new("."));
+            let mut base = String::new();
+            for component in path.split('/') {
+                attributes.read_file(&work_tree.join(&base).join(".gitattributes"), &base)?;
+                base.push_str(component);
+                base.push('/');
+            }
+        }
+        attributes.read_file(&git_dir.join("info/attributes"), "")?;
+
+        Ok(attributes)
+    }
+
+    fn read_file(&mut self, file: &Path, base: &str) -> crate::Result<()> {
+        let contents = match fs::read_to_string(file) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err).context(format!("read {}", file.display())),
+        };
+        for line in contents.lines() {
+            let mut fields = line.split_whitespace();
+            let Some(pattern) = fields.next().filter(|pattern| !pattern.starts_with('#')) else {
+                continue;
+            };
+            let attributes = fields
+                .flat_map(|field| match field.split_once('=') {
+                    Some((name, value)) => vec![(name, AttributeValue::Value(value.to_owned()))],
+                    None if field.starts_with('-') => vec![(&field[1..], AttributeValue::Unset)],
+                    // The only built-in macro attribute.
+                    None if field == "binary" => vec![
+                        ("binary", AttributeValue::Set),
+                        ("diff", AttributeValue::Unset),
+                        ("text", AttributeValue::Unset),
+                    ],
+                    None => vec![(field, AttributeValue::Set)],
+                })
+                .map(|(name, value)| (name.to_owned(), value))
+                .collect();
+            self.rules.push(Rule {
+                base: base.to_owned(),
+                pattern: pattern.to_owned(),
+                attributes,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// The value of the attribute `name` for `path`, from the last matching
+    /// line that mentions it.
+    pub fn get(&self, path: &str, name: &str) -> Option<&AttributeValue> {
+        self.rules
+            .iter()
+            .rev()
+            .filter(|rule| rule.matches(path))
+            .find_map(|rule| {
+                rule.attributes
+                    .iter()
+                    .rev()
+                    .find(|(attribute, _)| attribute == name)
+                    .map(|(_, value)| value)
+            })
+    }
+}
+
+impl Rule {
+    /// Patterns without a slash match the file name in any directory below
+    /// the attribute file, others the path relative to it.
+    fn matches(&self, path: &str) -> bool {
+        let Some(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        match self.pattern.strip_prefix('/') {
+            Some(pattern) => wildmatch(pattern.as_bytes(), relative.as_bytes()),
+            None if self.pattern.contains('/') => {
+                wildmatch(self.pattern.as_bytes(), relative.as_bytes())
+            }
+            None => {
+                let name = relative.rsplit('/').next().unwrap_or(relative);
+                wildmatch(self.pattern.as_bytes(), name.as_bytes())
+            }
+        }
+    }
+}
+
+/// Match `text` against the glob `pattern`, where `*` and `?` do not match
+/// a slash but `**` matches across directories.
+fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+            (0..=text.len()).any(|skip| wildmatch(rest, &text[skip..]))
+        }
+        [b'*', rest @ ..] => (0..=text.len())
+            .take_while(|&skip| skip == 0 || text[skip - 1] != b'/')
+            .any(|skip| wildmatch(rest, &text[skip..])),
+        [b'?', rest @ ..] => {
+            matches!(text.first(), Some(&c) if c != b'/') && wildmatch(rest, &text[1..])
+        }
+        [c, rest @ ..] => text.first() == Some(c) && wildmatch(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_globs() {
+        let cases: &[(&str, &str, bool)] = &[
+            ("*.txt", "notes.txt", true),
+            ("*.txt", "notes.txt.bak", false),
+            ("*.txt", "docs/notes.txt", false),
+            ("docs/*.md", "docs/readme.md", true),
+            ("docs/*.md", "docs/api/readme.md", false),
+            ("?.c", "a.c", true),
+            ("?.c", "ab.c", false),
+            ("a?b", "a/b", false),
+            ("**/build", "build", true),
+            ("**/build", "src/out/build", true),
+            ("src/**", "src/a/b.rs", true),
+            ("src/**", "lib/a.rs", false),
+            ("a/**/b", "a/b", true),
+            ("a/**/b", "a/x/y/b", true),
+            ("a/**/b", "a/x/c", false),
+            ("Makefile", "Makefile", true),
+            ("Makefile", "makefile", false),
+            ("", "", true),
+            ("*", "", true),
+        ];
+        for &(pattern, text, expected) in cases {
+            assert_eq!(
+                wildmatch(pattern.as_bytes(), text.as_bytes()),
+                expected,
+                "{pattern:?} against {text:?}"
+            );
+        }
+    }
+}
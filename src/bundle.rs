@@ -0,0 +1,148 @@
+use crate::common::Object;
+use crate::error::Context;
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::refs;
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    path::Path,
+};
+
+const V2_SIGNATURE: &str = "# v2 git bundle";
+const V3_SIGNATURE: &str = "# v3 git bundle";
+
+/// A bundle file: refs and the pack needed to fetch them, given that the
+/// receiving repository already has the prerequisite commits.
+#[derive(Debug, Clone, Default)]
+pub struct Bundle {
+    /// Format version, 2 or 3.
+    pub version: u32,
+    /// Version 2 bundles are always SHA-1.
+    pub object_format: ObjectFormat,
+    /// Commits the pack depends on, with their subject lines.
+    pub prerequisites: Vec<(ObjectId, String)>,
+    /// `(hash, refname)` pairs.
+    pub refs: Vec<(ObjectId, String)>,
+    pub pack: Vec<u8>,
+}
+
+impl Bundle {
+    pub fn read(path: impl AsRef<Path>) -> crate::Result<Bundle> {
+        let path = path.as_ref();
+        let file = fs::File::open(path).context(format!("could not open '{}'", path.display()))?;
+        Bundle::read_from(BufReader::new(file)).context(format!(
+            "'{}' does not look like a bundle file",
+            path.display()
+        ))
+    }
+
+    pub fn read_from(mut reader: impl BufRead) -> crate::Result<Bundle> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            crate::ensure!(
+                reader.read_line(&mut line).context("read bundle header")? > 0,
+                "unexpected end of bundle header"
+            );
+            let line = line
+                .strip_suffix('\n')
+                .context("unterminated header line")?;
+            if line.is_empty() {
+                break;
+            }
+            lines.push(line.to_owned());
+        }
+
+        let mut lines = lines.into_iter();
+        let version = match lines.next().as_deref() {
+            Some(V2_SIGNATURE) => 2,
+            Some(V3_SIGNATURE) => 3,
+            signature => crate::bail!("unsupported bundle signature {signature:?}"),
+        };
+        let mut bundle = Bundle {
+            version,
+            ..Default::default()
+        };
+        for line in lines {
+            if let Some(capability) = line.strip_prefix('@') {
+                crate::ensure!(version == 3, "capability {capability:?} in a v2 bundle");
+                match capability.split_once('=') {
+                    Some(("object-format", format)) => bundle.object_format = format.parse()?,
+                    _ => crate::bail!("unsupported bundle capability {capability:?}"),
+                }
+            } else if let Some(prerequisite) = line.strip_prefix('-') {
+                let (hash, comment) = prerequisite.split_once(' ').unwrap_or((prerequisite, ""));
+                bundle
+                    .prerequisites
+                    .push((hash.parse()?, comment.to_owned()));
+            } else {
+                let (hash, name) = line
+                    .split_once(' ')
+                    .context(format!("invalid ref line {line:?}"))?;
+                refs::check_ref_format(name)?;
+                bundle.refs.push((hash.parse()?, name.to_owned()));
+            }
+        }
+        reader
+            .read_to_end(&mut bundle.pack)
+            .context("read bundle pack")?;
+
+        Ok(bundle)
+    }
+
+    /// Prerequisite commits missing from the repository.
+    pub fn missing_prerequisites(&self) -> crate::Result<Vec<ObjectId>> {
+        let mut missing = Vec::new();
+        for (hash, _) in &self.prerequisites {
+            if !Object::exists(hash)? {
+                missing.push(*hash);
+            }
+        }
+
+        Ok(missing)
+    }
+
+    pub fn write_to(&self, mut writer: impl Write) -> crate::Result<()> {
+        let mut header = String::new();
+        match self.version {
+            2 => {
+                crate::ensure!(
+                    self.object_format == ObjectFormat::Sha1,
+                    "version 2 bundles cannot store {} objects",
+                    self.object_format
+                );
+                header.push_str(&format!("{V2_SIGNATURE}\n"));
+            }
+            3 => header.push_str(&format!(
+                "{V3_SIGNATURE}\n@object-format={}\n",
+                self.object_format
+            )),
+            version => crate::bail!("unsupported bundle version {version}"),
+        }
+        for (hash, comment) in &self.prerequisites {
+            header.push_str(&format!("-{hash} {comment}\n"));
+        }
+        for (hash, name) in &self.refs {
+            header.push_str(&format!("{hash} {name}\n"));
+        }
+        header.push('\n');
+        writer
+            .write_all(header.as_bytes())
+            .context("write bundle")?;
+        writer.write_all(&self.pack).context("write bundle")?;
+
+        Ok(())
+    }
+}
+
+/// Whether the file at `path` starts with a bundle signature.
+pub fn is_bundle(path: impl AsRef<Path>) -> bool {
+    let mut signature = [0; V2_SIGNATURE.len() + 1];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut signature))
+        .is_ok_and(|_| {
+            [V2_SIGNATURE, V3_SIGNATURE]
+                .iter()
+                .any(|expected| signature == format!("{expected}\n").as_bytes())
+        })
+}
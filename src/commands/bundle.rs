@@ -0,0 +1,177 @@
+use crate::bundle::Bundle;
+use crate::common::{self, reachable_from, Commit, Object, ObjectType};
+use crate::error::Context;
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::pack;
+use crate::refs;
+use crate::revision;
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs,
+    path::Path,
+};
+
+/// Write a bundle of format `version` (by default 2, or 3 in a SHA-256
+/// repository) to `file` containing the refs named by
+/// `revs` and the history needed to fetch them. `revs` are rev-list style
+/// arguments: refs or object names, optionally with `~<n>` and `^<n>`
+/// suffixes, `^<rev>` and `<rev>..<rev>` to leave out history the receiver
+/// already has, and `--all`, `--branches` and `--tags`.
+pub fn create(file: &Path, revs: &[String], version: Option<u32>) -> crate::Result<Bundle> {
+    // Tips paired with their ref names; only named tips are recorded as refs.
+    let mut include: Vec<(Option<String>, ObjectId)> = Vec::new();
+    let mut exclude = Vec::new();
+    for arg in revs {
+        let prefix = match arg.as_str() {
+            "--all" => Some(""),
+            "--branches" => Some("refs/heads/"),
+            "--tags" => Some("refs/tags/"),
+            _ => None,
+        };
+        if let Some(prefix) = prefix {
+            if arg == "--all" {
+                if let Some(head) = refs::read_ref("HEAD")? {
+                    include.push((Some("HEAD".to_owned()), head));
+                }
+            }
+            for (name, hash) in refs::list_refs()? {
+                if name.starts_with(prefix) {
+                    include.push((Some(name), hash));
+                }
+            }
+        } else if let Some(rev) = arg.strip_prefix('^') {
+            exclude.push(revision::resolve(rev)?.1);
+        } else if let Some((from, to)) = arg.split_once("..") {
+            exclude.push(revision::resolve(if from.is_empty() { "HEAD" } else { from })?.1);
+            include.push(revision::resolve(if to.is_empty() { "HEAD" } else { to })?);
+        } else if arg.starts_with('-') {
+            crate::bail!("unsupported option {arg}");
+        } else {
+            include.push(revision::resolve(arg)?);
+        }
+    }
+
+    let object_format = ObjectFormat::current()?;
+    // Only version 3 can record an object format other than SHA-1.
+    let default_version = if object_format == ObjectFormat::Sha1 {
+        2
+    } else {
+        3
+    };
+    let mut bundle = Bundle {
+        version: version.unwrap_or(default_version),
+        object_format,
+        ..Default::default()
+    };
+    for (name, hash) in &include {
+        if let Some(name) = name {
+            if !bundle.refs.iter().any(|(_, existing)| existing == name) {
+                bundle.refs.push((*hash, name.clone()));
+            }
+        }
+    }
+    crate::ensure!(!bundle.refs.is_empty(), "Refusing to create empty bundle.");
+
+    let excluded = reachable_from(exclude, &HashSet::new())?;
+    let mut hashes: Vec<ObjectId> =
+        reachable_from(include.into_iter().map(|(_, hash)| hash), &excluded)?
+            .into_iter()
+            .collect();
+    hashes.sort();
+    let mut prerequisites = BTreeSet::new();
+    let mut objects = Vec::new();
+    for hash in hashes {
+        let object = Object::read(&hash)?;
+        if object.ty == ObjectType::Commit {
+            let commit = Commit::read(&hash)?;
+            prerequisites.extend(
+                commit
+                    .parents
+                    .into_iter()
+                    .filter(|parent| excluded.contains(parent)),
+            );
+        }
+        objects.push((hash, object));
+    }
+    for hash in prerequisites {
+        let subject = Commit::read(&hash)?
+            .message
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_owned();
+        bundle.prerequisites.push((hash, subject));
+    }
+    bundle.pack = pack::encode_pack(&objects)?;
+
+    let mut contents = Vec::new();
+    bundle.write_to(&mut contents)?;
+    fs::write(file, contents).context(format!("write {}", file.display()))?;
+
+    Ok(bundle)
+}
+
+/// Check that `file` is a bundle with an intact pack whose prerequisite
+/// commits are all in the repository.
+pub fn verify(file: &Path) -> crate::Result<Bundle> {
+    let bundle = Bundle::read(file)?;
+    let pack = &bundle.pack;
+    crate::ensure!(
+        pack.len() >= 32 && pack.starts_with(b"PACK"),
+        "{}: invalid pack",
+        file.display()
+    );
+    let object_format = ObjectFormat::current()?;
+    crate::ensure!(
+        bundle.object_format == object_format,
+        "{}: bundle uses {} but the repository uses {object_format}",
+        file.display(),
+        bundle.object_format,
+    );
+    let (contents, checksum) = pack.split_at(pack.len() - bundle.object_format.hash_len());
+    crate::ensure!(
+        common::hash(contents)?.as_bytes() == checksum,
+        "{}: pack checksum mismatch",
+        file.display()
+    );
+    let missing = bundle.missing_prerequisites()?;
+    crate::ensure!(
+        missing.is_empty(),
+        "Repository lacks these prerequisite commits:\n{}",
+        missing
+            .iter()
+            .map(ObjectId::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+
+    Ok(bundle)
+}
+
+/// The `(hash, refname)` pairs of the bundle `file`, limited to refs matching
+/// one of `refnames` if any are given.
+pub fn list_heads(file: &Path, refnames: &[String]) -> crate::Result<Vec<(ObjectId, String)>> {
+    Ok(filter_refs(Bundle::read(file)?.refs, refnames))
+}
+
+/// Verify the bundle `file` and store its objects in the repository without
+/// updating any refs. Returns its refs as `list_heads` does.
+pub fn unbundle(file: &Path, refnames: &[String]) -> crate::Result<Vec<(ObjectId, String)>> {
+    let bundle = verify(file)?;
+    pack::index_pack(&bundle.pack).context("index bundle pack")?;
+
+    Ok(filter_refs(bundle.refs, refnames))
+}
+
+/// Keep the refs named by one of `refnames` in full or by a trailing part such
+/// as `main` for `refs/heads/main`.
+fn filter_refs(refs: Vec<(ObjectId, String)>, refnames: &[String]) -> Vec<(ObjectId, String)> {
+    refs.into_iter()
+        .filter(|(_, name)| {
+            refnames.is_empty()
+                || refnames
+                    .iter()
+                    .any(|pattern| name == pattern || name.ends_with(&format!("/{pattern}")))
+        })
+        .collect()
+}
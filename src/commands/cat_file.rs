@@ -1,9 +1,180 @@
-use crate::common::Object;
-use std::io::{stdout, Write};
+use crate::common::{Object, ObjectType, TreeEntry};
+use crate::convert;
+use crate::error::{Context, Error};
+use crate::object_id::ObjectId;
+use crate::object_store::{self, ObjectStore};
+use crate::revision;
+use std::io::{self, stdout, BufRead, Read, Write};
 
-pub fn run(object_hash: &str) -> anyhow::Result<()> {
+/// Format of `--batch` and `--batch-check` when none is given.
+pub const DEFAULT_BATCH_FORMAT: &str = "%(objectname) %(objecttype) %(objectsize)";
+
+/// A conversion applied to a blob for the path it is stored at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conversion {
+    /// `--textconv`: the `textconv` command of the path's diff driver.
+    Textconv,
+    /// `--filters`: the smudge filter and line ending conversion applied
+    /// when the path is checked out.
+    Filters,
+}
+
+/// Pretty-print the object to stdout: trees are listed like `ls-tree`,
+/// other objects are written as they are. Loose blobs are streamed, so a
+/// huge blob is piped through in constant memory.
+pub fn run(object_hash: &ObjectId) -> crate::Result<()> {
+    let mut reader = Object::open(object_hash)?;
+    let mut stdout = stdout().lock();
+    if reader.ty == ObjectType::Tree {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+        let entries = TreeEntry::parse_all(&contents, object_hash.format())
+            .ok_or_else(|| Error::CorruptObject(format!("invalid tree object {object_hash}")))?;
+        for entry in entries {
+            writeln!(stdout, "{entry}")?;
+        }
+    } else {
+        io::copy(&mut reader, &mut stdout)?;
+    }
+
+    Ok(())
+}
+
+/// Write the blob `object_hash` to stdout, converted for the file at `path`
+/// with `conversion`.
+pub fn run_converted(
+    object_hash: &ObjectId,
+    path: &str,
+    conversion: Conversion,
+) -> crate::Result<()> {
     let object = Object::read(object_hash)?;
-    stdout().write_all(&object.contents)?;
+    crate::ensure!(
+        object.ty == ObjectType::Blob,
+        "{object_hash} is a {}, not a blob",
+        object.ty
+    );
+    let contents = match conversion {
+        Conversion::Textconv => convert::textconv(object.contents, path)?,
+        Conversion::Filters => convert::to_working_tree(object.contents, path)?,
+    };
+    stdout().write_all(&contents)?;
+
+    Ok(())
+}
+
+pub fn object_type(object_hash: &ObjectId) -> crate::Result<ObjectType> {
+    Ok(Object::read_header(object_hash)?.0)
+}
+
+pub fn object_size(object_hash: &ObjectId) -> crate::Result<u64> {
+    Ok(Object::read_header(object_hash)?.1)
+}
+
+/// For each object named on a line of `input`, as for
+/// [`revision::resolve_object`], write a line in `format` to `output`,
+/// followed by the object's contents if `contents` is set, `<name> missing`
+/// if there is no such object, or `<name> ambiguous` if an abbreviated name
+/// matches several. Output is flushed after each object, so a script can
+/// query objects one at a time.
+///
+/// `format` may contain `%(objectname)`, `%(objecttype)`, `%(objectsize)`
+/// and `%(rest)`, the text after the first whitespace of the input line.
+pub fn batch(
+    input: impl BufRead,
+    mut output: impl Write,
+    format: &str,
+    contents: bool,
+) -> crate::Result<()> {
+    // Names may only contain whitespace if it is not split off as `%(rest)`.
+    let split_rest = format.contains("%(rest)");
+    for line in input.lines() {
+        let line = line?;
+        let (name, rest) = match line.split_once(char::is_whitespace) {
+            Some((name, rest)) if split_rest => (name, rest),
+            _ => (line.as_str(), ""),
+        };
+        match revision::resolve_object(name) {
+            Ok(hash) if Object::exists(&hash)? => {
+                batch_object(&mut output, &hash, format, rest, contents)?
+            }
+            Ok(_) => writeln!(output, "{name} missing")?,
+            // Names that do not resolve are reported, but failing to read
+            // the repository is not.
+            Err(err) => match err.root() {
+                Error::AmbiguousObject(_) => writeln!(output, "{name} ambiguous")?,
+                Error::Message(_) | Error::ObjectNotFound(_) | Error::InvalidHash(_) => {
+                    writeln!(output, "{name} missing")?
+                }
+                _ => return Err(err),
+            },
+        }
+        output.flush()?;
+    }
 
     Ok(())
 }
+
+/// Like [`batch`] for all objects in the repository and its alternates,
+/// sorted by hash, instead of those named on stdin.
+pub fn batch_all_objects(
+    mut output: impl Write,
+    format: &str,
+    contents: bool,
+) -> crate::Result<()> {
+    for hash in object_store::repository()?.list()? {
+        batch_object(&mut output, &hash, format, "", contents)?;
+    }
+    output.flush()?;
+
+    Ok(())
+}
+
+fn batch_object(
+    output: &mut impl Write,
+    hash: &ObjectId,
+    format: &str,
+    rest: &str,
+    contents: bool,
+) -> crate::Result<()> {
+    if contents {
+        let mut reader = Object::open(hash)?;
+        let info = expand_format(format, hash, reader.ty, reader.size, rest)?;
+        writeln!(output, "{info}")?;
+        io::copy(&mut reader, output)?;
+        writeln!(output)?;
+    } else {
+        let (ty, size) = Object::read_header(hash)?;
+        writeln!(output, "{}", expand_format(format, hash, ty, size, rest)?)?;
+    }
+
+    Ok(())
+}
+
+/// Replace the `%(atom)`s of a batch `format`.
+fn expand_format(
+    format: &str,
+    hash: &ObjectId,
+    ty: ObjectType,
+    size: u64,
+    rest: &str,
+) -> crate::Result<String> {
+    let mut expanded = String::new();
+    let mut remaining = format;
+    while let Some(start) = remaining.find("%(") {
+        expanded.push_str(&remaining[..start]);
+        let end = remaining[start..]
+            .find(')')
+            .context(format!("malformed format {format:?}"))?;
+        match &remaining[start + 2..start + end] {
+            "objectname" => expanded.push_str(&hash.to_string()),
+            "objecttype" => expanded.push_str(&ty.to_string()),
+            "objectsize" => expanded.push_str(&size.to_string()),
+            "rest" => expanded.push_str(rest),
+            atom => crate::bail!("unknown format element: {atom}"),
+        }
+        remaining = &remaining[start + end + 1..];
+    }
+    expanded.push_str(remaining);
+
+    Ok(expanded)
+}
@@ -1,193 +1,195 @@
-use std::io::{stdout, Write};
-
-use nom::{
-    bytes::complete::{tag, take, take_while},
-    IResult,
+use crate::commands::{fetch, init};
+use crate::common::{find_git_dir, read_tree, Commit, FileMode, Object};
+use crate::config::Config;
+use crate::error::Context;
+use crate::object_id::ObjectId;
+use crate::object_store;
+use crate::promisor::{self, ObjectFilter};
+use crate::refs;
+use crate::transport::{self, Deepen, LocalTransport, Transport};
+use std::{
+    fs,
+    path::{Path, PathBuf},
 };
 
-#[allow(dead_code)]
-#[derive(Debug)]
-struct ReferenceDiscovery {
-    sha_ref_pairs: Vec<(String, String)>,
-    capabilities: Vec<String>,
-}
-
-impl ReferenceDiscovery {
-    pub fn parse(input: &str) -> IResult<&str, Self> {
-        println!("--- PARSING REFERENCE DISCOVERY ---");
-        let lines: Vec<&str> = input.split('\n').collect();
-
-        // assert!(lines[0] == "001e# service=git-upload-pack");
-        // assert!(&lines[1][..4] == "0000");
-
-        // // First ref
-        // let first_ref = &lines[1][4..];
-        // dbg!(first_ref);
-        // let size = usize::from_str_radix(&first_ref[..4], 16).unwrap();
-        // dbg!(size);
-        // let sha = &first_ref[4..44];
-
-        // First line
-        let input = lines[0];
-        let _ = tag("001e# service=git-upload-pack")(input)?;
-        let input = lines[1];
-        let (input, _) = tag("0000")(input)?;
-
-        // First ref
-        let (input, _size) = take(4usize)(input)?;
-        //let size = usize::from_str_radix(size, 16).unwrap();
-        //let remaining_size = size - 4;
-        //let (remaining_pkt_lines, input) = take(remaining_size)(input)?;
-        println!("pkt_line: {}", input);
-
-        let (input, sha) = take(40usize)(input)?;
-        println!("sha: {}", sha);
-
-        let (input, _) = tag(" ")(input)?;
-        let (input, reference) = take_while(|b| b != '\0')(input)?;
-        println!("ref: {}", reference);
-
-        let (input, _) = tag("\0")(input)?;
-        let capabilities: Vec<String> = input.split(|b| b == ' ').map(|c| c.to_owned()).collect();
-
-        println!("capabilities: {:?}", capabilities);
-
-        let mut sha_ref_pairs: Vec<(String, String)> = lines[2..lines.len() - 1]
-            .iter()
-            .map(|line| {
-                let mut split = line[4..].split(' ');
-                let sha = split.next().unwrap().to_owned();
-                let reff = split.next().unwrap().to_owned();
-                (sha, reff)
-            })
-            .collect();
-        sha_ref_pairs.insert(0, (sha.to_owned(), reference.to_owned()));
-
-        println!("--- FINISHED PARSING ---");
-
-        Ok((
-            input,
-            ReferenceDiscovery {
-                sha_ref_pairs,
-                capabilities,
-            },
-        ))
+/// Clone the repository at `url` into `directory`, or into a directory named
+/// after the repository. With `local`, objects of a repository on the same
+/// filesystem are hard-linked instead of copied; with `shared`, they are not
+/// copied at all but borrowed through the alternates. Objects are also
+/// borrowed from the `references` repositories instead of being fetched.
+/// `deepen` makes a shallow clone and `filter` a partial clone, whose
+/// missing objects are fetched from the remote when needed. Returns the
+/// directory.
+pub fn run(
+    url: &str,
+    directory: Option<&Path>,
+    local: bool,
+    shared: bool,
+    references: &[PathBuf],
+    deepen: &Deepen,
+    filter: Option<&ObjectFilter>,
+) -> crate::Result<PathBuf> {
+    // Paths are relative to where we started, not to the new repository.
+    let url = if transport::is_local_url(url) && !url.starts_with("file://") {
+        let path = fs::canonicalize(url).context(format!("repository {url} does not exist"))?;
+        path.to_str().context("repository path utf-8")?.to_owned()
+    } else {
+        url.to_owned()
+    };
+    let url = url.as_str();
+    // Reference repositories too are relative to where we started.
+    let references = references
+        .iter()
+        .map(|reference| {
+            let git_dir = find_git_dir(reference)?;
+            fs::canonicalize(git_dir.join("objects"))
+                .context(format!("reference repository {reference:?} has no objects"))
+        })
+        .collect::<crate::Result<Vec<_>>>()?;
+    let directory = match directory {
+        Some(directory) => directory.to_owned(),
+        None => default_directory(url)?,
+    };
+    if directory.exists() {
+        crate::ensure!(
+            directory.is_dir() && fs::read_dir(&directory)?.next().is_none(),
+            "destination path {:?} already exists and is not an empty directory",
+            directory
+        );
     }
-}
-
-pub fn clone(repository_url: String) {
-    println!("Cloning repository: {}", repository_url);
-
-    let client = reqwest::blocking::Client::new();
-
-    // let response = client
-    //     .get(format!(
-    //         "{}/info/refs?service=git-upload-pack",
-    //         repository_url
-    //     ))
-    //     .header("git-protocol", "version=2").send()
-    //     .unwrap();
-    // let body = response.text().unwrap();
-    // println!("\n{}\n", body);
-
-    // let url = format!("{}/git-upload-pack", repository_url);
-    // let body = format!("0014command=ls-refs\n0000");
-    // let response = client
-    //     .post(url)
-    //     .header("git-protocol", "version=2")
-    //     .body(body)
-    //     .send()
-    //     .unwrap();
-    // println!("{:?}", response);
-    // let body = response.text().unwrap();
-    // println!("\n{}\n", body);
-
-    let url = format!("{}/git-upload-pack", repository_url);
-    // Hardcoded for now.
-    // TODO: use hashes from ls-refs
-    let body =
-        "0011command=fetch00010032want 20f7295d14cbf2d4a12bf41d3a1b6bf17c04c6a3\n0009done\n0000";
-    let response = client
-        .post(url)
-        .header("Git-Protocol", "version=2")
-        .body(body)
-        .send()
-        .unwrap();
-    //println!("{:?}", response);
-    let body = response.bytes().unwrap();
-    //dbg!(&body);
-
-    let mut buf = &body[..];
+    eprintln!("Cloning into '{}'...", directory.display());
+    fs::create_dir_all(&directory).context("create clone directory")?;
+    std::env::set_current_dir(&directory).context("enter clone directory")?;
+
+    // The new repository names objects the way the remote does.
+    let transport = Transport::connect(url)?;
+    init::create_git_dir(transport.object_format()?)?;
+    let mut config = Config::read()?;
+    config.set("remote.origin.url", url);
+    config.set("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*");
+    if let Some(filter) = filter {
+        config.set("core.repositoryformatversion", "1");
+        config.set("extensions.partialclone", "origin");
+        config.set("remote.origin.promisor", "true");
+        config.set("remote.origin.partialclonefilter", &filter.to_string());
+    }
+    config.write()?;
 
-    // Find start of PACK.
-    // TODO: do this in a smarter way so that progress is printed
-    loop {
-        let size =
-            usize::from_str_radix(&String::from_utf8(buf[..4].to_vec()).unwrap(), 16).unwrap();
-        // What is the 0x01 byte?
-        if buf[4..9] == b"\x01PACK"[..] {
-            break;
+    for reference in &references {
+        object_store::add_alternate(reference)?;
+    }
+    let is_local = transport::is_local_url(url) && !transport::is_bundle_url(url);
+    if shared {
+        crate::ensure!(is_local, "--shared requires a local repository");
+        let objects_dir = LocalTransport::open(url)?.git_dir().join("objects");
+        object_store::add_alternate(
+            &fs::canonicalize(objects_dir).context("resolve objects dir")?,
+        )?;
+    } else if local {
+        if is_local {
+            LocalTransport::open(url)?.copy_objects(true)?;
         } else {
-            buf = buf.get(size..).unwrap();
+            eprintln!("warning: --local is ignored");
         }
     }
-    println!("processing pack of len {}", buf.len());
-    buf = &buf[9..];
-
-    let protocol_version = u32::from_be_bytes(buf[..4].try_into().unwrap());
-    dbg!(protocol_version);
-    buf = &buf[4..];
-
-    let num_objects = u32::from_be_bytes(buf[..4].try_into().unwrap());
-    dbg!(num_objects);
-    buf = &buf[4..];
-
-    for _ in 0..num_objects {
-        println!("\n--- processing object ---");
-        let (object_type, idx, size) = pack_entry_size(buf);
-        buf = &buf[idx..];
-        // `size` is the size of the decompressed data, so we don't know how many bytes
-        // to read from `buf`. Therefore, we just decompress data from `buf`.
-        let mut decompressed_data = Vec::with_capacity(size);
-        let offset_to_next_entry = decompress_stream(buf, &mut decompressed_data);
-        assert!(decompressed_data.len() == size);
-        // Write data to stdout for now.
-        stdout().write_all(&decompressed_data).unwrap();
-
-        buf = &buf[offset_to_next_entry..];
+    fetch::run_with_transport(transport, "origin", &[], false, false, deepen)?;
+
+    let Some(tracking) = refs::read_symref("refs/remotes/origin/HEAD")? else {
+        eprintln!("warning: You appear to have cloned an empty repository.");
+        return Ok(directory);
+    };
+    let branch = tracking
+        .strip_prefix("refs/remotes/origin/")
+        .context("remote HEAD outside refs/remotes/origin")?
+        .to_owned();
+    let hash = refs::read_ref(&tracking)?.context("remote HEAD is dangling")?;
+    refs::update_ref(&format!("refs/heads/{branch}"), &hash)?;
+    refs::update_symref("HEAD", &format!("refs/heads/{branch}"))?;
+    config.set(&format!("branch.{branch}.remote"), "origin");
+    config.set(
+        &format!("branch.{branch}.merge"),
+        &format!("refs/heads/{branch}"),
+    );
+    config.write()?;
+
+    let tree = Commit::read(&hash)?.tree;
+    if filter.is_some() {
+        // Fetch the blobs left out in one go rather than one at a time.
+        let mut missing = Vec::new();
+        missing_blobs(&tree, &mut missing)?;
+        promisor::fetch_missing(&missing)?;
     }
-    // TODO: collect pack entries into Vec.
-}
-
-fn decompress_stream(buf: &[u8], decompressed_data: &mut Vec<u8>) -> usize {
-    let mut decompress = flate2::Decompress::new(true);
-    decompress
-        .decompress_vec(buf, decompressed_data, flate2::FlushDecompress::None)
-        .unwrap();
-    dbg!(decompress.total_in());
+    checkout_tree(&tree, Path::new("."))?;
 
-    decompress.total_in() as usize
+    Ok(directory)
 }
 
-fn pack_entry_size(buf: &[u8]) -> (u8, usize, usize) {
-    let mut idx = 0;
-
-    let mut c = buf[idx];
-    idx += 1;
+/// Name the clone after the last path component of `url` without `.git` or `.bundle`.
+fn default_directory(url: &str) -> crate::Result<PathBuf> {
+    let name = url
+        .trim_end_matches('/')
+        .trim_end_matches("/.git")
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap_or_default();
+    let name = name
+        .strip_suffix(".git")
+        .or_else(|| name.strip_suffix(".bundle"))
+        .unwrap_or(name);
+    crate::ensure!(!name.is_empty(), "cannot guess directory name from {url}");
+
+    Ok(PathBuf::from(name))
+}
 
-    let object_type = (c >> 4) & 0x7;
-    dbg!(object_type);
+/// Collect the blobs below the tree `hash` that are not in the object store.
+fn missing_blobs(hash: &ObjectId, missing: &mut Vec<ObjectId>) -> crate::Result<()> {
+    for entry in read_tree(hash)? {
+        match entry.mode {
+            FileMode::Directory => missing_blobs(&entry.hash, missing)?,
+            FileMode::Gitlink => (),
+            _ if !Object::exists(&entry.hash)? => missing.push(entry.hash),
+            _ => (),
+        }
+    }
 
-    let mut size = (c & 0x0f) as usize;
-    let mut shift: u8 = 4;
+    Ok(())
+}
 
-    while c & 0x80 != 0 {
-        c = buf[idx];
-        idx += 1;
-        size += ((c & 0x7f) as usize) << shift;
-        shift += 7;
+/// Write the files of the tree `hash` into `dir`.
+fn checkout_tree(hash: &ObjectId, dir: &Path) -> crate::Result<()> {
+    for entry in read_tree(hash)? {
+        let path = dir.join(&entry.file);
+        match entry.mode {
+            FileMode::Directory => {
+                fs::create_dir_all(&path).context(format!("create {path:?}"))?;
+                checkout_tree(&entry.hash, &path)?;
+            }
+            FileMode::RegularFile | FileMode::ExecutableFile => {
+                let blob = Object::read(&entry.hash)?;
+                fs::write(&path, &blob.contents).context(format!("write {path:?}"))?;
+                #[cfg(unix)]
+                if entry.mode == FileMode::ExecutableFile {
+                    use std::os::unix::fs::PermissionsExt;
+                    fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+                }
+            }
+            FileMode::Symlink => {
+                let blob = Object::read(&entry.hash)?;
+                #[cfg(unix)]
+                {
+                    use std::os::unix::ffi::OsStrExt;
+                    let target = std::ffi::OsStr::from_bytes(&blob.contents);
+                    std::os::unix::fs::symlink(target, &path)
+                        .context(format!("create symlink {path:?}"))?;
+                }
+                // Without symlinks, git checks out the target as a plain file.
+                #[cfg(not(unix))]
+                fs::write(&path, &blob.contents).context(format!("write {path:?}"))?;
+            }
+            // Submodules are not cloned, only their directory is created.
+            FileMode::Gitlink => fs::create_dir_all(&path).context(format!("create {path:?}"))?,
+        }
     }
-    dbg!(size);
 
-    (object_type, idx, size)
+    Ok(())
 }
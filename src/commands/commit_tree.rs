@@ -1,33 +1,31 @@
-use crate::common::hash_to_path;
-use std::io::{self, Write};
+use crate::common::Commit;
+use crate::error::Error;
+use crate::object_id::ObjectId;
+use crate::object_store::ObjectStore;
 
-pub fn run(hash: &str, parent_hash: &str, message: &str) -> anyhow::Result<()> {
-    if !hash_to_path(&hash)?.exists() {
-        return Err(anyhow::anyhow!("tree does not exist"));
+/// Store a commit of the tree `hash` with the parent `parent_hash` in
+/// `store` and return its hash.
+pub fn run(
+    store: &dyn ObjectStore,
+    hash: &ObjectId,
+    parent_hash: &ObjectId,
+    message: &str,
+) -> crate::Result<ObjectId> {
+    if !store.exists(hash)? {
+        return Err(Error::ObjectNotFound(hash.to_string()));
     }
-    if !hash_to_path(&parent_hash)?.exists() {
-        return Err(anyhow::anyhow!("parent tree does not exist"));
+    if !store.exists(parent_hash)? {
+        return Err(Error::ObjectNotFound(parent_hash.to_string()));
     }
 
-    let mut buf: Vec<u8> = Vec::new();
-    buf.extend(b"tree ");
-    buf.extend(hash.as_bytes());
-    buf.extend(b"\n");
-    buf.extend(b"parent ");
-    buf.extend(parent_hash.as_bytes());
-    buf.extend(b"\n");
-    buf.extend(b"author John Doe <john@doe.com> 1710605448 +0100\n");
-    buf.extend(b"committer John Doe <john@doe.com> 1710605448 +0100\n");
-    buf.extend(b"\n");
-    buf.extend(message.as_bytes());
-    buf.extend(b"\n");
+    let ident = "John Doe <john@doe.com> 1710605448 +0100";
+    let commit = Commit {
+        tree: *hash,
+        parents: vec![*parent_hash],
+        author: ident.to_owned(),
+        committer: ident.to_owned(),
+        message: format!("{message}\n"),
+    };
 
-    let mut newbuf: Vec<u8> = Vec::new();
-    newbuf.extend(b"commit ");
-    newbuf.extend(buf.len().to_string().as_bytes());
-    newbuf.extend(&buf);
-
-    io::stdout().write_all(&buf).unwrap();
-
-    Ok(())
+    commit.write(store)
 }
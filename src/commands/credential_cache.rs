@@ -0,0 +1,172 @@
+use crate::credential::Credential;
+use crate::error::Context;
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    os::unix::{
+        fs::DirBuilderExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+/// Where the cache daemon listens unless `--socket` is given:
+/// `$XDG_CACHE_HOME/mygit/credential/socket` or `~/.cache/mygit/credential/socket`.
+pub fn default_socket() -> crate::Result<PathBuf> {
+    let cache = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(cache) => PathBuf::from(cache),
+        None => PathBuf::from(std::env::var_os("HOME").context("HOME is not set")?).join(".cache"),
+    };
+
+    Ok(cache.join("mygit/credential/socket"))
+}
+
+/// Credential helper keeping credentials in memory for `timeout` seconds.
+/// `action` is `get`, `store` or `erase` with the credential on stdin, or
+/// `exit` to stop the daemon holding the cache. The daemon listening on
+/// `socket` is started by the first `store`.
+pub fn run(action: &str, timeout: u64, socket: &Path) -> crate::Result<()> {
+    let credential = match action {
+        "get" | "store" | "erase" => Credential::parse(std::io::stdin().lock())?.0,
+        "exit" => Credential::default(),
+        // Helpers ignore actions they do not know.
+        _ => return Ok(()),
+    };
+    let mut stream = match UnixStream::connect(socket) {
+        Ok(stream) => stream,
+        Err(_) if action == "store" => spawn_daemon(socket)?,
+        // Nothing is cached without a daemon.
+        Err(_) => return Ok(()),
+    };
+    stream.write_all(format!("action={action}\ntimeout={timeout}\n").as_bytes())?;
+    credential.write_to(&mut stream)?;
+    stream.flush()?;
+    let mut reply = Vec::new();
+    stream
+        .read_to_end(&mut reply)
+        .context("read from cache daemon")?;
+    std::io::stdout().write_all(&reply)?;
+
+    Ok(())
+}
+
+/// Start the cache daemon for `socket` and connect to it.
+fn spawn_daemon(socket: &Path) -> crate::Result<UnixStream> {
+    Command::new(std::env::current_exe()?)
+        .arg("credential-cache--daemon")
+        .arg(socket)
+        // The daemon outlives us and must not hold on to our output, which
+        // may be a pipe someone waits on.
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("start credential cache daemon")?;
+    let started = Instant::now();
+    loop {
+        match UnixStream::connect(socket) {
+            Ok(stream) => return Ok(stream),
+            Err(err) if started.elapsed() > Duration::from_secs(5) => {
+                return Err(err).context("connect to credential cache daemon");
+            }
+            Err(_) => std::thread::sleep(Duration::from_millis(20)),
+        }
+    }
+}
+
+/// Serve the credential cache on `socket` until every credential has
+/// expired or an `exit` request arrives.
+pub fn daemon(socket: &Path) -> crate::Result<()> {
+    if let Some(dir) = socket.parent() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .context(format!("create {}", dir.display()))?;
+    }
+    // A socket left behind by a daemon that died would fail the bind.
+    if UnixStream::connect(socket).is_err() {
+        let _ = fs::remove_file(socket);
+    }
+    let listener = UnixListener::bind(socket).context(format!("bind {}", socket.display()))?;
+    listener.set_nonblocking(true)?;
+
+    let mut cache: Vec<(Credential, Instant)> = Vec::new();
+    let mut served = false;
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                served = true;
+                stream.set_nonblocking(false)?;
+                match handle(stream, &mut cache) {
+                    Ok(true) => break,
+                    Ok(false) => (),
+                    Err(err) => log::warn!("{err:#}"),
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            Err(err) => return Err(err).context("accept"),
+        }
+        let now = Instant::now();
+        cache.retain(|(_, expiry)| *expiry > now);
+        if served && cache.is_empty() {
+            break;
+        }
+    }
+    let _ = fs::remove_file(socket);
+
+    Ok(())
+}
+
+/// Answer one request. Returns whether the daemon should exit.
+fn handle(stream: UnixStream, cache: &mut Vec<(Credential, Instant)>) -> crate::Result<bool> {
+    let mut reader = BufReader::new(&stream);
+    let mut action = String::new();
+    let mut timeout = 0;
+    let mut request = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line == "\n" {
+            break;
+        }
+        if let Some(value) = line.trim_end().strip_prefix("action=") {
+            action = value.to_owned();
+        } else if let Some(value) = line.trim_end().strip_prefix("timeout=") {
+            timeout = value.parse().context("invalid timeout")?;
+        } else {
+            request.extend_from_slice(line.as_bytes());
+        }
+    }
+    let (credential, _) = Credential::parse(&request[..])?;
+
+    match action.as_str() {
+        "get" => {
+            if let Some((cached, _)) = cache.iter().find(|(cached, _)| cached.matches(&credential))
+            {
+                let (Some(username), Some(password)) = (&cached.username, &cached.password) else {
+                    return Ok(false);
+                };
+                let mut writer = &stream;
+                writer
+                    .write_all(format!("username={username}\npassword={password}\n").as_bytes())?;
+            }
+        }
+        "store" => {
+            cache.retain(|(cached, _)| {
+                !(cached.matches(&credential) && cached.username == credential.username)
+            });
+            if credential.is_complete() {
+                cache.push((credential, Instant::now() + Duration::from_secs(timeout)));
+            }
+        }
+        "erase" => cache.retain(|(cached, _)| !cached.matches(&credential)),
+        "exit" => return Ok(true),
+        _ => (),
+    }
+
+    Ok(false)
+}
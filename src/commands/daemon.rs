@@ -0,0 +1,113 @@
+use crate::commands::serve::resolve_repo;
+use crate::error::Context;
+use crate::pktline::{PktLineReader, PktLineWriter};
+use std::{
+    net::{TcpListener, TcpStream},
+    os::fd::OwnedFd,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Arc,
+};
+
+/// Options shared by all connections of a daemon.
+#[derive(Debug)]
+struct Daemon {
+    /// A repository, or a directory containing repositories.
+    root: PathBuf,
+    /// Serve repositories without a `git-daemon-export-ok` file.
+    export_all: bool,
+    enable_receive_pack: bool,
+}
+
+/// Serve the repository at `directory`, or the repositories below it, over
+/// the `git://` protocol on `addr`. Only repositories containing a
+/// `git-daemon-export-ok` file are served unless `export_all` is set, and
+/// pushes are refused unless `enable_receive_pack` is set.
+pub fn run(
+    addr: &str,
+    directory: &Path,
+    export_all: bool,
+    enable_receive_pack: bool,
+) -> crate::Result<()> {
+    let daemon = Arc::new(Daemon {
+        root: directory
+            .canonicalize()
+            .context(format!("{} not found", directory.display()))?,
+        export_all,
+        enable_receive_pack,
+    });
+    let listener = TcpListener::bind(addr).context(format!("bind {addr}"))?;
+    eprintln!(
+        "Serving {} on git://{}",
+        daemon.root.display(),
+        listener.local_addr()?
+    );
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                log::warn!("accept: {err}");
+                continue;
+            }
+        };
+        let daemon = Arc::clone(&daemon);
+        std::thread::spawn(move || {
+            if let Err(err) = handle(&daemon, stream) {
+                log::warn!("{err:#}");
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Read the `<service> <path>\0host=<host>\0[\0<param>\0...]` request and run
+/// the service with the connection as its stdin and stdout.
+fn handle(daemon: &Daemon, stream: TcpStream) -> crate::Result<()> {
+    let packet = PktLineReader::new(&stream)
+        .read_packet()?
+        .context("connection closed before request")?;
+    let line = packet.as_line().context("invalid request")?;
+    let mut fields = line.split('\0');
+    let (service, path) = fields
+        .next()
+        .and_then(|command| command.split_once(' '))
+        .context(format!("invalid request {line:?}"))?;
+    // Extra parameters such as `version=2` follow an empty field.
+    let params: Vec<&str> = fields
+        .skip_while(|field| !field.is_empty())
+        .filter(|field| !field.is_empty())
+        .collect();
+    log::info!("{service} {path}");
+
+    let subcommand = match service {
+        "git-upload-pack" => Some("upload-pack"),
+        "git-receive-pack" if daemon.enable_receive_pack => Some("receive-pack"),
+        _ => None,
+    };
+    let git_dir = resolve_repo(&daemon.root, path)
+        .filter(|git_dir| daemon.export_all || git_dir.join("git-daemon-export-ok").exists());
+    let (Some(subcommand), Some(git_dir)) = (subcommand, git_dir) else {
+        let mut writer = PktLineWriter::new(&stream);
+        return writer.write_line(&format!(
+            "ERR access denied or repository not exported: {path}"
+        ));
+    };
+
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .arg(subcommand)
+        .arg(&git_dir)
+        .env_remove("GIT_DIR")
+        .stdin(Stdio::from(OwnedFd::from(stream.try_clone()?)))
+        .stdout(Stdio::from(OwnedFd::from(stream)));
+    if params.is_empty() {
+        command.env_remove("GIT_PROTOCOL");
+    } else {
+        command.env("GIT_PROTOCOL", params.join(":"));
+    }
+    let status = command.status().context(format!("run {subcommand}"))?;
+    crate::ensure!(status.success(), "{subcommand} failed: {status}");
+
+    Ok(())
+}
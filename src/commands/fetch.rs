@@ -0,0 +1,495 @@
+use crate::commands::gc;
+use crate::common::{git_dir, is_ancestor, Commit, Object};
+use crate::config::Config;
+use crate::error::Context;
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::object_store;
+use crate::pktline;
+use crate::promisor::ObjectFilter;
+use crate::refs;
+use crate::refspec::Refspec;
+use crate::shallow;
+use crate::transport::{self, Deepen, FetchRequest, RemoteRef, Transport};
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt::Display,
+    fs,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Number of local commits offered as `have`s during negotiation.
+const MAX_HAVES: usize = 256;
+
+/// Outcome of fetching a single remote ref.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchStatus {
+    UpToDate,
+    Updated,
+    /// The local ref was not updated.
+    Rejected(String),
+    /// The local ref was deleted because the remote ref no longer exists.
+    Pruned,
+}
+
+#[derive(Debug, Clone)]
+pub struct FetchedRef {
+    pub remote_ref: String,
+    /// Local ref that was updated, `None` if the ref was only written to `FETCH_HEAD`.
+    pub local_ref: Option<String>,
+    pub old: Option<ObjectId>,
+    pub new: ObjectId,
+    pub forced: bool,
+    pub status: FetchStatus,
+}
+
+impl FetchedRef {
+    pub fn is_error(&self) -> bool {
+        matches!(self.status, FetchStatus::Rejected(_))
+    }
+}
+
+impl Display for FetchedRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let remote = short_ref_name(&self.remote_ref);
+        let local = self
+            .local_ref
+            .as_deref()
+            .map_or("FETCH_HEAD", short_ref_name);
+        let refs = format!("{remote:<10} -> {local}");
+        match (&self.status, &self.old) {
+            (FetchStatus::UpToDate, _) => write!(f, " = {:<17} {refs}", "[up to date]"),
+            (FetchStatus::Pruned, _) => {
+                write!(f, " - {:<17} {:<10} -> {local}", "[deleted]", "(none)")
+            }
+            (FetchStatus::Rejected(reason), _) => {
+                write!(f, " ! {:<17} {refs}  ({reason})", "[rejected]")
+            }
+            (FetchStatus::Updated, None) => {
+                let kind = if self.local_ref.is_none() {
+                    if self.remote_ref.starts_with("refs/tags/") {
+                        "tag"
+                    } else {
+                        "branch"
+                    }
+                } else if self.remote_ref.starts_with("refs/tags/") {
+                    "[new tag]"
+                } else if self.remote_ref.starts_with("refs/heads/") {
+                    "[new branch]"
+                } else {
+                    "[new ref]"
+                };
+                write!(f, " * {kind:<17} {refs}")
+            }
+            (FetchStatus::Updated, Some(old)) if self.forced => {
+                let range = format!("{}...{}", old.abbrev(7), self.new.abbrev(7));
+                write!(f, " + {range:<17} {refs}  (forced update)")
+            }
+            (FetchStatus::Updated, Some(old)) => {
+                let range = format!("{}..{}", old.abbrev(7), self.new.abbrev(7));
+                write!(f, "   {range:<17} {refs}")
+            }
+        }
+    }
+}
+
+/// A remote ref selected for fetching.
+struct Wanted {
+    remote: RemoteRef,
+    local_ref: Option<String>,
+    force: bool,
+    for_merge: bool,
+}
+
+/// Fetch the refs selected by `refspecs` (or the configured `remote.<remote>.fetch`
+/// refspecs) from `remote`, a configured remote name or a URL.
+/// With `prune`, remote-tracking refs whose remote ref is gone are deleted.
+/// With `tags`, all remote tags are fetched as well. `deepen` limits the
+/// history fetched into a shallow repository.
+/// Returns the remote URL and the result for each ref.
+pub fn run(
+    remote: &str,
+    refspecs: &[String],
+    prune: bool,
+    tags: bool,
+    deepen: &Deepen,
+) -> crate::Result<(String, Vec<FetchedRef>)> {
+    let url = Config::read()?
+        .get(&format!("remote.{remote}.url"))
+        .unwrap_or(remote)
+        .to_owned();
+    let transport = Transport::connect(&url)?;
+
+    run_with_transport(transport, remote, refspecs, prune, tags, deepen)
+}
+
+/// Like [`run`], over a `transport` already connected to `remote`.
+pub fn run_with_transport(
+    mut transport: Transport,
+    remote: &str,
+    refspecs: &[String],
+    prune: bool,
+    tags: bool,
+    deepen: &Deepen,
+) -> crate::Result<(String, Vec<FetchedRef>)> {
+    let config = Config::read()?;
+    let url = transport.url().to_owned();
+    let configured = config
+        .get_all(&format!("remote.{remote}.fetch"))
+        .into_iter()
+        .map(Refspec::parse)
+        .collect::<crate::Result<Vec<_>>>()?;
+    let from_command_line = !refspecs.is_empty();
+    let mut refspecs = if from_command_line {
+        refspecs
+            .iter()
+            .map(|spec| Refspec::parse(spec))
+            .collect::<crate::Result<Vec<_>>>()?
+    } else {
+        configured.clone()
+    };
+    crate::ensure!(!refspecs.is_empty(), "no refspecs to fetch from {url}");
+    let explicit_refspecs = refspecs.len();
+    if tags {
+        refspecs.push(Refspec::parse("refs/tags/*:refs/tags/*")?);
+    }
+
+    transport::check_object_format(transport.object_format()?)?;
+    let mut advertised = transport.ls_refs(&[])?;
+    // Names from the remote become local ref names, which must not be able
+    // to address files outside `refs/`.
+    advertised.retain(|remote_ref| {
+        let valid = refs::check_ref_format(&remote_ref.name).is_ok()
+            && remote_ref
+                .symref_target
+                .as_deref()
+                .is_none_or(|target| refs::check_ref_format(target).is_ok());
+        if !valid {
+            eprintln!("warning: ignoring ref with broken name {}", remote_ref.name);
+        }
+        valid
+    });
+
+    let merge_ref = current_branch()?.and_then(|branch| {
+        let merge_remote = config.get(&format!("branch.{branch}.remote"))?;
+        (merge_remote == remote)
+            .then(|| config.get(&format!("branch.{branch}.merge")))
+            .flatten()
+            .map(str::to_owned)
+    });
+    let mut wanted: Vec<Wanted> = Vec::new();
+    for remote_ref in &advertised {
+        for (idx, spec) in refspecs.iter().enumerate() {
+            let local_ref = if spec.is_glob() {
+                match spec.map_src(&remote_ref.name) {
+                    Some(local_ref) => Some(local_ref),
+                    None => continue,
+                }
+            } else if matches_short_name(&spec.src, &remote_ref.name) {
+                match &spec.dst {
+                    Some(dst) if dst.is_empty() => None,
+                    Some(dst) if dst.starts_with("refs/") => Some(dst.clone()),
+                    Some(dst) => Some(format!("refs/heads/{dst}")),
+                    // Opportunistically update the remote-tracking ref.
+                    None => configured
+                        .iter()
+                        .find_map(|spec| spec.map_src(&remote_ref.name)),
+                }
+            } else {
+                continue;
+            };
+            let for_merge = if from_command_line {
+                !spec.is_glob() && idx < explicit_refspecs
+            } else {
+                merge_ref.as_deref() == Some(remote_ref.name.as_str())
+            };
+            wanted.push(Wanted {
+                remote: remote_ref.clone(),
+                local_ref,
+                force: spec.force,
+                for_merge,
+            });
+            break;
+        }
+    }
+
+    let mut wants = BTreeSet::new();
+    for w in &wanted {
+        // Deepening needs the history below tips we already have.
+        if !deepen.is_empty() || !Object::exists(&w.remote.hash)? {
+            wants.insert(w.remote.hash);
+        }
+    }
+    if !wants.is_empty() {
+        let request = FetchRequest {
+            wants: wants.into_iter().collect(),
+            haves: local_haves()?,
+            include_tag: true,
+            shallow: shallow::read()?.into_iter().collect(),
+            deepen: deepen.clone(),
+            filter: promisor_filter(&config, remote)?,
+        };
+        transport.fetch(&request, pktline::print_progress)?;
+    }
+    for w in &wanted {
+        crate::ensure!(
+            Object::exists(&w.remote.hash)?,
+            "remote did not send all necessary objects"
+        );
+    }
+
+    let head_branch = current_branch()?.map(|branch| format!("refs/heads/{branch}"));
+    let mut results = Vec::new();
+    for w in &wanted {
+        results.push(update_local_ref(w, head_branch.as_deref())?);
+    }
+
+    // Follow tags that point into the history we now have.
+    if !tags {
+        for remote_ref in &advertised {
+            let Some(name) = remote_ref.name.strip_prefix("refs/tags/") else {
+                continue;
+            };
+            if remote_ref.name.ends_with("^{}")
+                || refs::read_ref(&remote_ref.name)?.is_some()
+                || !Object::exists(&remote_ref.hash)?
+            {
+                continue;
+            }
+            log::debug!("Following tag {name}");
+            refs::update_ref(&remote_ref.name, &remote_ref.hash)?;
+            results.push(FetchedRef {
+                remote_ref: remote_ref.name.clone(),
+                local_ref: Some(remote_ref.name.clone()),
+                old: None,
+                new: remote_ref.hash,
+                forced: false,
+                status: FetchStatus::Updated,
+            });
+        }
+    }
+
+    if prune {
+        // Only prune refs covered by the glob refspecs, never tags fetched by `--tags`.
+        let globs: Vec<&Refspec> = refspecs[..explicit_refspecs]
+            .iter()
+            .filter(|spec| spec.is_glob())
+            .collect();
+        for (local_ref, hash) in refs::list_refs()? {
+            let Some(remote_ref) = globs.iter().find_map(|spec| spec.map_dst(&local_ref)) else {
+                continue;
+            };
+            if refs::read_symref(&local_ref)?.is_some() {
+                continue;
+            }
+            if advertised.iter().all(|r| r.name != remote_ref) {
+                refs::delete_ref(&local_ref)?;
+                results.push(FetchedRef {
+                    remote_ref,
+                    local_ref: Some(local_ref),
+                    old: Some(hash),
+                    new: ObjectFormat::current()?.null(),
+                    forced: false,
+                    status: FetchStatus::Pruned,
+                });
+            }
+        }
+    }
+
+    if config.get(&format!("remote.{remote}.url")).is_some() {
+        create_remote_head(remote, &advertised, &configured)?;
+    }
+    write_fetch_head(&url, &wanted)?;
+
+    Ok((url, results))
+}
+
+/// The filter of a partial clone's promisor `remote`, from
+/// `remote.<remote>.partialCloneFilter`.
+fn promisor_filter(config: &Config, remote: &str) -> crate::Result<Option<ObjectFilter>> {
+    if config.get_bool(&format!("remote.{remote}.promisor")) != Some(true) {
+        return Ok(None);
+    }
+    config
+        .get(&format!("remote.{remote}.partialclonefilter"))
+        .map(ObjectFilter::parse)
+        .transpose()
+}
+
+/// Parse the date of `--shallow-since`: seconds since the epoch, or a
+/// relative date such as `2.weeks.ago`.
+pub fn parse_since(value: &str) -> crate::Result<i64> {
+    if let Ok(timestamp) = value.parse() {
+        return Ok(timestamp);
+    }
+    let age = gc::parse_expiry(value)?.context(format!("invalid date: {value}"))?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+
+    Ok(now.saturating_sub(age).as_secs() as i64)
+}
+
+/// Point `refs/remotes/<remote>/HEAD` at the tracking ref of the remote's
+/// default branch, unless it already exists.
+fn create_remote_head(
+    remote: &str,
+    advertised: &[RemoteRef],
+    configured: &[Refspec],
+) -> crate::Result<()> {
+    let remote_head = format!("refs/remotes/{remote}/HEAD");
+    if refs::read_symref(&remote_head)?.is_some() {
+        return Ok(());
+    }
+    let Some(head) = advertised.iter().find(|r| r.name == "HEAD") else {
+        return Ok(());
+    };
+    // Servers that do not advertise symrefs leave us to guess from the hash,
+    // preferring the conventional default branch names.
+    let target = head.symref_target.clone().or_else(|| {
+        let mut candidates: Vec<&RemoteRef> = advertised
+            .iter()
+            .filter(|r| r.name.starts_with("refs/heads/") && r.hash == head.hash)
+            .collect();
+        candidates
+            .sort_by_key(|r| !matches!(r.name.as_str(), "refs/heads/main" | "refs/heads/master"));
+        candidates.first().map(|r| r.name.clone())
+    });
+    let tracking =
+        target.and_then(|target| configured.iter().find_map(|spec| spec.map_src(&target)));
+    if let Some(tracking) = tracking {
+        refs::update_symref(&remote_head, &tracking)?;
+    }
+
+    Ok(())
+}
+
+/// Point `wanted.local_ref` at the fetched object if allowed.
+fn update_local_ref(wanted: &Wanted, head_branch: Option<&str>) -> crate::Result<FetchedRef> {
+    let mut result = FetchedRef {
+        remote_ref: wanted.remote.name.clone(),
+        local_ref: wanted.local_ref.clone(),
+        old: None,
+        new: wanted.remote.hash,
+        forced: false,
+        status: FetchStatus::Updated,
+    };
+    let Some(local_ref) = &wanted.local_ref else {
+        return Ok(result);
+    };
+    result.old = refs::read_ref(local_ref)?;
+
+    let status = match &result.old {
+        Some(old) if *old == result.new => FetchStatus::UpToDate,
+        _ if Some(local_ref.as_str()) == head_branch => {
+            FetchStatus::Rejected("refusing to fetch into current branch".to_owned())
+        }
+        None => FetchStatus::Updated,
+        Some(_) if wanted.force => FetchStatus::Updated,
+        Some(_) if local_ref.starts_with("refs/tags/") => {
+            FetchStatus::Rejected("would clobber existing tag".to_owned())
+        }
+        Some(old) if is_ancestor(old, &result.new).unwrap_or(false) => FetchStatus::Updated,
+        Some(_) => FetchStatus::Rejected("non-fast-forward".to_owned()),
+    };
+    if status == FetchStatus::Updated {
+        result.forced = match &result.old {
+            Some(old) => !is_ancestor(old, &result.new).unwrap_or(false),
+            None => false,
+        };
+        refs::update_ref(local_ref, &result.new)?;
+    }
+    result.status = status;
+
+    Ok(result)
+}
+
+/// Write `FETCH_HEAD` with merge candidates first.
+fn write_fetch_head(url: &str, wanted: &[Wanted]) -> crate::Result<()> {
+    let mut lines = Vec::new();
+    for for_merge in [true, false] {
+        for w in wanted.iter().filter(|w| w.for_merge == for_merge) {
+            let name = &w.remote.name;
+            let description = if let Some(branch) = name.strip_prefix("refs/heads/") {
+                format!("branch '{branch}' of {url}")
+            } else if let Some(tag) = name.strip_prefix("refs/tags/") {
+                format!("tag '{tag}' of {url}")
+            } else {
+                format!("'{name}' of {url}")
+            };
+            let marker = if for_merge { "" } else { "not-for-merge" };
+            lines.push(format!("{}\t{}\t{}\n", w.remote.hash, marker, description));
+        }
+    }
+    fs::write(git_dir().join("FETCH_HEAD"), lines.concat()).context("write FETCH_HEAD")?;
+
+    Ok(())
+}
+
+/// Recent commits reachable from local refs, newest first. The refs of
+/// repositories we borrow objects from count as local, so that objects in
+/// the alternates are not fetched again.
+fn local_haves() -> crate::Result<Vec<ObjectId>> {
+    let mut pending: Vec<(i64, ObjectId)> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut local_refs = refs::list_refs()?;
+    for objects_dir in object_store::alternates(&git_dir().join("objects"))? {
+        let Some(alternate_git_dir) = objects_dir.parent() else {
+            continue;
+        };
+        if let Ok(alternate_refs) = refs::list_refs_in(alternate_git_dir) {
+            local_refs.extend(alternate_refs);
+        }
+    }
+    for (_, hash) in local_refs {
+        if seen.insert(hash) {
+            if let Ok(commit) = Commit::read(&hash) {
+                pending.push((commit.time(), hash));
+            }
+        }
+    }
+
+    let mut haves = Vec::new();
+    while haves.len() < MAX_HAVES {
+        pending.sort();
+        let Some((_, hash)) = pending.pop() else {
+            break;
+        };
+        let commit = Commit::read(&hash)?;
+        haves.push(hash);
+        for parent in commit.parents {
+            if seen.insert(parent) {
+                if let Ok(parent_commit) = Commit::read(&parent) {
+                    pending.push((parent_commit.time(), parent));
+                }
+            }
+        }
+    }
+
+    Ok(haves)
+}
+
+/// Name of the checked-out branch, `None` if `HEAD` is detached.
+fn current_branch() -> crate::Result<Option<String>> {
+    let head = fs::read_to_string(git_dir().join("HEAD")).context("read HEAD")?;
+    Ok(head
+        .trim_end()
+        .strip_prefix("ref: refs/heads/")
+        .map(str::to_owned))
+}
+
+/// Whether the (possibly abbreviated) ref `short` names the remote ref `name`.
+fn matches_short_name(short: &str, name: &str) -> bool {
+    [
+        short.to_owned(),
+        format!("refs/{short}"),
+        format!("refs/tags/{short}"),
+        format!("refs/heads/{short}"),
+    ]
+    .iter()
+    .any(|candidate| candidate == name)
+}
+
+fn short_ref_name(name: &str) -> &str {
+    name.strip_prefix("refs/heads/")
+        .or(name.strip_prefix("refs/tags/"))
+        .or(name.strip_prefix("refs/remotes/"))
+        .unwrap_or(name)
+}
@@ -0,0 +1,317 @@
+use crate::common::{compare_tree_entry_names, FileMode, Object, ObjectType, TreeEntry};
+use crate::error::Error;
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::object_store::{self, FileStore};
+use crate::pack;
+use crate::refs;
+use crate::shallow;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Display,
+};
+
+/// A problem found while checking the object store.
+#[derive(Debug)]
+pub enum FsckIssue {
+    /// An object could not be read or does not hash to its name.
+    Corrupt { hash: ObjectId, reason: String },
+    /// An object was read but its contents are malformed.
+    Invalid {
+        ty: ObjectType,
+        hash: ObjectId,
+        reason: String,
+    },
+    /// A ref points to an object that does not exist.
+    BadRef { name: String, hash: ObjectId },
+    /// An object is referenced but not present.
+    Missing { ty: ObjectType, hash: ObjectId },
+    /// An object is present but not referenced by any ref or object.
+    Dangling { ty: ObjectType, hash: ObjectId },
+}
+
+impl FsckIssue {
+    /// Whether this issue means the repository is corrupt.
+    pub fn is_error(&self) -> bool {
+        !matches!(self, FsckIssue::Dangling { .. })
+    }
+}
+
+impl Display for FsckIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FsckIssue::Corrupt { hash, reason } => write!(f, "error: {hash}: {reason}"),
+            FsckIssue::Invalid { ty, hash, reason } => {
+                write!(f, "error in {ty} {hash}: {reason}")
+            }
+            FsckIssue::BadRef { name, hash } => {
+                write!(f, "error: {name}: invalid sha1 pointer {hash}")
+            }
+            FsckIssue::Missing { ty, hash } => write!(f, "missing {ty} {hash}"),
+            FsckIssue::Dangling { ty, hash } => write!(f, "dangling {ty} {hash}"),
+        }
+    }
+}
+
+/// Verify every loose and packed object, including those of the alternates,
+/// check that refs point to existing objects and report missing and dangling
+/// objects.
+pub fn run() -> crate::Result<Vec<FsckIssue>> {
+    let mut issues = Vec::new();
+    // Types of all objects that could be read.
+    let mut present = BTreeMap::new();
+    // Objects referenced by refs or other objects, with the type they are expected to have.
+    let mut referenced = BTreeMap::new();
+    let mut roots = HashSet::new();
+    // Objects referenced from promisor packs, which the promisor remote can provide.
+    let mut promised = HashSet::new();
+    let shallow = shallow::read()?;
+
+    let mut check = |hash: ObjectId,
+                     object: crate::Result<Object>,
+                     promisor: bool,
+                     issues: &mut Vec<FsckIssue>| {
+        let object = match object {
+            Ok(object) => object,
+            Err(err) => {
+                issues.push(FsckIssue::Corrupt {
+                    hash,
+                    reason: format!("{err:#}"),
+                });
+                return;
+            }
+        };
+        let actual = object
+            .write(std::io::sink())
+            .unwrap_or_else(|_| hash.format().null());
+        if actual != hash {
+            issues.push(FsckIssue::Corrupt {
+                reason: format!("hash mismatch, contents hash to {actual}"),
+                hash,
+            });
+            return;
+        }
+        match check_object(&object, hash.format()) {
+            Ok(mut references) => {
+                // The parents of shallow commits are expected to be missing.
+                if shallow.contains(&hash) {
+                    references.retain(|(_, ty)| *ty != ObjectType::Commit);
+                }
+                if promisor {
+                    promised.extend(references.iter().map(|(hash, _)| *hash));
+                }
+                referenced.extend(references);
+            }
+            Err(reason) => issues.push(FsckIssue::Invalid {
+                ty: object.ty,
+                hash,
+                reason,
+            }),
+        }
+        present.insert(hash, object.ty);
+    };
+
+    // Objects of the alternates are checked too, as refs and objects may
+    // point into them, but are not reported as dangling.
+    let mut borrowed = HashSet::new();
+    let format = ObjectFormat::current()?;
+    for (idx, objects_dir) in object_store::object_dirs()?.into_iter().enumerate() {
+        let store = FileStore::new(&objects_dir, format);
+        let loose = if objects_dir.is_dir() {
+            pack::loose_objects_in(&objects_dir)?
+        } else {
+            Vec::new()
+        };
+        for hash in loose {
+            let object = Object::read_loose(store.loose_path(&hash));
+            check(hash, object, false, &mut issues);
+            if idx > 0 {
+                borrowed.insert(hash);
+            }
+        }
+        for pack in store.packs()? {
+            let promisor = pack.is_promisor();
+            for hash in pack.hashes() {
+                let object = pack.read(&hash).and_then(|object| {
+                    object.ok_or_else(|| Error::ObjectNotFound(hash.to_string()))
+                });
+                check(hash, object, promisor, &mut issues);
+                if idx > 0 {
+                    borrowed.insert(hash);
+                }
+            }
+        }
+    }
+
+    let mut refs = refs::list_refs()?;
+    if let Some(head) = refs::read_ref("HEAD")? {
+        refs.push(("HEAD".to_owned(), head));
+    }
+    for (name, hash) in refs {
+        if !present.contains_key(&hash) {
+            issues.push(FsckIssue::BadRef { name, hash });
+        } else {
+            roots.insert(hash);
+        }
+    }
+
+    for (hash, ty) in &referenced {
+        if !present.contains_key(hash) && !promised.contains(hash) {
+            issues.push(FsckIssue::Missing {
+                ty: *ty,
+                hash: *hash,
+            });
+        }
+    }
+    for (hash, ty) in &present {
+        if !referenced.contains_key(hash) && !roots.contains(hash) && !borrowed.contains(hash) {
+            issues.push(FsckIssue::Dangling {
+                ty: *ty,
+                hash: *hash,
+            });
+        }
+    }
+
+    Ok(issues)
+}
+
+/// Validate the contents of `object` and return the objects it references
+/// together with their expected types.
+fn check_object(
+    object: &Object,
+    format: ObjectFormat,
+) -> Result<Vec<(ObjectId, ObjectType)>, String> {
+    match object.ty {
+        ObjectType::Blob => Ok(Vec::new()),
+        ObjectType::Tree => check_tree(&object.contents, format),
+        ObjectType::Commit => check_commit(&object.contents, format),
+        ObjectType::Tag => check_tag(&object.contents, format),
+    }
+}
+
+fn check_tree(
+    contents: &[u8],
+    format: ObjectFormat,
+) -> Result<Vec<(ObjectId, ObjectType)>, String> {
+    let mut input = contents;
+    let mut entries: Vec<TreeEntry> = Vec::new();
+    while !input.is_empty() {
+        let (rest, entry) = TreeEntry::parse(input, format).map_err(|_| {
+            match FileMode::parse(input) {
+                Ok(_) => "malformed entry",
+                Err(_) => "contains bad file modes",
+            }
+            .to_owned()
+        })?;
+        if entry.file.is_empty() || entry.file.contains('/') {
+            return Err(format!("contains bad file name {:?}", entry.file));
+        }
+        if let Some(previous) = entries.last() {
+            let ordering = compare_tree_entry_names(
+                previous.file.as_bytes(),
+                previous.mode == FileMode::Directory,
+                entry.file.as_bytes(),
+                entry.mode == FileMode::Directory,
+            );
+            match ordering {
+                std::cmp::Ordering::Less => (),
+                std::cmp::Ordering::Equal => {
+                    return Err(format!("contains duplicate file entries {:?}", entry.file))
+                }
+                std::cmp::Ordering::Greater => return Err("not properly sorted".to_owned()),
+            }
+        }
+        entries.push(entry);
+        input = rest;
+    }
+
+    // Submodule commits live in other repositories.
+    Ok(entries
+        .into_iter()
+        .filter(|entry| entry.mode != FileMode::Gitlink)
+        .map(|entry| (entry.hash, entry.ty))
+        .collect())
+}
+
+fn check_commit(
+    contents: &[u8],
+    format: ObjectFormat,
+) -> Result<Vec<(ObjectId, ObjectType)>, String> {
+    let mut lines = header_lines(contents)?.into_iter().peekable();
+    let mut references = Vec::new();
+
+    let tree = expect_header(lines.next(), "tree")?;
+    references.push((check_hash(tree, format)?, ObjectType::Tree));
+    while let Some(parent) = lines.peek().and_then(|line| line.strip_prefix("parent ")) {
+        references.push((check_hash(parent, format)?, ObjectType::Commit));
+        lines.next();
+    }
+    check_ident(expect_header(lines.next(), "author")?)?;
+    check_ident(expect_header(lines.next(), "committer")?)?;
+
+    Ok(references)
+}
+
+fn check_tag(contents: &[u8], format: ObjectFormat) -> Result<Vec<(ObjectId, ObjectType)>, String> {
+    let mut lines = header_lines(contents)?.into_iter();
+
+    let target = check_hash(expect_header(lines.next(), "object")?, format)?;
+    let ty = expect_header(lines.next(), "type")?;
+    let ty = ObjectType::try_from(ty).map_err(|_| format!("invalid type {ty:?}"))?;
+    let name = expect_header(lines.next(), "tag")?;
+    if name.is_empty() {
+        return Err("empty tag name".to_owned());
+    }
+    // Very old tags have no tagger line.
+    if let Some(tagger) = lines.next() {
+        check_ident(expect_header(Some(tagger), "tagger")?)?;
+    }
+
+    Ok(vec![(target, ty)])
+}
+
+/// Header lines of a commit or tag, up to the blank line before the message.
+fn header_lines(contents: &[u8]) -> Result<Vec<&str>, String> {
+    let text = std::str::from_utf8(contents).map_err(|_| "header is not valid utf-8".to_owned())?;
+    let header = text.split_once("\n\n").map_or(text, |(header, _)| header);
+
+    Ok(header.lines().collect())
+}
+
+fn expect_header<'a>(line: Option<&'a str>, key: &str) -> Result<&'a str, String> {
+    line.and_then(|line| line.strip_prefix(key))
+        .and_then(|rest| rest.strip_prefix(' '))
+        .ok_or(format!("missing {key} header"))
+}
+
+fn check_hash(hash: &str, format: ObjectFormat) -> Result<ObjectId, String> {
+    match hash.parse::<ObjectId>() {
+        Ok(object_id)
+            if object_id.format() == format && !hash.bytes().any(|b| b.is_ascii_uppercase()) =>
+        {
+            Ok(object_id)
+        }
+        _ => Err(format!("invalid object id {hash:?}")),
+    }
+}
+
+/// Check an identity of the form `Name <email> <timestamp> <+|-hhmm>`.
+fn check_ident(ident: &str) -> Result<(), String> {
+    let bad = || Err(format!("bad identity {ident:?}"));
+    let Some((name_email, date)) = ident.rsplit_once('>') else {
+        return bad();
+    };
+    if !name_email.contains('<') {
+        return bad();
+    }
+    let Some((timestamp, tz)) = date.trim_start().split_once(' ') else {
+        return bad();
+    };
+    let tz_valid = tz.len() == 5
+        && (tz.starts_with('+') || tz.starts_with('-'))
+        && tz[1..].bytes().all(|b| b.is_ascii_digit());
+    if timestamp.is_empty() || !timestamp.bytes().all(|b| b.is_ascii_digit()) || !tz_valid {
+        return bad();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use crate::commands::repack;
+use crate::common::hash_to_path;
+use crate::error::{Context, Error};
+use crate::pack;
+use crate::refs;
+use std::{
+    fs,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Reflog entries older than this are expired (git's `gc.reflogExpire` default).
+const REFLOG_EXPIRE: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+/// Pack refs, expire old reflog entries, repack all reachable objects into a
+/// single pack and prune unreachable objects older than `prune_expiry`.
+/// Unreachable objects of the old packs are unpacked first, so that they too
+/// are only pruned once expired. A `prune_expiry` of `None` never prunes.
+pub fn run(prune_expiry: Option<Duration>) -> crate::Result<()> {
+    refs::pack_refs().context("pack refs")?;
+    expire_reflogs(REFLOG_EXPIRE).context("expire reflogs")?;
+    repack::run(true, true, true).context("repack")?;
+    if let Some(expiry) = prune_expiry {
+        prune(expiry).context("prune")?;
+    }
+
+    Ok(())
+}
+
+/// Parse an expiry such as `now`, `never` or `2.weeks.ago` into a duration.
+/// `never` is represented as `None`.
+pub fn parse_expiry(value: &str) -> crate::Result<Option<Duration>> {
+    match value {
+        "now" => return Ok(Some(Duration::ZERO)),
+        "never" => return Ok(None),
+        _ => (),
+    }
+    let invalid = || Error::Message(format!("invalid expiry: {value}"));
+    let mut parts = value.split('.');
+    let (Some(count), Some(unit), Some("ago"), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+    let seconds = match unit.trim_end_matches('s') {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+
+    Ok(Some(Duration::from_secs(count * seconds)))
+}
+
+/// Drop reflog entries whose timestamp is older than `expiry`.
+fn expire_reflogs(expiry: Duration) -> crate::Result<()> {
+    let cutoff = SystemTime::now()
+        .duration_since(UNIX_EPOCH)?
+        .saturating_sub(expiry)
+        .as_secs();
+    for path in repack::reflog_paths()? {
+        let contents = fs::read_to_string(&path).context(format!("read reflog {:?}", path))?;
+        let kept: String = contents
+            .lines()
+            .filter(|line| {
+                // <old> <new> <name> <<email>> <timestamp> <tz>\t<message>
+                let identity = line.split('\t').next().unwrap_or_default();
+                let timestamp = identity.rsplit(' ').nth(1);
+                timestamp
+                    .and_then(|timestamp| timestamp.parse::<u64>().ok())
+                    .is_none_or(|timestamp| timestamp >= cutoff)
+            })
+            .map(|line| format!("{line}\n"))
+            .collect();
+        if kept.len() != contents.len() {
+            fs::write(&path, kept).context(format!("write reflog {:?}", path))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove unreachable loose objects last modified more than `expiry` ago.
+fn prune(expiry: Duration) -> crate::Result<()> {
+    let reachable = repack::reachable_objects()?;
+    let cutoff = SystemTime::now() - expiry;
+    for hash in pack::loose_objects()? {
+        if reachable.contains(&hash) {
+            continue;
+        }
+        let path = hash_to_path(&hash);
+        let modified = fs::metadata(&path)?.modified()?;
+        if modified <= cutoff {
+            log::debug!("Pruning unreachable object {hash}");
+            fs::remove_file(&path).context(format!("remove loose object {hash}"))?;
+            if let Some(dir) = path.parent() {
+                let _ = fs::remove_dir(dir);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_expiry() {
+        assert_eq!(parse_expiry("now").unwrap(), Some(Duration::ZERO));
+        assert_eq!(parse_expiry("never").unwrap(), None);
+        assert_eq!(
+            parse_expiry("2.weeks.ago").unwrap(),
+            Some(Duration::from_secs(14 * 24 * 60 * 60))
+        );
+        assert_eq!(
+            parse_expiry("1.day.ago").unwrap(),
+            Some(Duration::from_secs(24 * 60 * 60))
+        );
+        assert_eq!(
+            parse_expiry("90.minutes.ago").unwrap(),
+            Some(Duration::from_secs(90 * 60))
+        );
+        for invalid in [
+            "",
+            "soon",
+            "2.weeks",
+            "2.fortnights.ago",
+            "x.days.ago",
+            "1.day.ago.now",
+        ] {
+            assert!(parse_expiry(invalid).is_err(), "{invalid:?}");
+        }
+    }
+}
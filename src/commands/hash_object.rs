@@ -1,18 +1,26 @@
 use crate::common::{Object, ObjectType};
-use std::path::Path;
+use crate::error::Context;
+use crate::object_id::{ObjectFormat, ObjectId};
+use std::{fs::File, io::BufReader, path::Path};
 
 /// Compute hash of `file`'s contents' blob object representation.
-/// If `write` is `true`, write blob object.
-pub fn run(write: bool, file: impl AsRef<Path>) -> anyhow::Result<String> {
-    let contents = std::fs::read(file)?;
-    let blob = Object {
-        ty: ObjectType::Blob,
-        contents,
-    };
+/// If `write` is `true`, write blob object. The file is streamed, so its
+/// size is not limited by memory.
+pub fn run(write: bool, file: impl AsRef<Path>) -> crate::Result<ObjectId> {
+    let file = file.as_ref();
+    let reader = File::open(file).context(format!("open {}", file.display()))?;
+    let size = reader.metadata()?.len();
+    let reader = BufReader::new(reader);
     let hash = if write {
-        blob.write_to_objects_store()?
+        Object::stream_to_objects_store(ObjectType::Blob, size, reader)?
     } else {
-        blob.write(std::io::sink())?
+        Object::stream(
+            ObjectFormat::current()?,
+            ObjectType::Blob,
+            size,
+            reader,
+            std::io::sink(),
+        )?
     };
 
     Ok(hash)
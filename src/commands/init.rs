@@ -1,9 +1,28 @@
+use crate::config::Config;
+use crate::error::Context;
+use crate::object_id::ObjectFormat;
 use std::fs;
 
-pub fn run() {
-    fs::create_dir(".git").unwrap();
-    fs::create_dir(".git/objects").unwrap();
-    fs::create_dir(".git/refs").unwrap();
-    fs::write(".git/HEAD", "ref: refs/heads/main\n").unwrap();
+pub fn run(object_format: ObjectFormat) -> crate::Result<()> {
+    create_git_dir(object_format)?;
     println!("Initialized git directory");
+
+    Ok(())
+}
+
+/// Create an empty `.git` directory in the current directory for objects
+/// named with `object_format`.
+pub fn create_git_dir(object_format: ObjectFormat) -> crate::Result<()> {
+    fs::create_dir(".git").context("create .git")?;
+    fs::create_dir(".git/objects").context("create .git/objects")?;
+    fs::create_dir(".git/refs").context("create .git/refs")?;
+    fs::write(".git/HEAD", "ref: refs/heads/main\n").context("write HEAD")?;
+    if object_format != ObjectFormat::Sha1 {
+        let mut config = Config::default();
+        config.set("core.repositoryformatversion", "1");
+        config.set("extensions.objectFormat", &object_format.to_string());
+        config.write_to(".git/config")?;
+    }
+
+    Ok(())
 }
@@ -1,13 +1,196 @@
-use crate::common::{hash_to_path, parse_tree_entries, zlib_decode, TreeEntry};
-use anyhow::Ok;
+use crate::common::{read_tree, Object, ObjectType, TreeEntry};
+use crate::error::Context;
+use crate::object_id::ObjectId;
+use crate::revision;
 
-/// Return entries in tree object identified by `hash`.
-pub fn run(hash: &str) -> anyhow::Result<Vec<TreeEntry>> {
-    let object_path = hash_to_path(hash)?;
-    let object_contents = std::fs::read(object_path)?;
-    let decoded_object_contents = zlib_decode(&object_contents);
+/// Output format of `ls-tree` when none is given.
+pub const DEFAULT_FORMAT: &str = "%(objectmode) %(objecttype) %(objectname)%x09%(path)";
+/// Output format of `ls-tree --long`, which adds the size of blobs.
+pub const LONG_FORMAT: &str =
+    "%(objectmode) %(objecttype) %(objectname) %(objectsize:padded)%x09%(path)";
+/// Output format of `ls-tree --name-only`.
+pub const NAME_ONLY_FORMAT: &str = "%(path)";
 
-    let tree_entries = parse_tree_entries(&decoded_object_contents).unwrap().1;
+/// Return the entries of the tree-ish `treeish` (a tree, commit or tag,
+/// optionally with `:<path>`), named by their path relative to it.
+///
+/// Only entries matching one of `paths` are listed, if any are given: the
+/// entry at the path itself, or, for paths ending with `/`, the entries of
+/// the tree at the path. With `recursive`, subtrees are listed instead of
+/// shown, and only shown as well with `show_trees`. With `only_trees`,
+/// blobs are left out.
+pub fn run(
+    treeish: &str,
+    paths: &[String],
+    recursive: bool,
+    show_trees: bool,
+    only_trees: bool,
+) -> crate::Result<Vec<TreeEntry>> {
+    let tree = revision::resolve_tree(treeish)?;
+    // Like git, listing only trees recursively shows the trees recursed into.
+    let show_trees = show_trees || (recursive && only_trees);
+    let mut entries = Vec::new();
+    list(
+        &tree,
+        "",
+        paths,
+        recursive,
+        show_trees,
+        only_trees,
+        &mut entries,
+    )?;
 
-    Ok(tree_entries)
+    Ok(entries)
+}
+
+fn list(
+    tree: &ObjectId,
+    prefix: &str,
+    paths: &[String],
+    recursive: bool,
+    show_trees: bool,
+    only_trees: bool,
+    entries: &mut Vec<TreeEntry>,
+) -> crate::Result<()> {
+    for mut entry in read_tree(tree)? {
+        let path = format!("{prefix}{}", entry.file);
+        // The entry itself or something below it was asked for.
+        let selected = paths.is_empty()
+            || paths.iter().any(|filter| {
+                *filter == path
+                    || path
+                        .strip_prefix(filter.trim_end_matches('/'))
+                        .is_some_and(|rest| rest.starts_with('/'))
+            });
+        // A path below this entry was asked for.
+        let leads_to_filter = paths.iter().any(|filter| {
+            filter
+                .strip_prefix(&path)
+                .is_some_and(|rest| rest.starts_with('/'))
+        });
+        let descend = entry.ty == ObjectType::Tree && (leads_to_filter || (recursive && selected));
+        let show = if entry.ty == ObjectType::Tree {
+            (selected && !descend) || (descend && show_trees)
+        } else {
+            selected && !only_trees
+        };
+        let hash = entry.hash;
+        if show {
+            entry.file = path.clone();
+            entries.push(entry);
+        }
+        if descend {
+            list(
+                &hash,
+                &format!("{path}/"),
+                paths,
+                recursive,
+                show_trees,
+                only_trees,
+                entries,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Format `entry` with `format`, replacing `%(objectmode)`, `%(objecttype)`,
+/// `%(objectname)`, `%(objectsize)`, `%(objectsize:padded)` and `%(path)`
+/// as well as `%%`, `%n` and `%xNN` hex escapes, which may produce any byte.
+/// Any other `%` is an error.
+/// Object names are abbreviated to `abbrev` digits if given. Paths with
+/// unusual characters are quoted with `quote`.
+pub fn format_entry(
+    entry: &TreeEntry,
+    format: &str,
+    abbrev: Option<usize>,
+    quote: bool,
+) -> crate::Result<Vec<u8>> {
+    let mut formatted = Vec::new();
+    let mut remaining = format;
+    while let Some(start) = remaining.find('%') {
+        formatted.extend_from_slice(&remaining.as_bytes()[..start]);
+        remaining = &remaining[start + 1..];
+        if let Some(rest) = remaining.strip_prefix('%') {
+            formatted.push(b'%');
+            remaining = rest;
+            continue;
+        }
+        if let Some(rest) = remaining.strip_prefix('n') {
+            formatted.push(b'\n');
+            remaining = rest;
+            continue;
+        }
+        if let Some(hex) = remaining.strip_prefix('x') {
+            let byte = hex
+                .get(..2)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .context(format!("invalid hex escape in format {format:?}"))?;
+            formatted.push(byte);
+            remaining = &hex[2..];
+            continue;
+        }
+        let Some(atom) = remaining.strip_prefix('(') else {
+            crate::bail!("bad ls-tree format: element '{remaining}' does not start with '('");
+        };
+        let end = atom
+            .find(')')
+            .context(format!("malformed format {format:?}"))?;
+        let expanded = match &atom[..end] {
+            "objectmode" => format!("{:0>6}", entry.mode.to_string()),
+            "objecttype" => entry.ty.to_string(),
+            "objectname" => match abbrev {
+                Some(len) => entry.hash.abbrev(len),
+                None => entry.hash.to_string(),
+            },
+            "objectsize" => object_size(entry)?,
+            "objectsize:padded" => format!("{:>7}", object_size(entry)?),
+            "path" if quote => quote_path(&entry.file),
+            "path" => entry.file.clone(),
+            atom => crate::bail!("unknown format element: {atom}"),
+        };
+        formatted.extend_from_slice(expanded.as_bytes());
+        remaining = &atom[end + 1..];
+    }
+    formatted.extend_from_slice(remaining.as_bytes());
+
+    Ok(formatted)
+}
+
+/// Size of a blob entry, `-` for other entries.
+fn object_size(entry: &TreeEntry) -> crate::Result<String> {
+    if entry.ty != ObjectType::Blob {
+        return Ok("-".to_owned());
+    }
+
+    Ok(Object::read_header(&entry.hash)?.1.to_string())
+}
+
+/// Quote `path` the way git does if it contains control characters, quotes,
+/// backslashes or non-ASCII characters, which are escaped as octal bytes.
+fn quote_path(path: &str) -> String {
+    let needs_quoting = |byte: u8| !(0x20..0x7f).contains(&byte) || byte == b'"' || byte == b'\\';
+    if !path.bytes().any(needs_quoting) {
+        return path.to_owned();
+    }
+    let mut quoted = String::from("\"");
+    for byte in path.bytes() {
+        match byte {
+            b'\x07' => quoted.push_str("\\a"),
+            b'\x08' => quoted.push_str("\\b"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\x0b' => quoted.push_str("\\v"),
+            b'\x0c' => quoted.push_str("\\f"),
+            b'\r' => quoted.push_str("\\r"),
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            byte if needs_quoting(byte) => quoted.push_str(&format!("\\{byte:03o}")),
+            byte => quoted.push(byte as char),
+        }
+    }
+    quoted.push('"');
+
+    quoted
 }
@@ -1,7 +1,18 @@
+pub mod bundle;
 pub mod cat_file;
 pub mod clone;
 pub mod commit_tree;
+pub mod credential_cache;
+pub mod daemon;
+pub mod fetch;
+pub mod fsck;
+pub mod gc;
 pub mod hash_object;
 pub mod init;
 pub mod ls_tree;
+pub mod push;
+pub mod receive_pack;
+pub mod repack;
+pub mod serve;
+pub mod upload_pack;
 pub mod write_tree;
@@ -0,0 +1,685 @@
+use crate::common::{git_dir, is_ancestor, walk_commits, walk_tree, Commit, Object, ObjectType};
+use crate::config::Config;
+use crate::error::Context;
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::pack;
+use crate::pktline::{self, PktLineReader, PktLineWriter, SidebandReader};
+use crate::refs;
+use crate::refspec::{self, Refspec};
+use crate::transport::{self, HttpClient, LocalTransport, StreamTransport};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    io::{Read, Write},
+};
+
+/// Outcome of updating a single remote ref.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefStatus {
+    UpToDate,
+    Ok,
+    /// Refused locally before anything was sent.
+    Rejected(String),
+    /// Refused by the remote.
+    RemoteRejected(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct RefUpdate {
+    /// Local source ref, empty for deletes.
+    pub src: String,
+    /// Remote ref being updated.
+    pub dst: String,
+    pub old: ObjectId,
+    pub new: ObjectId,
+    pub forced: bool,
+    pub status: RefStatus,
+}
+
+impl RefUpdate {
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self.status,
+            RefStatus::Rejected(_) | RefStatus::RemoteRejected(_)
+        )
+    }
+}
+
+impl Display for RefUpdate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let src = short_ref_name(&self.src);
+        let dst = short_ref_name(&self.dst);
+        let refs = format!("{src} -> {dst}");
+        match &self.status {
+            RefStatus::UpToDate => write!(f, " = {:<17} {refs}", "[up to date]"),
+            RefStatus::Ok if self.new.is_null() => write!(f, " - {:<17} {dst}", "[deleted]"),
+            RefStatus::Ok if self.old.is_null() => {
+                let kind = if self.dst.starts_with("refs/heads/") {
+                    "[new branch]"
+                } else if self.dst.starts_with("refs/tags/") {
+                    "[new tag]"
+                } else {
+                    "[new reference]"
+                };
+                write!(f, " * {kind:<17} {refs}")
+            }
+            RefStatus::Ok if self.forced => {
+                let range = format!("{}...{}", self.old.abbrev(7), self.new.abbrev(7));
+                write!(f, " + {range:<17} {refs} (forced update)")
+            }
+            RefStatus::Ok => {
+                let range = format!("{}..{}", self.old.abbrev(7), self.new.abbrev(7));
+                write!(f, "   {range:<17} {refs}")
+            }
+            RefStatus::Rejected(reason) => write!(f, " ! {:<17} {refs} ({reason})", "[rejected]"),
+            RefStatus::RemoteRejected(reason) => {
+                write!(f, " ! {:<17} {refs} ({reason})", "[remote rejected]")
+            }
+        }
+    }
+}
+
+/// Push the local refs selected by `refspecs` to `remote` (a configured remote
+/// name or a URL). Without refspecs, the current branch is pushed.
+/// `force_with_lease` is `Some("")` to protect every ref with its remote-tracking
+/// ref, `Some("<ref>")` or `Some("<ref>:<expected>")` to protect a single ref.
+/// Returns the remote URL and the result for each ref.
+pub fn run(
+    remote: &str,
+    refspecs: &[String],
+    force: bool,
+    force_with_lease: Option<&str>,
+) -> crate::Result<(String, Vec<RefUpdate>)> {
+    let config = Config::read()?;
+    let url = config
+        .get(&format!("remote.{remote}.pushurl"))
+        .or(config.get(&format!("remote.{remote}.url")))
+        .unwrap_or(remote)
+        .trim_end_matches('/')
+        .to_owned();
+    let fetch_refspecs = config
+        .get_all(&format!("remote.{remote}.fetch"))
+        .into_iter()
+        .map(Refspec::parse)
+        .collect::<crate::Result<Vec<_>>>()?;
+    // Remote-tracking ref for the remote ref `name`.
+    let tracking_ref = |name: &str| fetch_refspecs.iter().find_map(|spec| spec.map_src(name));
+
+    let mut target = if transport::is_ssh_url(&url) || transport::is_daemon_url(&url) {
+        Target::Stream(StreamTransport::connect(&url, "git-receive-pack")?)
+    } else if transport::is_local_url(&url) {
+        Target::Local(LocalTransport::open(&url)?)
+    } else {
+        Target::Http(HttpClient::new(&url)?)
+    };
+    let (advertised, capabilities) = match &target {
+        Target::Http(client) => discover_refs(client)?,
+        Target::Stream(stream) => match stream.advertisement() {
+            transport::Advertisement::V0 { refs, capabilities } => {
+                let refs = refs.iter().map(|r| (r.name.clone(), r.hash));
+                (refs.collect(), capabilities.clone())
+            }
+            transport::Advertisement::V2 { .. } => {
+                crate::bail!("receive-pack advertised protocol v2")
+            }
+        },
+        Target::Local(local) => {
+            let refs = local
+                .ls_refs(&["refs/".to_owned()])?
+                .into_iter()
+                .map(|r| (r.name, r.hash))
+                .collect();
+            let capabilities = vec![
+                "delete-refs".to_owned(),
+                format!("object-format={}", local.object_format()?),
+            ];
+            (refs, capabilities)
+        }
+    };
+    transport::check_object_format(transport::object_format(&capabilities)?)?;
+    let null = ObjectFormat::current()?.null();
+
+    let refspecs = if refspecs.is_empty() {
+        let head = std::fs::read_to_string(git_dir().join("HEAD")).context("read HEAD")?;
+        let branch = head
+            .trim_end()
+            .strip_prefix("ref: ")
+            .context("HEAD is detached, specify a refspec")?;
+        vec![Refspec::parse(branch)?]
+    } else {
+        refspecs
+            .iter()
+            .map(|spec| Refspec::parse(spec))
+            .collect::<crate::Result<Vec<_>>>()?
+    };
+
+    let mut updates = Vec::new();
+    for spec in &refspecs {
+        crate::ensure!(!spec.is_glob(), "glob refspecs are not supported for push");
+        let (src, new) = if spec.is_delete() {
+            (String::new(), null)
+        } else {
+            resolve_src(&spec.src)?
+        };
+        let dst = match &spec.dst {
+            Some(dst) if dst.starts_with("refs/") => dst.clone(),
+            Some(dst) => expand_dst(dst, &src, &advertised)?,
+            None if src.starts_with("refs/") => src.clone(),
+            None => expand_dst(&spec.src, &src, &advertised)?,
+        };
+        let old = advertised
+            .iter()
+            .find(|(name, _)| *name == dst)
+            .map_or(null, |(_, hash)| *hash);
+
+        let lease = match force_with_lease {
+            Some("") => Some(tracking_ref(&dst)),
+            Some(lease) => {
+                let (name, expected) = match lease.split_once(':') {
+                    Some((name, expected)) => (name, Some(expected)),
+                    None => (lease, None),
+                };
+                if name == dst || Some(name) == dst.strip_prefix("refs/heads/") {
+                    match expected {
+                        Some(expected) => Some(Some(expected.to_owned())),
+                        None => Some(tracking_ref(&dst)),
+                    }
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+
+        let mut update = RefUpdate {
+            src,
+            dst,
+            old,
+            new,
+            forced: false,
+            status: RefStatus::Ok,
+        };
+        update.status = check_update(&update, force || spec.force, lease)?;
+        update.forced = update.status == RefStatus::Ok
+            && !update.old.is_null()
+            && !update.new.is_null()
+            && !is_ancestor(&update.old, &update.new).unwrap_or(false);
+        updates.push(update);
+    }
+
+    let to_send: Vec<&RefUpdate> = updates
+        .iter()
+        .filter(|update| update.status == RefStatus::Ok)
+        .collect();
+    if to_send.is_empty() {
+        return Ok((url, updates));
+    }
+    if to_send.iter().any(|update| update.new.is_null()) {
+        crate::ensure!(
+            capabilities.iter().any(|c| c == "delete-refs"),
+            "remote does not support deleting refs"
+        );
+    }
+
+    let tips: Vec<ObjectId> = to_send
+        .iter()
+        .filter(|update| !update.new.is_null())
+        .map(|update| update.new)
+        .collect();
+    let (objects, mut bases) = if tips.is_empty() {
+        (Vec::new(), HashMap::new())
+    } else {
+        pack_objects(tips, &advertised)?
+    };
+    if capabilities.iter().any(|c| c == "no-thin") {
+        bases.clear();
+    }
+    let report = match &mut target {
+        Target::Http(client) => {
+            let body = receive_pack_request(&capabilities, &to_send, &objects, &bases)?;
+            let headers = [("Content-Type", "application/x-git-receive-pack-request")];
+            let response = client
+                .post("git-receive-pack", &headers, &body)
+                .context("send receive-pack request")?;
+            crate::ensure!(
+                response.status().is_success(),
+                "receive-pack failed: {}",
+                response.status()
+            );
+            read_report(response, &capabilities, &to_send)?
+        }
+        Target::Stream(stream) => {
+            let body = receive_pack_request(&capabilities, &to_send, &objects, &bases)?;
+            stream
+                .writer()?
+                .get_mut()
+                .write_all(&body)
+                .context("send receive-pack request")?;
+            let report = read_report(stream.reader().into_inner(), &capabilities, &to_send)?;
+            stream.close();
+            report
+        }
+        Target::Local(local) => push_local(local, &to_send, &objects)?,
+    };
+
+    for update in &mut updates {
+        if update.status != RefStatus::Ok {
+            continue;
+        }
+        if let Some(reason) = &report.unpack_error {
+            update.status = RefStatus::RemoteRejected(format!("unpacker error: {reason}"));
+        } else if let Some((_, reason)) =
+            report.rejected.iter().find(|(name, _)| *name == update.dst)
+        {
+            update.status = RefStatus::RemoteRejected(reason.clone());
+        } else if let Some(tracking) = tracking_ref(&update.dst) {
+            if update.new.is_null() {
+                refs::delete_ref(&tracking)?;
+            } else {
+                refs::update_ref(&tracking, &update.new)?;
+            }
+        }
+    }
+
+    Ok((url, updates))
+}
+
+/// Where a push goes.
+enum Target {
+    Http(HttpClient),
+    Stream(StreamTransport),
+    Local(LocalTransport),
+}
+
+/// Build a receive-pack request: the ref update commands followed by the pack,
+/// a thin one with objects deltified against `bases`.
+fn receive_pack_request(
+    capabilities: &[String],
+    to_send: &[&RefUpdate],
+    objects: &[(ObjectId, Object)],
+    bases: &HashMap<ObjectId, ObjectId>,
+) -> crate::Result<Vec<u8>> {
+    let mut writer = PktLineWriter::new(Vec::new());
+    for (idx, update) in to_send.iter().enumerate() {
+        let mut line = format!("{} {} {}", update.old, update.new, update.dst);
+        if idx == 0 {
+            line.push_str("\0report-status");
+            if capabilities.iter().any(|c| c == "delete-refs") {
+                line.push_str(" delete-refs");
+            }
+            if capabilities.iter().any(|c| c == "side-band-64k") {
+                line.push_str(" side-band-64k");
+            }
+            if capabilities.iter().any(|c| c.starts_with("object-format=")) {
+                line.push_str(&format!(" object-format={}", ObjectFormat::current()?));
+            }
+            line.push_str(" agent=mygit/0.1.0");
+        }
+        writer.write_line(&line)?;
+    }
+    writer.write_flush()?;
+    let mut body = writer.into_inner();
+    // receive-pack expects a pack, possibly empty, unless all updates are deletions.
+    if to_send.iter().any(|update| !update.new.is_null()) {
+        body.extend(pack::encode_thin_pack(objects, bases)?);
+    }
+
+    Ok(body)
+}
+
+/// Read the `report-status` response, demultiplexing it if side-band was requested.
+fn read_report(
+    response: impl Read,
+    capabilities: &[String],
+    to_send: &[&RefUpdate],
+) -> crate::Result<Report> {
+    if capabilities.iter().any(|c| c == "side-band-64k") {
+        parse_report(
+            SidebandReader::new(PktLineReader::new(response), pktline::print_progress),
+            to_send,
+        )
+    } else {
+        parse_report(response, to_send)
+    }
+}
+
+/// Apply the ref updates directly to a repository on the same filesystem,
+/// writing `objects` into its object store as a new pack.
+fn push_local(
+    local: &LocalTransport,
+    to_send: &[&RefUpdate],
+    objects: &[(ObjectId, Object)],
+) -> crate::Result<Report> {
+    let mut report = Report::default();
+    if !objects.is_empty() {
+        pack::write_pack_to(&local.git_dir().join("objects/pack"), objects)?;
+    }
+    let checked_out = if local.is_bare() {
+        None
+    } else {
+        refs::read_symref_in(local.git_dir(), "HEAD")?
+    };
+    for update in to_send {
+        let current = refs::read_ref_in(local.git_dir(), &update.dst)?;
+        if checked_out.as_deref() == Some(update.dst.as_str()) {
+            report.rejected.push((
+                update.dst.clone(),
+                "branch is currently checked out".to_owned(),
+            ));
+        } else if current.unwrap_or(ObjectFormat::current()?.null()) != update.old {
+            report
+                .rejected
+                .push((update.dst.clone(), "failed to lock".to_owned()));
+        } else if update.new.is_null() {
+            refs::delete_ref_in(local.git_dir(), &update.dst)?;
+        } else {
+            refs::update_ref_in(local.git_dir(), &update.dst, &update.new)?;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Resolve the source side of a refspec to a full ref name and hash.
+fn resolve_src(src: &str) -> crate::Result<(String, ObjectId)> {
+    if let Ok(hash) = src.parse::<ObjectId>() {
+        if Object::exists(&hash).unwrap_or(false) {
+            return Ok((src.to_owned(), hash));
+        }
+    }
+    let mut name =
+        refspec::expand_local(src)?.context(format!("src refspec {src} does not match any"))?;
+    if name == "HEAD" {
+        let head = std::fs::read_to_string(git_dir().join("HEAD")).context("read HEAD")?;
+        if let Some(branch) = head.trim_end().strip_prefix("ref: ") {
+            name = branch.to_owned();
+        }
+    }
+    let hash = refs::read_ref(&name)?.context(format!("src refspec {src} does not match any"))?;
+
+    Ok((name, hash))
+}
+
+/// Expand a short destination name, preferring an existing remote ref and
+/// otherwise using the namespace of the source ref.
+fn expand_dst(dst: &str, src: &str, advertised: &[(String, ObjectId)]) -> crate::Result<String> {
+    for prefix in ["refs/heads/", "refs/tags/"] {
+        let name = format!("{prefix}{dst}");
+        if advertised
+            .iter()
+            .any(|(advertised_name, _)| *advertised_name == name)
+        {
+            return Ok(name);
+        }
+    }
+    if src.starts_with("refs/tags/") {
+        Ok(format!("refs/tags/{dst}"))
+    } else if src.starts_with("refs/heads/") || src == dst || src.is_empty() {
+        Ok(format!("refs/heads/{dst}"))
+    } else {
+        crate::bail!("destination {dst} is not a full ref name")
+    }
+}
+
+/// Decide locally whether `update` may be sent. `lease` is `Some(expected)` if the
+/// update is protected by `--force-with-lease`, where `expected` is `None` if the
+/// ref is expected not to exist on the remote.
+fn check_update(
+    update: &RefUpdate,
+    force: bool,
+    lease: Option<Option<String>>,
+) -> crate::Result<RefStatus> {
+    if update.old == update.new {
+        return Ok(RefStatus::UpToDate);
+    }
+    if update.new.is_null() && update.old.is_null() {
+        return Ok(RefStatus::Rejected("remote ref does not exist".to_owned()));
+    }
+    if let Some(expected) = lease {
+        let expected = match expected {
+            Some(expected) => match refs::read_ref(&expected)? {
+                Some(hash) => hash,
+                None => expected.parse()?,
+            },
+            None => ObjectFormat::current()?.null(),
+        };
+        return Ok(if expected == update.old {
+            RefStatus::Ok
+        } else {
+            RefStatus::Rejected("stale info".to_owned())
+        });
+    }
+    if force || update.old.is_null() || update.new.is_null() {
+        return Ok(RefStatus::Ok);
+    }
+    if update.dst.starts_with("refs/tags/") {
+        return Ok(RefStatus::Rejected("already exists".to_owned()));
+    }
+    if !Object::exists(&update.old)? {
+        return Ok(RefStatus::Rejected("fetch first".to_owned()));
+    }
+    if !is_ancestor(&update.old, &update.new)? {
+        return Ok(RefStatus::Rejected("non-fast-forward".to_owned()));
+    }
+
+    Ok(RefStatus::Ok)
+}
+
+/// Collect the objects reachable from `tips` that the remote does not have,
+/// judging by the advertised refs we have locally. Also returns, for objects
+/// at a path that already exists on the remote, the object there to send
+/// them as a delta against.
+#[allow(clippy::type_complexity)]
+fn pack_objects(
+    tips: Vec<ObjectId>,
+    advertised: &[(String, ObjectId)],
+) -> crate::Result<(Vec<(ObjectId, Object)>, HashMap<ObjectId, ObjectId>)> {
+    let mut remote_tips = Vec::new();
+    for (_, hash) in advertised {
+        if !hash.is_null() && Object::exists(hash)? {
+            remote_tips.push(*hash);
+        }
+    }
+    let (new_commits, remote_commits, boundary) =
+        walk_commits(&tips, &remote_tips, &HashSet::new())?;
+
+    // Objects the remote has: the commits walked from its refs, and the trees
+    // of those that the new commits build on.
+    let mut seen: HashSet<ObjectId> = remote_commits.into_iter().chain(remote_tips).collect();
+    let mut remote_paths = HashMap::new();
+    for commit in &boundary {
+        let tree = Commit::read(commit)?.tree;
+        if seen.insert(tree) {
+            walk_tree(&tree, "", &mut seen, &mut |path, entry| {
+                remote_paths.entry(path).or_insert(entry.hash);
+                Ok(true)
+            })?;
+        }
+    }
+
+    let partial = crate::promisor::is_partial_clone()?;
+    let mut objects = Vec::new();
+    let mut bases = HashMap::new();
+    let mut add_tree = |tree: ObjectId,
+                        seen: &mut HashSet<ObjectId>,
+                        objects: &mut Vec<(ObjectId, Object)>|
+     -> crate::Result<()> {
+        if !seen.insert(tree) {
+            return Ok(());
+        }
+        objects.push((tree, Object::read(&tree)?));
+        walk_tree(&tree, "", seen, &mut |path, entry| {
+            // Objects left out of a partial clone are on the remote already.
+            if partial && !Object::exists(&entry.hash)? {
+                return Ok(false);
+            }
+            if let Some(base) = remote_paths.get(&path) {
+                bases.insert(entry.hash, *base);
+            }
+            objects.push((entry.hash, Object::read(&entry.hash)?));
+            Ok(true)
+        })
+    };
+    for commit in new_commits {
+        let object = Object::read(&commit)?;
+        let tree = Commit::parse(&object.contents)?.tree;
+        seen.insert(commit);
+        objects.push((commit, object));
+        add_tree(tree, &mut seen, &mut objects)?;
+    }
+    // Tags and whatever else they point to.
+    for mut hash in tips {
+        while !seen.contains(&hash) {
+            let object = Object::read(&hash)?;
+            match object.ty {
+                ObjectType::Tree => {
+                    add_tree(hash, &mut seen, &mut objects)?;
+                    break;
+                }
+                ObjectType::Tag => {
+                    let target = object.references()?.into_iter().next();
+                    seen.insert(hash);
+                    objects.push((hash, object));
+                    match target {
+                        Some(target) => hash = target,
+                        None => break,
+                    }
+                }
+                ObjectType::Blob | ObjectType::Commit => {
+                    seen.insert(hash);
+                    objects.push((hash, object));
+                }
+            }
+        }
+    }
+
+    Ok((objects, bases))
+}
+
+/// Fetch the receive-pack ref advertisement. Returns the advertised refs and
+/// the server's capabilities.
+#[allow(clippy::type_complexity)]
+fn discover_refs(client: &HttpClient) -> crate::Result<(Vec<(String, ObjectId)>, Vec<String>)> {
+    let response = client
+        .get("info/refs?service=git-receive-pack", &[])
+        .context("request ref advertisement")?;
+    crate::ensure!(
+        response.status().is_success(),
+        "ref discovery failed: {}",
+        response.status()
+    );
+
+    let transport::Advertisement::V0 { refs, capabilities } =
+        transport::read_advertisement(&mut PktLineReader::new(response))?
+    else {
+        crate::bail!("receive-pack advertised protocol v2");
+    };
+    let refs = refs.into_iter().map(|r| (r.name, r.hash)).collect();
+
+    Ok((refs, capabilities))
+}
+
+#[derive(Debug, Default)]
+struct Report {
+    unpack_error: Option<String>,
+    rejected: Vec<(String, String)>,
+}
+
+/// Parse a `report-status` response to the commands `to_send`. Each command
+/// must get exactly one status; those that don't are taken to have failed.
+fn parse_report(response: impl Read, to_send: &[&RefUpdate]) -> crate::Result<Report> {
+    let mut report = Report::default();
+    let (lines, _) = PktLineReader::new(response).read_lines()?;
+    let mut lines = lines.iter();
+    match lines.next().and_then(|line| line.strip_prefix("unpack ")) {
+        Some("ok") => (),
+        Some(status) => report.unpack_error = Some(status.to_owned()),
+        None => report.unpack_error = Some("no unpack status reported".to_owned()),
+    }
+    let mut statuses: HashMap<&str, Vec<Option<&str>>> = HashMap::new();
+    for line in lines {
+        if let Some(name) = line.strip_prefix("ok ") {
+            statuses.entry(name).or_default().push(None);
+        } else if let Some(rest) = line.strip_prefix("ng ") {
+            let (name, reason) = rest.split_once(' ').unwrap_or((rest, "failed"));
+            statuses.entry(name).or_default().push(Some(reason));
+        } else {
+            crate::bail!(Protocol, "unexpected report-status line '{line}'");
+        }
+    }
+    for update in to_send {
+        let reason = match statuses.get(update.dst.as_str()).map(Vec::as_slice) {
+            Some([None]) => continue,
+            Some([Some(reason)]) => reason,
+            Some([]) | None => "remote failed to report status",
+            Some(_) => "remote reported more than one status",
+        };
+        report
+            .rejected
+            .push((update.dst.clone(), reason.to_owned()));
+    }
+
+    Ok(report)
+}
+
+fn short_ref_name(name: &str) -> &str {
+    name.strip_prefix("refs/heads/")
+        .or(name.strip_prefix("refs/tags/"))
+        .unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(lines: &[&str], names: &[&str]) -> Report {
+        let mut writer = PktLineWriter::new(Vec::new());
+        for line in lines {
+            writer.write_line(line).unwrap();
+        }
+        writer.write_flush().unwrap();
+        let updates: Vec<RefUpdate> = names
+            .iter()
+            .map(|name| RefUpdate {
+                src: String::new(),
+                dst: name.to_string(),
+                old: ObjectFormat::Sha1.null(),
+                new: ObjectFormat::Sha1.null(),
+                forced: false,
+                status: RefStatus::Ok,
+            })
+            .collect();
+        let to_send: Vec<&RefUpdate> = updates.iter().collect();
+
+        parse_report(writer.into_inner().as_slice(), &to_send).unwrap()
+    }
+
+    #[test]
+    fn parses_report_status() {
+        let ok = report(
+            &["unpack ok", "ok refs/heads/a", "ng refs/heads/b stale info"],
+            &["refs/heads/a", "refs/heads/b"],
+        );
+        assert_eq!(ok.unpack_error, None);
+        assert_eq!(
+            ok.rejected,
+            [("refs/heads/b".to_owned(), "stale info".to_owned())]
+        );
+
+        let missing_unpack = report(&["ok refs/heads/a"], &["refs/heads/a"]);
+        assert!(missing_unpack.unpack_error.is_some());
+        let unpack_failed = report(&["unpack error", "ok refs/heads/a"], &["refs/heads/a"]);
+        assert_eq!(unpack_failed.unpack_error.as_deref(), Some("error"));
+
+        let incomplete = report(
+            &["unpack ok", "ok refs/heads/a", "ok refs/heads/a"],
+            &["refs/heads/a", "refs/heads/b"],
+        );
+        let rejected: Vec<&str> = incomplete
+            .rejected
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(rejected, ["refs/heads/a", "refs/heads/b"]);
+    }
+}
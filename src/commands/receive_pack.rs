@@ -0,0 +1,132 @@
+use crate::common::{enter_repository, git_dir, is_bare, Object};
+use crate::error::Context;
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::pack;
+use crate::pktline::{PktLineReader, PktLineWriter};
+use crate::refs;
+use crate::transport;
+use std::{io::Write, path::Path};
+
+/// A ref update requested by a pushing client.
+#[derive(Debug)]
+struct Command {
+    old: ObjectId,
+    new: ObjectId,
+    name: String,
+}
+
+/// Receive objects and ref updates pushed to the repository at `directory`
+/// over stdin and stdout. With `stateless_rpc` no advertisement is written,
+/// as over HTTP; with `advertise_refs` only the advertisement is written.
+pub fn run(directory: &Path, stateless_rpc: bool, advertise_refs: bool) -> crate::Result<()> {
+    enter_repository(directory)?;
+    let mut reader = PktLineReader::new(std::io::stdin().lock());
+    let mut writer = PktLineWriter::new(std::io::stdout().lock());
+    if !stateless_rpc || advertise_refs {
+        let capabilities = [
+            "report-status",
+            "delete-refs",
+            "side-band-64k",
+            "ofs-delta",
+            &format!("object-format={}", ObjectFormat::current()?),
+            "agent=mygit/0.1.0",
+        ]
+        .map(str::to_owned);
+        transport::write_advertisement(&mut writer, &refs::list_refs()?, &capabilities)?;
+        writer.get_mut().flush()?;
+        if advertise_refs {
+            return Ok(());
+        }
+    }
+
+    let (lines, _) = reader.read_lines()?;
+    if lines.is_empty() {
+        // Nothing to update.
+        return Ok(());
+    }
+    let mut capabilities = Vec::new();
+    let mut commands = Vec::new();
+    for line in &lines {
+        let line = match line.split_once('\0') {
+            Some((line, caps)) => {
+                capabilities.extend(caps.split(' ').map(str::to_owned));
+                line
+            }
+            None => line,
+        };
+        let mut parts = line.splitn(3, ' ');
+        let (Some(old), Some(new), Some(name)) = (parts.next(), parts.next(), parts.next()) else {
+            crate::bail!(Protocol, "invalid command {line:?}");
+        };
+        commands.push(Command {
+            old: old.parse()?,
+            new: new.parse()?,
+            name: name.to_owned(),
+        });
+    }
+    let has = |capability: &str| capabilities.iter().any(|c| c == capability);
+
+    let unpack = if commands.iter().any(|command| !command.new.is_null()) {
+        pack::read_pack_stream(&mut reader.into_inner())
+            .and_then(|data| pack::index_pack(&data))
+            .map(|_| ())
+    } else {
+        Ok(())
+    };
+    let mut report = Vec::new();
+    match &unpack {
+        Ok(()) => report.push("unpack ok".to_owned()),
+        Err(err) => report.push(format!("unpack {err}")),
+    }
+    for command in &commands {
+        let result = match &unpack {
+            Ok(()) => update(command),
+            Err(_) => Err("unpacker error".to_owned()),
+        };
+        match result {
+            Ok(()) => report.push(format!("ok {}", command.name)),
+            Err(reason) => report.push(format!("ng {} {reason}", command.name)),
+        }
+    }
+
+    if has("report-status") {
+        let mut status = PktLineWriter::new(Vec::new());
+        for line in &report {
+            status.write_line(line)?;
+        }
+        status.write_flush()?;
+        if has("side-band-64k") {
+            writer.write_band(1, &status.into_inner())?;
+            writer.write_flush()?;
+        } else {
+            writer.get_mut().write_all(&status.into_inner())?;
+        }
+    }
+    writer.get_mut().flush()?;
+
+    unpack.context("unpack pushed objects")
+}
+
+/// Apply a single ref update, returning the reason it was refused.
+fn update(command: &Command) -> Result<(), String> {
+    let refuse = |err: crate::Error| err.to_string();
+    if !command.name.starts_with("refs/") || refs::check_ref_format(&command.name).is_err() {
+        return Err("funny refname".to_owned());
+    }
+    if !is_bare(&git_dir())
+        && refs::read_symref("HEAD").map_err(refuse)?.as_deref() == Some(command.name.as_str())
+    {
+        return Err("branch is currently checked out".to_owned());
+    }
+    let current = refs::read_ref(&command.name).map_err(refuse)?;
+    if current.unwrap_or(command.old.format().null()) != command.old {
+        return Err("failed to lock".to_owned());
+    }
+    if command.new.is_null() {
+        refs::delete_ref(&command.name).map_err(refuse)
+    } else if !Object::exists(&command.new).map_err(refuse)? {
+        Err("missing necessary objects".to_owned())
+    } else {
+        refs::update_ref(&command.name, &command.new).map_err(refuse)
+    }
+}
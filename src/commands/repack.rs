@@ -0,0 +1,179 @@
+use crate::common::{git_dir, reachable_from, Object};
+use crate::error::Context;
+use crate::object_id::ObjectId;
+use crate::object_store::FileStore;
+use crate::pack::{self, Pack};
+use crate::refs;
+use std::{collections::HashSet, fs, path::Path};
+
+/// Pack reachable objects into a new pack and return its name.
+/// If `all` is `true`, every reachable object is packed, otherwise only loose ones.
+/// If `delete` is `true`, remove packs made redundant by the new pack and
+/// loose objects that are now packed. With `unpack_unreachable`, the
+/// unreachable objects of the removed packs are kept as loose objects, to be
+/// pruned once they are old enough.
+pub fn run(all: bool, delete: bool, unpack_unreachable: bool) -> crate::Result<Option<String>> {
+    let reachable = reachable_objects()?;
+    let old_packs = Pack::all()?;
+    let mut hashes: Vec<ObjectId> = if all {
+        reachable.iter().copied().collect()
+    } else {
+        reachable
+            .iter()
+            .copied()
+            .filter(|hash| !old_packs.iter().any(|pack| pack.contains(hash)))
+            .collect()
+    };
+    hashes.sort();
+    // Objects from promisor packs go into a promisor pack of their own, so the
+    // objects they reference may stay missing.
+    let (promised, hashes): (Vec<ObjectId>, Vec<ObjectId>) = hashes.into_iter().partition(|hash| {
+        old_packs
+            .iter()
+            .any(|pack| pack.is_promisor() && pack.contains(hash))
+    });
+    let promisor_name = write_pack(promised)?;
+    if let Some(name) = &promisor_name {
+        pack::mark_promisor(name)?;
+    }
+
+    let name = write_pack(hashes)?;
+    if name.is_none() && promisor_name.is_none() {
+        log::debug!("Nothing new to pack");
+    }
+
+    if delete {
+        if all {
+            for old_pack in &old_packs {
+                let is_new = [&name, &promisor_name]
+                    .into_iter()
+                    .flatten()
+                    .any(|name| old_pack.pack_path.ends_with(format!("pack-{name}.pack")));
+                let is_kept = old_pack.pack_path.with_extension("keep").exists();
+                if !is_new && !is_kept {
+                    if unpack_unreachable {
+                        unpack_unreachable_objects(old_pack, &reachable)?;
+                    }
+                    log::debug!("Removing redundant pack {:?}", old_pack.pack_path);
+                    fs::remove_file(&old_pack.pack_path).context("remove pack")?;
+                    fs::remove_file(old_pack.pack_path.with_extension("idx"))
+                        .context("remove pack index")?;
+                    if old_pack.is_promisor() {
+                        fs::remove_file(old_pack.pack_path.with_extension("promisor"))
+                            .context("remove promisor file")?;
+                    }
+                }
+            }
+        }
+        prune_packed()?;
+    }
+
+    Ok(name)
+}
+
+/// Write the objects of `pack` that are not in `reachable` out as loose
+/// objects, dated like the pack so that pruning goes by the pack's age.
+fn unpack_unreachable_objects(pack: &Pack, reachable: &HashSet<ObjectId>) -> crate::Result<()> {
+    let store = FileStore::current()?;
+    let modified = fs::metadata(&pack.pack_path)?.modified()?;
+    for hash in pack.hashes() {
+        let path = store.loose_path(&hash);
+        if reachable.contains(&hash) || path.exists() {
+            continue;
+        }
+        let object = pack
+            .read(&hash)?
+            .context(format!("read unreachable object {hash}"))?;
+        log::debug!("Unpacking unreachable object {hash}");
+        store.write_loose(&object)?;
+        fs::File::open(&path)
+            .and_then(|file| file.set_modified(modified))
+            .context(format!("set time of loose object {hash}"))?;
+    }
+
+    Ok(())
+}
+
+/// Write the objects `hashes` into a new pack, returning its name unless there
+/// are none.
+fn write_pack(hashes: Vec<ObjectId>) -> crate::Result<Option<String>> {
+    if hashes.is_empty() {
+        return Ok(None);
+    }
+    let objects = hashes
+        .into_iter()
+        .map(|hash| Ok((hash, Object::read(&hash)?)))
+        .collect::<crate::Result<Vec<_>>>()?;
+
+    Ok(Some(pack::write_pack(&objects)?))
+}
+
+/// Hashes of all objects reachable from refs, `HEAD` and reflog entries.
+pub fn reachable_objects() -> crate::Result<HashSet<ObjectId>> {
+    let mut pending: Vec<ObjectId> = refs::list_refs()?
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect();
+    if let Some(head) = refs::read_ref("HEAD")? {
+        pending.push(head);
+    }
+    pending.extend(reflog_objects()?);
+
+    reachable_from(pending, &HashSet::new())
+}
+
+/// Object hashes mentioned in reflog entries below `.git/logs`.
+fn reflog_objects() -> crate::Result<Vec<ObjectId>> {
+    let mut hashes = Vec::new();
+    for path in reflog_paths()? {
+        let contents = fs::read_to_string(&path).context(format!("read reflog {:?}", path))?;
+        for line in contents.lines() {
+            let mut fields = line.split(' ');
+            for field in [fields.next(), fields.next()].into_iter().flatten() {
+                hashes.extend(field.parse::<ObjectId>().ok());
+            }
+        }
+    }
+
+    Ok(hashes)
+}
+
+/// Paths of all reflog files.
+pub fn reflog_paths() -> crate::Result<Vec<std::path::PathBuf>> {
+    fn walk(dir: &Path, paths: &mut Vec<std::path::PathBuf>) -> crate::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                walk(&path, paths)?;
+            } else {
+                paths.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    let mut paths = Vec::new();
+    let logs = git_dir().join("logs");
+    if logs.is_dir() {
+        walk(&logs, &mut paths)?;
+    }
+
+    Ok(paths)
+}
+
+/// Remove loose objects that are also stored in a pack.
+fn prune_packed() -> crate::Result<()> {
+    let packs = Pack::all()?;
+    for hash in pack::loose_objects()? {
+        if packs.iter().any(|pack| pack.contains(&hash)) {
+            let path = crate::common::hash_to_path(&hash);
+            fs::remove_file(&path).context(format!("remove loose object {hash}"))?;
+            if let Some(dir) = path.parent() {
+                // Only succeeds once the fan-out directory is empty.
+                let _ = fs::remove_dir(dir);
+            }
+        }
+    }
+
+    Ok(())
+}
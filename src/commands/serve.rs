@@ -0,0 +1,285 @@
+use crate::common::find_git_dir;
+use crate::error::Context;
+use crate::pktline::PktLineWriter;
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Arc,
+};
+
+/// Options shared by all connections of a server.
+#[derive(Debug)]
+struct Server {
+    /// A repository, or a directory containing repositories.
+    root: PathBuf,
+    /// Expected `user:password` for basic authentication.
+    auth: Option<String>,
+    read_only: bool,
+}
+
+/// A parsed HTTP request.
+#[derive(Debug)]
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    /// Header names are lowercased.
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Serve the repository at `directory`, or every repository below it, over
+/// smart HTTP on `addr`. With `auth` (`user:password`) clients must use basic
+/// authentication; with `read_only` pushes are refused.
+pub fn run(addr: &str, directory: &Path, auth: Option<&str>, read_only: bool) -> crate::Result<()> {
+    let server = Arc::new(Server {
+        root: directory
+            .canonicalize()
+            .context(format!("{} not found", directory.display()))?,
+        auth: auth.map(str::to_owned),
+        read_only,
+    });
+    let listener = TcpListener::bind(addr).context(format!("bind {addr}"))?;
+    eprintln!(
+        "Serving {} on http://{}",
+        server.root.display(),
+        listener.local_addr()?
+    );
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                log::warn!("accept: {err}");
+                continue;
+            }
+        };
+        let server = Arc::clone(&server);
+        std::thread::spawn(move || {
+            if let Err(err) = handle(&server, stream) {
+                log::warn!("{err:#}");
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn handle(server: &Server, mut stream: TcpStream) -> crate::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request = read_request(&mut reader, &mut stream)?;
+    log::info!("{} {}", request.method, request.path);
+
+    if let Some(auth) = &server.auth {
+        let credentials = request
+            .header("authorization")
+            .and_then(|value| value.strip_prefix("Basic "))
+            .and_then(|encoded| base64::decode(encoded.trim()).ok());
+        if credentials.as_deref() != Some(auth.as_bytes()) {
+            let challenge = ("WWW-Authenticate", "Basic realm=\"mygit\"".to_owned());
+            return respond(&mut stream, "401 Unauthorized", &[challenge], b"");
+        }
+    }
+
+    let (repo, service, advertise) = match (request.method.as_str(), &request.path) {
+        ("GET", path) if path.ends_with("/info/refs") => {
+            let service = request
+                .query
+                .split('&')
+                .find_map(|param| param.strip_prefix("service="));
+            let Some(service) = service else {
+                return respond(
+                    &mut stream,
+                    "403 Forbidden",
+                    &[],
+                    b"dumb http is not supported\n",
+                );
+            };
+            (&path[..path.len() - "/info/refs".len()], service, true)
+        }
+        ("POST", path) => match path.rsplit_once('/') {
+            Some((repo, service)) => (repo, service, false),
+            None => return respond(&mut stream, "404 Not Found", &[], b""),
+        },
+        _ => return respond(&mut stream, "404 Not Found", &[], b""),
+    };
+    let subcommand = match service {
+        "git-upload-pack" => "upload-pack",
+        "git-receive-pack" if server.read_only => {
+            return respond(
+                &mut stream,
+                "403 Forbidden",
+                &[],
+                b"repository is read-only\n",
+            );
+        }
+        "git-receive-pack" => "receive-pack",
+        _ => return respond(&mut stream, "404 Not Found", &[], b""),
+    };
+    let Some(repo) = resolve_repo(&server.root, repo) else {
+        return respond(&mut stream, "404 Not Found", &[], b"repository not found\n");
+    };
+
+    let protocol = request.header("git-protocol");
+    let mut body = Vec::new();
+    if advertise && !protocol.is_some_and(|protocol| protocol.contains("version=2")) {
+        let mut writer = PktLineWriter::new(&mut body);
+        writer.write_line(&format!("# service={service}"))?;
+        writer.write_flush()?;
+    }
+    let mut command = Command::new(std::env::current_exe()?);
+    command.arg(subcommand).arg("--stateless-rpc");
+    if advertise {
+        command.arg("--advertise-refs");
+    }
+    command.arg(&repo).env_remove("GIT_DIR");
+    match protocol {
+        Some(protocol) => command.env("GIT_PROTOCOL", protocol),
+        None => command.env_remove("GIT_PROTOCOL"),
+    };
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context(format!("spawn {subcommand}"))?;
+    // The request is read completely before any response is written, so
+    // there is no need to feed stdin from another thread.
+    let mut stdin = child.stdin.take().context("child stdin")?;
+    let input = if request.header("content-encoding") == Some("gzip") {
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&request.body[..])
+            .read_to_end(&mut decoded)
+            .context("decompress request body")?;
+        decoded
+    } else {
+        request.body
+    };
+    // The child may exit early on invalid input; its output says why.
+    let _ = stdin.write_all(&input);
+    drop(stdin);
+    child
+        .stdout
+        .take()
+        .context("child stdout")?
+        .read_to_end(&mut body)?;
+    let status = child.wait()?;
+    if !status.success() && body.is_empty() {
+        return respond(&mut stream, "500 Internal Server Error", &[], b"");
+    }
+
+    let content_type = if advertise {
+        format!("application/x-{service}-advertisement")
+    } else {
+        format!("application/x-{service}-result")
+    };
+    let headers = [
+        ("Content-Type", content_type),
+        ("Cache-Control", "no-cache".to_owned()),
+    ];
+    respond(&mut stream, "200 OK", &headers, &body)
+}
+
+/// Map the URL path of a repository to its git directory below `root`.
+/// Paths may not leave `root`.
+pub fn resolve_repo(root: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    find_git_dir(&root.join(relative)).ok()
+}
+
+fn read_request(reader: &mut impl BufRead, stream: &mut TcpStream) -> crate::Result<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line).context("read request line")?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        crate::bail!("invalid request line {line:?}");
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = Request {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        query: query.to_owned(),
+        headers: Vec::new(),
+        body: Vec::new(),
+    };
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).context("read header")?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .context(format!("invalid header {line:?}"))?;
+        request
+            .headers
+            .push((name.to_ascii_lowercase(), value.trim().to_owned()));
+    }
+
+    if request.header("expect") == Some("100-continue") {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+    }
+    if request.header("transfer-encoding") == Some("chunked") {
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).context("read chunk size")?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16).context("invalid chunk size")?;
+            if size == 0 {
+                // Skip trailers.
+                line.clear();
+                while reader.read_line(&mut line)? > 0 && !line.trim_end().is_empty() {
+                    line.clear();
+                }
+                break;
+            }
+            let start = request.body.len();
+            request.body.resize(start + size, 0);
+            reader.read_exact(&mut request.body[start..])?;
+            reader.read_line(&mut line)?;
+        }
+    } else if let Some(length) = request.header("content-length") {
+        let length = length.parse().context("invalid content length")?;
+        request.body.resize(length, 0);
+        reader.read_exact(&mut request.body)?;
+    }
+
+    Ok(request)
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    headers: &[(&str, String)],
+    body: &[u8],
+) -> crate::Result<()> {
+    let mut response = format!("HTTP/1.1 {status}\r\n");
+    for (name, value) in headers {
+        response.push_str(&format!("{name}: {value}\r\n"));
+    }
+    response.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    ));
+    stream.write_all(response.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    Ok(())
+}
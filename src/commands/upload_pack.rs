@@ -0,0 +1,740 @@
+use crate::common::{
+    enter_repository, read_tree, walk_commits, walk_tree, Commit, FileMode, Object, ObjectType,
+};
+use crate::error::Context;
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::pack;
+use crate::pktline::{self, Packet, PktLineReader, PktLineWriter};
+use crate::promisor::ObjectFilter;
+use crate::refs;
+use crate::transport::{self, Deepen, INFINITE_DEPTH};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+/// Serve the repository at `directory` to a fetching client over stdin and
+/// stdout. With `stateless_rpc` a single request is handled, as over HTTP;
+/// with `advertise_refs` only the advertisement is written. Protocol v2 is
+/// spoken if `GIT_PROTOCOL` asks for it.
+pub fn run(directory: &Path, stateless_rpc: bool, advertise_refs: bool) -> crate::Result<()> {
+    enter_repository(directory)?;
+    let mut reader = PktLineReader::new(std::io::stdin().lock());
+    let mut writer = PktLineWriter::new(std::io::stdout().lock());
+    let v2 = std::env::var("GIT_PROTOCOL")
+        .is_ok_and(|protocol| protocol.split(':').any(|param| param == "version=2"));
+    if v2 {
+        serve_v2(&mut reader, &mut writer, stateless_rpc, advertise_refs)
+    } else {
+        serve_v0(&mut reader, &mut writer, stateless_rpc, advertise_refs)
+    }
+}
+
+/// What the client asked for, collected from either protocol version.
+#[derive(Debug, Default)]
+struct Request {
+    wants: Vec<ObjectId>,
+    haves: Vec<ObjectId>,
+    /// Commits the client's history is cut off at.
+    client_shallow: HashSet<ObjectId>,
+    deepen: Deepen,
+    filter: Option<ObjectFilter>,
+    include_tag: bool,
+    no_progress: bool,
+    done: bool,
+}
+
+impl Request {
+    /// Parse a want, have, shallow, deepen or filter line, returning `false` for anything else.
+    fn parse_line(&mut self, line: &str) -> crate::Result<bool> {
+        let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "want" => {
+                let want = arg.split(' ').next().unwrap_or_default();
+                let hash = want.parse().map_err(|_| {
+                    crate::Error::Message(format!("upload-pack: not our ref {want}"))
+                })?;
+                self.wants.push(hash);
+            }
+            "have" => self.haves.push(arg.parse()?),
+            "shallow" => {
+                self.client_shallow.insert(arg.parse()?);
+            }
+            "deepen" => self.deepen.depth = Some(arg.parse().context("invalid deepen")?),
+            "deepen-since" => {
+                self.deepen.since = Some(arg.parse().context("invalid deepen-since")?)
+            }
+            "deepen-not" => self.deepen.not.push(arg.to_owned()),
+            "deepen-relative" => self.deepen.relative = true,
+            "filter" => self.filter = Some(ObjectFilter::parse(arg)?),
+            "done" => self.done = true,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+fn serve_v0(
+    reader: &mut PktLineReader<impl Read>,
+    writer: &mut PktLineWriter<impl Write>,
+    stateless_rpc: bool,
+    advertise_refs: bool,
+) -> crate::Result<()> {
+    if !stateless_rpc || advertise_refs {
+        let mut advertised = Vec::new();
+        if let Some(head) = refs::read_ref("HEAD")? {
+            advertised.push(("HEAD".to_owned(), head));
+        }
+        for (name, hash) in refs::list_refs()? {
+            let peeled = refs::peel_tag(&hash)?;
+            advertised.push((name.clone(), hash));
+            if let Some(peeled) = peeled {
+                advertised.push((format!("{name}^{{}}"), peeled));
+            }
+        }
+        let mut capabilities: Vec<String> = [
+            "multi_ack_detailed",
+            "side-band-64k",
+            "ofs-delta",
+            "shallow",
+            "deepen-since",
+            "deepen-not",
+            "deepen-relative",
+            "filter",
+            // Objects reachable from a ref may be wanted, e.g. by a partial
+            // clone fetching missing objects; see `check_wants`.
+            "allow-tip-sha1-in-want",
+            "allow-reachable-sha1-in-want",
+            "no-progress",
+            "include-tag",
+        ]
+        .map(str::to_owned)
+        .to_vec();
+        if let Some(target) = refs::read_symref("HEAD")? {
+            capabilities.push(format!("symref=HEAD:{target}"));
+        }
+        capabilities.push(format!("object-format={}", ObjectFormat::current()?));
+        capabilities.push("agent=mygit/0.1.0".to_owned());
+        transport::write_advertisement(writer, &advertised, &capabilities)?;
+        writer.get_mut().flush()?;
+        if advertise_refs {
+            return Ok(());
+        }
+    }
+
+    let mut request = Request::default();
+    let (lines, _) = reader.read_lines()?;
+    if lines.is_empty() {
+        // The client is up to date.
+        return Ok(());
+    }
+    let mut capabilities = Vec::new();
+    for line in &lines {
+        if let Some(caps) = line
+            .strip_prefix("want ")
+            .and_then(|want| want.split_once(' '))
+        {
+            capabilities.extend(caps.1.split(' ').map(str::to_owned));
+        }
+        if let Err(err) = request.parse_line(line) {
+            writer.write_line(&format!("ERR {err}"))?;
+            return Err(err);
+        }
+    }
+    if let Err(err) = check_wants(&request.wants).and_then(|()| check_object_format(&capabilities))
+    {
+        writer.write_line(&format!("ERR {err}"))?;
+        return Err(err);
+    }
+    let has = |capability: &str| capabilities.iter().any(|c| c == capability);
+    request.include_tag = has("include-tag");
+    request.no_progress = has("no-progress");
+    request.deepen.relative = has("deepen-relative");
+    let multi_ack = has("multi_ack_detailed");
+    let sideband = has("side-band-64k");
+
+    let shallow = ShallowInfo::compute(&request)?;
+    if !request.deepen.is_empty() {
+        shallow.write(writer)?;
+        writer.write_flush()?;
+        writer.get_mut().flush()?;
+    }
+
+    let mut negotiation = Negotiation::new(&request.wants);
+    loop {
+        let Some(packet) = reader.read_packet()? else {
+            // The client hung up.
+            return Ok(());
+        };
+        match packet {
+            Packet::Flush => {
+                if negotiation.common.is_empty() || multi_ack {
+                    let ready = multi_ack && negotiation.is_ready()?;
+                    if let (true, Some(last)) = (ready, negotiation.common.last()) {
+                        writer.write_line(&format!("ACK {last} ready"))?;
+                    }
+                    writer.write_line("NAK")?;
+                }
+                writer.get_mut().flush()?;
+                if stateless_rpc {
+                    return Ok(());
+                }
+            }
+            packet => {
+                let line = packet.as_line().context("invalid have line")?.to_owned();
+                if line == "done" {
+                    break;
+                }
+                let have: ObjectId = line
+                    .strip_prefix("have ")
+                    .context("expected have line")?
+                    .parse()?;
+                let first = negotiation.common.is_empty();
+                if negotiation.add_have(&have)? {
+                    if multi_ack {
+                        writer.write_line(&format!("ACK {have} common"))?;
+                    } else if first {
+                        writer.write_line(&format!("ACK {have}"))?;
+                    }
+                }
+            }
+        }
+    }
+    match negotiation.common.last() {
+        Some(last) if multi_ack => writer.write_line(&format!("ACK {last}"))?,
+        Some(_) => (),
+        None => writer.write_line("NAK")?,
+    }
+
+    request.haves = negotiation.common;
+    send_pack(writer, &request, &shallow, sideband)
+}
+
+fn serve_v2(
+    reader: &mut PktLineReader<impl Read>,
+    writer: &mut PktLineWriter<impl Write>,
+    stateless_rpc: bool,
+    advertise_refs: bool,
+) -> crate::Result<()> {
+    if !stateless_rpc || advertise_refs {
+        for line in [
+            "version 2",
+            "agent=mygit/0.1.0",
+            "ls-refs",
+            "fetch=shallow filter",
+            &format!("object-format={}", ObjectFormat::current()?),
+        ] {
+            writer.write_line(line)?;
+        }
+        writer.write_flush()?;
+        writer.get_mut().flush()?;
+        if advertise_refs {
+            return Ok(());
+        }
+    }
+
+    loop {
+        let (lines, end) = reader.read_lines()?;
+        let Some(command) = lines.iter().find_map(|line| line.strip_prefix("command=")) else {
+            // End of the session.
+            return Ok(());
+        };
+        let args = if end == Some(Packet::Delimiter) {
+            reader.read_lines()?.0
+        } else {
+            Vec::new()
+        };
+        if let Err(err) = check_object_format(&lines) {
+            writer.write_line(&format!("ERR {err}"))?;
+            return Err(err);
+        }
+        match command {
+            "ls-refs" => ls_refs(writer, &args)?,
+            "fetch" => fetch(writer, &args)?,
+            command => {
+                writer.write_line(&format!("ERR unknown command {command}"))?;
+                crate::bail!(Protocol, "unknown command {command}");
+            }
+        }
+        writer.get_mut().flush()?;
+        if stateless_rpc {
+            return Ok(());
+        }
+    }
+}
+
+/// Protocol v2 `ls-refs`.
+fn ls_refs(writer: &mut PktLineWriter<impl Write>, args: &[String]) -> crate::Result<()> {
+    let peel = args.iter().any(|arg| arg == "peel");
+    let symrefs = args.iter().any(|arg| arg == "symrefs");
+    let prefixes: Vec<&str> = args
+        .iter()
+        .filter_map(|arg| arg.strip_prefix("ref-prefix "))
+        .collect();
+
+    let mut all = Vec::new();
+    if let Some(head) = refs::read_ref("HEAD")? {
+        all.push(("HEAD".to_owned(), head));
+    }
+    all.extend(refs::list_refs()?);
+    for (name, hash) in all {
+        if !prefixes.is_empty() && !prefixes.iter().any(|prefix| name.starts_with(prefix)) {
+            continue;
+        }
+        let mut line = format!("{hash} {name}");
+        if symrefs {
+            if let Some(target) = refs::read_symref(&name)? {
+                line.push_str(&format!(" symref-target:{target}"));
+            }
+        }
+        if peel {
+            if let Some(peeled) = refs::peel_tag(&hash)? {
+                line.push_str(&format!(" peeled:{peeled}"));
+            }
+        }
+        writer.write_line(&line)?;
+    }
+    writer.write_flush()
+}
+
+/// Protocol v2 `fetch`.
+fn fetch(writer: &mut PktLineWriter<impl Write>, args: &[String]) -> crate::Result<()> {
+    let mut request = Request::default();
+    for arg in args {
+        match arg.as_str() {
+            "include-tag" => request.include_tag = true,
+            "no-progress" => request.no_progress = true,
+            arg => {
+                if let Err(err) = request.parse_line(arg) {
+                    writer.write_line(&format!("ERR {err}"))?;
+                    return Err(err);
+                }
+            }
+        }
+    }
+    if let Err(err) = check_wants(&request.wants) {
+        writer.write_line(&format!("ERR {err}"))?;
+        return Err(err);
+    }
+
+    let mut negotiation = Negotiation::new(&request.wants);
+    for have in &request.haves {
+        negotiation.add_have(have)?;
+    }
+    if !request.done {
+        writer.write_line("acknowledgments")?;
+        if negotiation.common.is_empty() {
+            writer.write_line("NAK")?;
+        }
+        for common in &negotiation.common {
+            writer.write_line(&format!("ACK {common}"))?;
+        }
+        if !negotiation.is_ready()? {
+            return writer.write_flush();
+        }
+        writer.write_line("ready")?;
+        writer.write_delimiter()?;
+    }
+
+    let shallow = ShallowInfo::compute(&request)?;
+    if !request.deepen.is_empty() {
+        writer.write_line("shallow-info")?;
+        shallow.write(writer)?;
+        writer.write_delimiter()?;
+    }
+    writer.write_line("packfile")?;
+    request.haves = negotiation.common;
+    send_pack(writer, &request, &shallow, true)
+}
+
+/// Tracks which of the client's haves we have too.
+struct Negotiation {
+    wants: Vec<ObjectId>,
+    common: Vec<ObjectId>,
+    /// Commits reachable from each want, computed when first needed.
+    want_ancestors: Option<Vec<HashSet<ObjectId>>>,
+}
+
+impl Negotiation {
+    fn new(wants: &[ObjectId]) -> Negotiation {
+        Negotiation {
+            wants: wants.to_vec(),
+            common: Vec::new(),
+            want_ancestors: None,
+        }
+    }
+
+    /// Record `have` if it is a commit we have. Returns whether it is common.
+    fn add_have(&mut self, have: &ObjectId) -> crate::Result<bool> {
+        if !Object::exists(have)? || Commit::read(have).is_err() {
+            return Ok(false);
+        }
+        if !self.common.contains(have) {
+            self.common.push(*have);
+        }
+        Ok(true)
+    }
+
+    /// Whether every want has a common commit in its history, so that
+    /// further haves would not make the pack much smaller.
+    fn is_ready(&mut self) -> crate::Result<bool> {
+        if self.common.is_empty() {
+            return Ok(false);
+        }
+        if self.want_ancestors.is_none() {
+            let mut all = Vec::new();
+            for want in &self.wants {
+                let mut ancestors = HashSet::new();
+                let mut pending = vec![peel_to_commit(want)?];
+                while let Some(hash) = pending.pop() {
+                    if ancestors.insert(hash) {
+                        pending.extend(Commit::read(&hash)?.parents);
+                    }
+                }
+                all.push(ancestors);
+            }
+            self.want_ancestors = Some(all);
+        }
+        let ancestors = self.want_ancestors.as_ref().unwrap();
+
+        Ok(ancestors
+            .iter()
+            .all(|ancestors| self.common.iter().any(|c| ancestors.contains(c))))
+    }
+}
+
+/// Shallow boundary for a deepening fetch.
+#[derive(Debug, Default)]
+struct ShallowInfo {
+    /// Commits whose parents are not sent.
+    shallow: HashSet<ObjectId>,
+    /// Commits the client had as shallow whose parents are now sent.
+    unshallow: HashSet<ObjectId>,
+}
+
+impl ShallowInfo {
+    fn compute(request: &Request) -> crate::Result<ShallowInfo> {
+        let mut info = ShallowInfo::default();
+        let deepen = &request.deepen;
+        if deepen.is_empty() {
+            info.shallow = request.client_shallow.clone();
+            return Ok(info);
+        }
+
+        let mut excluded = HashSet::new();
+        for name in &deepen.not {
+            let hash = resolve_ref(name)?.context(format!("deepen-not {name} is not a ref"))?;
+            let mut pending = vec![peel_to_commit(&hash)?];
+            while let Some(hash) = pending.pop() {
+                if excluded.insert(hash) {
+                    pending.extend(Commit::read(&hash)?.parents);
+                }
+            }
+        }
+        // A relative depth counts from the client's shallow commits.
+        let (starts, depth) = if deepen.relative {
+            let depth = deepen.depth.unwrap_or(INFINITE_DEPTH);
+            let starts = request.client_shallow.iter().copied().collect();
+            (starts, depth.saturating_add(1))
+        } else {
+            let starts = request
+                .wants
+                .iter()
+                .map(peel_to_commit)
+                .collect::<crate::Result<Vec<_>>>()?;
+            (starts, deepen.depth.unwrap_or(INFINITE_DEPTH))
+        };
+
+        // Breadth-first so each commit is first seen at its smallest depth.
+        let mut pending: VecDeque<(ObjectId, usize)> =
+            starts.into_iter().map(|hash| (hash, 1)).collect();
+        let mut depths: HashMap<ObjectId, usize> = HashMap::new();
+        while let Some((hash, commit_depth)) = pending.pop_front() {
+            if depths.contains_key(&hash) {
+                continue;
+            }
+            depths.insert(hash, commit_depth);
+            let commit = Commit::read(&hash)?;
+            let mut cut = commit_depth >= depth;
+            for parent in &commit.parents {
+                if excluded.contains(parent)
+                    || deepen
+                        .since
+                        .is_some_and(|since| Commit::read(parent).is_ok_and(|p| p.time() < since))
+                {
+                    cut = true;
+                }
+            }
+            if cut {
+                if !commit.parents.is_empty() {
+                    info.shallow.insert(hash);
+                }
+                continue;
+            }
+            if request.client_shallow.contains(&hash) {
+                info.unshallow.insert(hash);
+            }
+            pending.extend(commit.parents.into_iter().map(|p| (p, commit_depth + 1)));
+        }
+        // The client keeps its other shallow commits.
+        for hash in &request.client_shallow {
+            if !depths.contains_key(hash) {
+                info.shallow.insert(*hash);
+            }
+        }
+
+        Ok(info)
+    }
+
+    /// Write the `shallow`/`unshallow` lines for commits the client does not
+    /// already know to be shallow.
+    fn write(&self, writer: &mut PktLineWriter<impl Write>) -> crate::Result<()> {
+        let mut shallow: Vec<&ObjectId> = self.shallow.iter().collect();
+        shallow.sort();
+        for hash in shallow {
+            writer.write_line(&format!("shallow {hash}"))?;
+        }
+        let mut unshallow: Vec<&ObjectId> = self.unshallow.iter().collect();
+        unshallow.sort();
+        for hash in unshallow {
+            writer.write_line(&format!("unshallow {hash}"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Fail if the client asked for an object format other than the repository's.
+fn check_object_format(capabilities: &[String]) -> crate::Result<()> {
+    // git only sends the format with protocol v2.
+    if capabilities.iter().any(|c| c.starts_with("object-format=")) {
+        let format = transport::object_format(capabilities)?;
+        let current = ObjectFormat::current()?;
+        crate::ensure!(
+            format == current,
+            Protocol,
+            "mismatched object format: the client uses {format}, the repository {current}"
+        );
+    }
+
+    Ok(())
+}
+
+/// Resolve a branch or tag name given to `deepen-not`.
+fn resolve_ref(name: &str) -> crate::Result<Option<ObjectId>> {
+    for candidate in [
+        name.to_owned(),
+        format!("refs/heads/{name}"),
+        format!("refs/tags/{name}"),
+    ] {
+        if candidate.starts_with("refs/") {
+            if let Some(hash) = refs::read_ref(&candidate)? {
+                return Ok(Some(hash));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// If `hash` is an annotated tag, the commit it points to.
+fn peel_to_commit(hash: &ObjectId) -> crate::Result<ObjectId> {
+    Ok(refs::peel_tag(hash)?.unwrap_or(*hash))
+}
+
+/// Fail unless every want is an advertised ref or reachable from one, as
+/// `allow-reachable-sha1-in-want` allows, so that objects no ref leads to
+/// cannot be fetched by id. Only object ids are kept while walking.
+fn check_wants(wants: &[ObjectId]) -> crate::Result<()> {
+    let mut tips = Vec::new();
+    if let Some(head) = refs::read_ref("HEAD")? {
+        tips.push(head);
+    }
+    for (_, hash) in refs::list_refs()? {
+        tips.push(hash);
+        tips.extend(refs::peel_tag(&hash)?);
+    }
+    let mut unmatched: HashSet<ObjectId> = wants
+        .iter()
+        .filter(|want| !tips.contains(want))
+        .copied()
+        .collect();
+
+    // Blobs are known from their tree entries and never read.
+    let mut pending: Vec<(ObjectId, Option<ObjectType>)> =
+        tips.into_iter().map(|hash| (hash, None)).collect();
+    let mut seen = HashSet::new();
+    while !unmatched.is_empty() {
+        let Some((hash, ty)) = pending.pop() else {
+            break;
+        };
+        if !seen.insert(hash) {
+            continue;
+        }
+        unmatched.remove(&hash);
+        let ty = match ty {
+            Some(ty) => ty,
+            None => Object::read_header(&hash)?.0,
+        };
+        match ty {
+            ObjectType::Commit => {
+                let commit = Commit::read(&hash)?;
+                pending.push((commit.tree, Some(ObjectType::Tree)));
+                pending.extend(commit.parents.into_iter().map(|parent| (parent, None)));
+            }
+            ObjectType::Tree => pending.extend(
+                read_tree(&hash)?
+                    .into_iter()
+                    .filter(|entry| entry.mode != FileMode::Gitlink)
+                    .map(|entry| (entry.hash, Some(entry.ty))),
+            ),
+            ObjectType::Tag => {
+                let target = Object::read(&hash)?.references()?;
+                pending.extend(target.into_iter().map(|target| (target, None)));
+            }
+            ObjectType::Blob => (),
+        }
+    }
+    if let Some(want) = unmatched.into_iter().next() {
+        crate::bail!("upload-pack: not our ref {want}");
+    }
+
+    Ok(())
+}
+
+/// The objects to send for `request`, as ids in the order to send them.
+/// Commits are only walked down to the ones the client has, and of their
+/// objects only those in the trees of the ones the new commits build on are
+/// left out, rather than everything the client has.
+fn select_objects(request: &Request, shallow: &ShallowInfo) -> crate::Result<Vec<ObjectId>> {
+    // The client already has unshallowed commits but not their parents.
+    let mut tips = request.wants.clone();
+    for hash in &shallow.unshallow {
+        tips.extend(Commit::read(hash)?.parents);
+    }
+    let cut: HashSet<ObjectId> = shallow
+        .shallow
+        .union(&request.client_shallow)
+        .copied()
+        .collect();
+    let (new_commits, common_commits, boundary) = walk_commits(&tips, &request.haves, &cut)?;
+
+    let mut seen = common_commits;
+    seen.extend(&request.haves);
+    for commit in &boundary {
+        let tree = Commit::read(commit)?.tree;
+        if seen.insert(tree) {
+            walk_tree(&tree, "", &mut seen, &mut |_, _| Ok(true))?;
+        }
+    }
+
+    let filter = request.filter.as_ref();
+    let mut hashes = Vec::new();
+    for commit in new_commits {
+        seen.insert(commit);
+        hashes.push(commit);
+        let tree = Commit::read(&commit)?.tree;
+        add_tree(tree, filter, false, &mut seen, &mut hashes)?;
+    }
+    // Wanted objects other than commits, and the tags to include.
+    let mut others = request.wants.clone();
+    if request.include_tag {
+        let sent: HashSet<ObjectId> = hashes.iter().copied().collect();
+        for (_, hash) in refs::list_refs()? {
+            if refs::peel_tag(&hash)?.is_some_and(|peeled| sent.contains(&peeled)) {
+                others.push(hash);
+            }
+        }
+    }
+    for mut hash in others {
+        while !hash.is_null() && !seen.contains(&hash) {
+            match Object::read_header(&hash)?.0 {
+                ObjectType::Tree => {
+                    add_tree(hash, filter, true, &mut seen, &mut hashes)?;
+                    break;
+                }
+                ObjectType::Tag => {
+                    let target = Object::read(&hash)?.references()?.into_iter().next();
+                    seen.insert(hash);
+                    hashes.push(hash);
+                    match target {
+                        Some(target) => hash = target,
+                        None => break,
+                    }
+                }
+                ObjectType::Blob | ObjectType::Commit => {
+                    seen.insert(hash);
+                    hashes.push(hash);
+                }
+            }
+        }
+    }
+
+    Ok(hashes)
+}
+
+/// Add the tree `tree` and the objects below it not in `seen` to `hashes`,
+/// leaving out those `filter` rejects. The tree itself is added regardless
+/// of the filter if `wanted`.
+fn add_tree(
+    tree: ObjectId,
+    filter: Option<&ObjectFilter>,
+    wanted: bool,
+    seen: &mut HashSet<ObjectId>,
+    hashes: &mut Vec<ObjectId>,
+) -> crate::Result<()> {
+    if !seen.insert(tree) {
+        return Ok(());
+    }
+    if !wanted && filter.is_some_and(|filter| !filter.includes(ObjectType::Tree, 0, 0)) {
+        return Ok(());
+    }
+    hashes.push(tree);
+    walk_tree(&tree, "", seen, &mut |path, entry| {
+        if let Some(filter) = filter {
+            // Entries of the root tree are one level below it.
+            let depth = path.matches('/').count() + 1;
+            let size = match entry.ty {
+                ObjectType::Blob => Object::read_header(&entry.hash)?.1,
+                _ => 0,
+            };
+            if !filter.includes(entry.ty, size, depth) {
+                return Ok(false);
+            }
+        }
+        hashes.push(entry.hash);
+        Ok(true)
+    })
+}
+
+/// Send the pack for `request`, on side-band 1 with progress on band 2 if `sideband`.
+fn send_pack(
+    writer: &mut PktLineWriter<impl Write>,
+    request: &Request,
+    shallow: &ShallowInfo,
+    sideband: bool,
+) -> crate::Result<()> {
+    let hashes = select_objects(request, shallow)?;
+    let progress = sideband && !request.no_progress;
+    if progress {
+        let message = format!("Enumerating objects: {}, done.\n", hashes.len());
+        writer.write_band(2, message.as_bytes())?;
+    }
+
+    if sideband {
+        let mut band = BufWriter::with_capacity(pktline::MAX_DATA_LEN - 1, writer.band(1));
+        pack::write_pack_stream(&hashes, &mut band)?;
+        band.flush()?;
+        drop(band);
+        if progress {
+            let message = format!("Total {} (delta 0), reused 0 (delta 0)\n", hashes.len());
+            writer.write_band(2, message.as_bytes())?;
+        }
+        writer.write_flush()?;
+    } else {
+        pack::write_pack_stream(&hashes, writer.get_mut())?;
+    }
+    writer.get_mut().flush()?;
+
+    Ok(())
+}
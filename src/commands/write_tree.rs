@@ -1,127 +1,117 @@
-use crate::commands::hash_object;
-use crate::common::{FileMode, Object, ObjectType, TreeEntry};
-use anyhow::Context;
-use std::{fs::DirEntry, os::unix::fs::PermissionsExt, path::Path};
+use crate::common::{write_tree, FileMode, Object, ObjectType, TreeEntry};
+use crate::error::Context;
+use crate::object_id::ObjectId;
+use crate::object_store::{self, ObjectStore};
+use crate::refs;
+use std::{
+    fs::{self, DirEntry, File},
+    io::BufReader,
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    path::Path,
+};
 
-/// Write tree object for current directory and return its hash.
-pub fn run() -> anyhow::Result<String> {
-    let contents = compute_tree_contents(".")?;
-    let tree_object = Object {
-        ty: ObjectType::Tree,
-        contents,
-    };
-    let hash = tree_object.write_to_objects_store()?;
-
-    Ok(hash)
+/// Write the tree of the current directory, with the blobs and subtrees it
+/// refers to, to the object store and return its hash.
+pub fn run() -> crate::Result<ObjectId> {
+    write_dir(".", &object_store::repository()?)
 }
 
-/// Compute the tree entries for all files in `dir`.
-fn compute_tree_contents(dir: impl AsRef<Path>) -> anyhow::Result<Vec<u8>> {
+/// Store the files below `dir` and the trees holding them in `store` and
+/// return the hash of the tree of `dir`.
+pub fn write_dir(dir: impl AsRef<Path>, store: &dyn ObjectStore) -> crate::Result<ObjectId> {
     let mut tree_entries = Vec::new();
+    for file in fs::read_dir(&dir)? {
+        let file = file?;
+        let file_mode = file_mode(&file)?;
+        let file_name = file.file_name();
+        let name = file_name
+            .to_str()
+            .context(format!("file name {file_name:?} is not valid utf-8"))?;
+        // Not canonicalized, which would follow symlinks.
+        let path = dir.as_ref().join(&file_name);
 
-    // Get Vec of sorted files in directory
-    let mut files = std::fs::read_dir(&dir)?
-        .into_iter()
-        .collect::<Result<Vec<DirEntry>, _>>()?;
-    files.sort_by(|f1, f2| {
-        // https://github.com/git/git/blob/11c821f2f2a31e70fb5cc449f9a29401c333aad2/tree.c#L99
-        let name1 = f1.file_name();
-        let name1 = name1.as_encoded_bytes();
-        let ty1 = f1.file_type().expect("file type");
-        let name2 = f2.file_name();
-        let name2 = name2.as_encoded_bytes();
-        let ty2 = f2.file_type().expect("file type");
-
-        let min_len = name1.len().min(name2.len());
-
-        match name1[..min_len].cmp(&name2[..min_len]) {
-            std::cmp::Ordering::Equal => (),
-            ord => return ord,
-        };
-
-        let c1 = match name1.get(min_len).copied() {
-            Some(c) => Some(c),
-            _ if ty1.is_dir() => Some(b'/'),
-            _ => None,
-        };
-        let c2 = match name2.get(min_len).copied() {
-            Some(c) => Some(c),
-            _ if ty2.is_dir() => Some(b'/'),
-            _ => None,
+        let hash = match file_mode {
+            FileMode::RegularFile | FileMode::ExecutableFile => {
+                // Stream the file contents into the store.
+                let reader = File::open(&path).context(format!("open {path:?}"))?;
+                let size = reader.metadata()?.len();
+                store.write_stream(ObjectType::Blob, size, &mut BufReader::new(reader))?
+            }
+            FileMode::Symlink => {
+                // The blob of a symlink holds its target.
+                let target = fs::read_link(&path).context(format!("read link {path:?}"))?;
+                store.write(&Object {
+                    ty: ObjectType::Blob,
+                    contents: target.as_os_str().as_bytes().to_vec(),
+                })?
+            }
+            FileMode::Gitlink => submodule_head(&path)?,
+            // Ignore `.git` and files in `.gitignore`.
+            FileMode::Directory
+                if path.to_string_lossy().ends_with(".git")
+                    || path.to_string_lossy().ends_with("target") =>
+            {
+                continue;
+            }
+            // Recursively write subtrees.
+            FileMode::Directory => write_dir(&path, store)?,
         };
+        tree_entries.push(TreeEntry {
+            mode: file_mode,
+            ty: ObjectType::from(file_mode),
+            hash,
+            file: name.to_owned(),
+        });
+    }
 
-        c1.cmp(&c2)
-    });
+    write_tree(store, tree_entries)
+}
 
-    // Compute tree entry for each file
-    for file in files {
-        let file_name = file.file_name();
-        let file_name_abs = dir
-            .as_ref()
-            .to_path_buf()
-            .join(&file_name)
-            .canonicalize()
-            .context(format!("get full path for {:?}", file_name))?;
-        let file_type = file.file_type().unwrap();
+/// The mode `file` is stored with: directories holding a repository of
+/// their own are submodules, and symlinks are stored as links rather than
+/// followed.
+fn file_mode(file: &DirEntry) -> crate::Result<FileMode> {
+    let file_type = file.file_type()?;
+    let mode = if file_type.is_symlink() {
+        FileMode::Symlink
+    } else if file_type.is_dir() {
+        if file.path().join(".git").exists() {
+            FileMode::Gitlink
+        } else {
+            FileMode::Directory
+        }
+    } else {
         let is_exec = file
             .metadata()
-            .context(format!("get metadata for {:?}", file_name))?
+            .context(format!("get metadata for {:?}", file.file_name()))?
             .permissions()
             .mode()
             & 0o111
             != 0;
-        let file_mode = if file_type.is_file() {
-            if is_exec {
-                FileMode::ExecutableFile
-            } else {
-                FileMode::RegularFile
-            }
+        if is_exec {
+            FileMode::ExecutableFile
         } else {
-            FileMode::Directory
-        };
-
-        if file_mode != FileMode::Directory {
-            // Blob.
-            // Hash file contents.
-            let hash = hash_object::run(false, &file_name_abs).unwrap();
-            let entry = TreeEntry {
-                mode: file_mode,
-                ty: ObjectType::from(file_mode),
-                hash,
-                file: file_name.to_str().unwrap().to_owned(),
-            };
-            tree_entries.push(entry);
-        } else if !file_name_abs.as_path().to_str().unwrap().ends_with(".git")
-            && !file_name_abs
-                .as_path()
-                .to_str()
-                .unwrap()
-                .ends_with("target")
-        {
-            // Tree.
-            // Ignore `.git` and files in `.gitignore`.
-            // Recursively compute tree entries.
-            let contents = compute_tree_contents(file_name_abs)?;
-            let tree_object = Object {
-                ty: ObjectType::Tree,
-                contents,
-            };
-            let hash = tree_object.write(std::io::sink())?;
-            tree_entries.push(TreeEntry {
-                mode: FileMode::Directory,
-                ty: ObjectType::Tree,
-                hash,
-                file: file_name.to_str().unwrap().to_owned(),
-            })
+            FileMode::RegularFile
         }
-    }
+    };
 
-    let mut buf = Vec::new();
-    for tree_entry in &tree_entries {
-        tree_entry
-            .write(&mut buf)
-            .context("write tree entry into buffer")?;
-    }
+    Ok(mode)
+}
+
+/// The commit checked out in the submodule at `path`, whose `.git` is
+/// either its git directory or a file pointing to it.
+fn submodule_head(path: &Path) -> crate::Result<ObjectId> {
+    let dot_git = path.join(".git");
+    let git_dir = if dot_git.is_file() {
+        let contents = fs::read_to_string(&dot_git).context(format!("read {dot_git:?}"))?;
+        let target = contents
+            .trim_end()
+            .strip_prefix("gitdir: ")
+            .context(format!("invalid gitfile {dot_git:?}"))?;
+        path.join(target)
+    } else {
+        dot_git
+    };
 
-    Ok(buf)
+    refs::read_ref_in(&git_dir, "HEAD")?.context(format!("submodule {path:?} has no commit"))
 }
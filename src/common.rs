@@ -1,94 +1,537 @@
-use anyhow::Context;
+use crate::error::{Context, Error};
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::object_store::{FileStore, ObjectStore};
+use crate::shallow;
 use nom::{bytes::complete::tag, IResult};
-use sha1::{Digest, Sha1};
 use std::{
+    collections::{BinaryHeap, HashMap, HashSet},
     ffi::CStr,
     fmt::Display,
     fs,
     io::{BufRead, BufReader, Cursor, Read, Write},
     path::{Path, PathBuf},
+    sync::OnceLock,
 };
 
+#[derive(Debug, Clone)]
 pub struct Object {
     pub ty: ObjectType,
     pub contents: Vec<u8>,
 }
 
 impl Object {
-    pub fn read(object_hash: &str) -> anyhow::Result<Object> {
-        let object_dir = object_hash
-            .get(..2)
-            .ok_or(anyhow::anyhow!("invalid object hash"))?;
-        let object_file = &object_hash[2..];
-        let object_path = format!(".git/objects/{}/{}", object_dir, object_file);
-        let file_contents = std::fs::read(object_path).context("read object file")?;
-        let decoded = zlib_decode(&file_contents);
-        let mut buf_reader = BufReader::new(Cursor::new(decoded));
-        let mut buf = Vec::new();
-        buf_reader.read_until(0, &mut buf).context("read header")?;
-        let header = CStr::from_bytes_with_nul(&buf).context("should end with a nul byte")?;
-        let header = header.to_str().context("header should be valid utf-8")?;
-        let (ty, size) = header
-            .split_once(' ')
-            .context("object type and size should be separated by a space")?;
-        let ty = ObjectType::try_from(ty).map_err(|err| anyhow::anyhow!(err))?;
-        let size: usize = size
-            .parse()
-            .context("expected object size to be decimal encoded")?;
-        let mut contents = vec![0; size];
-        buf_reader
-            .read_exact(&mut contents)
-            .context(format!("could not read {size} bytes"))?;
-        let object = Object { ty, contents };
+    /// Read the object identified by `object_hash` from the loose objects or packs
+    /// and check that its contents hash to `object_hash`.
+    pub fn read(object_hash: &ObjectId) -> crate::Result<Object> {
+        let store = crate::object_store::repository()?;
+        let object = match store.read(object_hash)? {
+            Some(object) => object,
+            // A partial clone fetches objects it left out on first use.
+            None if crate::promisor::lazy_fetch(object_hash)? => {
+                store.read(object_hash)?.ok_or_else(|| {
+                    Error::Message(format!("promisor remote did not send {object_hash}"))
+                })?
+            }
+            None => return Err(Error::ObjectNotFound(object_hash.to_string())),
+        };
+        let hash = object.write(std::io::sink())?;
+        crate::ensure!(
+            hash == *object_hash,
+            CorruptObject,
+            "hash mismatch: object {object_hash} hashes to {hash}"
+        );
 
         Ok(object)
     }
 
-    pub fn write(self, mut writer: impl std::io::Write) -> anyhow::Result<String> {
-        let size = self.contents.len().to_string();
+    /// Read the loose object stored at `object_path` without verifying its hash.
+    pub fn read_loose(object_path: impl AsRef<Path>) -> crate::Result<Object> {
+        let (ty, size, mut reader) = inflate_loose(object_path.as_ref())?;
+        let mut contents = Vec::new();
+        reader
+            .read_to_end(&mut contents)
+            .map_err(|_| corrupt(format!("could not read {size} bytes")))?;
+        crate::ensure!(
+            contents.len() as u64 == size,
+            CorruptObject,
+            "could not read {size} bytes"
+        );
+
+        Ok(Object { ty, contents })
+    }
+
+    /// Open the object identified by `object_hash` for reading its contents.
+    /// A loose object is inflated as it is read, so even a huge blob takes
+    /// constant memory; its hash is checked once it has been read to the end.
+    /// Packed objects are read into memory.
+    pub fn open(object_hash: &ObjectId) -> crate::Result<ObjectReader> {
+        let object_path = crate::object_store::object_dirs()?
+            .into_iter()
+            .map(|dir| FileStore::new(dir, object_hash.format()).loose_path(object_hash))
+            .find(|path| path.exists());
+        let Some(object_path) = object_path else {
+            let object = Object::read(object_hash)?;
+            return Ok(ObjectReader {
+                ty: object.ty,
+                size: object.contents.len() as u64,
+                remaining: object.contents.len() as u64,
+                contents: Box::new(Cursor::new(object.contents)),
+                verify: None,
+            });
+        };
+        let (ty, size, contents) = inflate_loose(&object_path)?;
+        let mut hasher = object_hash.format().hasher();
+        hasher.write_all(&header(ty, size))?;
+
+        Ok(ObjectReader {
+            ty,
+            size,
+            remaining: size,
+            contents: Box::new(contents),
+            verify: Some((hasher, *object_hash)),
+        })
+    }
+
+    /// Type and size of the object identified by `object_hash`, without
+    /// inflating the contents of a loose object.
+    pub fn read_header(object_hash: &ObjectId) -> crate::Result<(ObjectType, u64)> {
+        match crate::object_store::repository()?.read_header(object_hash)? {
+            Some(header) => Ok(header),
+            // Missing objects are lazily fetched or reported by `read`.
+            None => {
+                let object = Object::read(object_hash)?;
+                Ok((object.ty, object.contents.len() as u64))
+            }
+        }
+    }
+
+    /// Whether the object identified by `object_hash` is stored loose or in a pack.
+    pub fn exists(object_hash: &ObjectId) -> crate::Result<bool> {
+        crate::object_store::repository()?.exists(object_hash)
+    }
 
-        let mut buf = Vec::new();
-        buf.extend_from_slice(self.ty.to_string().as_bytes());
-        buf.extend(b" ");
-        buf.extend_from_slice(size.as_bytes());
-        buf.push(0);
+    pub fn write(&self, mut writer: impl std::io::Write) -> crate::Result<ObjectId> {
+        let mut buf = header(self.ty, self.contents.len() as u64);
         buf.extend_from_slice(&self.contents);
 
         writer.write_all(&buf).context("write object")?;
 
-        Ok(hash(&buf))
+        hash(&buf)
+    }
+
+    /// The hash of the object in a repository using `format`.
+    pub fn hash_with(&self, format: ObjectFormat) -> crate::Result<ObjectId> {
+        let mut hasher = format.hasher();
+        hasher.write_all(&header(self.ty, self.contents.len() as u64))?;
+        hasher.write_all(&self.contents)?;
+
+        Ok(hasher.finish())
+    }
+
+    pub fn write_to_objects_store(self) -> crate::Result<ObjectId> {
+        FileStore::current()?.write(&self)
+    }
+
+    /// Like [`Object::write`] for an object of type `ty` whose `size` bytes
+    /// of contents are read from `reader` instead of held in memory, hashed
+    /// with `format`.
+    pub fn stream(
+        format: ObjectFormat,
+        ty: ObjectType,
+        size: u64,
+        reader: impl Read,
+        mut writer: impl Write,
+    ) -> crate::Result<ObjectId> {
+        let header = header(ty, size);
+        let mut hasher = format.hasher();
+        hasher.write_all(&header)?;
+        writer.write_all(&header).context("write object")?;
+        let mut tee = Tee(&mut hasher, &mut writer);
+        let copied = std::io::copy(&mut reader.take(size), &mut tee).context("write object")?;
+        crate::ensure!(
+            copied == size,
+            "object contents ended after {copied} of {size} bytes"
+        );
+
+        Ok(hasher.finish())
+    }
+
+    /// Like [`Object::write_to_objects_store`] for an object streamed from
+    /// `reader`, see [`ObjectStore::write_stream`].
+    pub fn stream_to_objects_store(
+        ty: ObjectType,
+        size: u64,
+        mut reader: impl Read,
+    ) -> crate::Result<ObjectId> {
+        FileStore::current()?.write_stream(ty, size, &mut reader)
+    }
+
+    /// Hashes of the objects this object points to: tree entries,
+    /// a commit's tree and parents, or a tag's target. The commits of
+    /// submodules are in other repositories and not included.
+    pub fn references(&self) -> crate::Result<Vec<ObjectId>> {
+        match self.ty {
+            ObjectType::Blob => Ok(Vec::new()),
+            ObjectType::Tree => {
+                let entries = TreeEntry::parse_all(&self.contents, ObjectFormat::current()?)
+                    .ok_or_else(|| corrupt("invalid tree object"))?;
+                Ok(entries
+                    .into_iter()
+                    .filter(|entry| entry.mode != FileMode::Gitlink)
+                    .map(|entry| entry.hash)
+                    .collect())
+            }
+            ObjectType::Commit | ObjectType::Tag => {
+                let text = std::str::from_utf8(&self.contents)
+                    .map_err(|_| corrupt(format!("{} should be valid utf-8", self.ty)))?;
+                text.lines()
+                    .take_while(|line| !line.is_empty())
+                    .filter_map(|line| {
+                        let (key, value) = line.split_once(' ')?;
+                        matches!(key, "tree" | "parent" | "object").then_some(value)
+                    })
+                    .map(str::parse)
+                    .collect()
+            }
+        }
+    }
+}
+
+/// An object opened with [`Object::open`], whose contents are read through
+/// [`Read`].
+pub struct ObjectReader {
+    pub ty: ObjectType,
+    pub size: u64,
+    remaining: u64,
+    contents: Box<dyn Read>,
+    /// Hasher fed with the contents read so far and the expected hash,
+    /// until the end has been reached and checked.
+    verify: Option<(crate::object_id::Hasher, ObjectId)>,
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.contents.read(buf)?;
+        self.remaining -= len as u64;
+        if let Some((hasher, _)) = &mut self.verify {
+            hasher.write_all(&buf[..len])?;
+        }
+        if len == 0 && !buf.is_empty() {
+            let invalid = |message| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+            if self.remaining > 0 {
+                return Err(invalid(format!(
+                    "object ended {} bytes short of its size",
+                    self.remaining
+                )));
+            }
+            if let Some((hasher, expected)) = self.verify.take() {
+                let hash = hasher.finish();
+                if hash != expected {
+                    return Err(invalid(format!(
+                        "hash mismatch: object {expected} hashes to {hash}"
+                    )));
+                }
+            }
+        }
+
+        Ok(len)
+    }
+}
+
+/// Writes everything to both writers.
+struct Tee<A, B>(A, B);
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.1.write(buf)?;
+        self.0.write_all(&buf[..len])?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()?;
+        self.1.flush()
+    }
+}
+
+/// The `<type> <size>\0` header an object is hashed and stored with.
+fn header(ty: ObjectType, size: u64) -> Vec<u8> {
+    format!("{ty} {size}\0").into_bytes()
+}
+
+/// Open the loose object at `object_path` and parse its header, returning
+/// its type, size and a reader inflating the contents that follow.
+pub(crate) fn inflate_loose(object_path: &Path) -> crate::Result<(ObjectType, u64, impl Read)> {
+    let file = fs::File::open(object_path).context("read object file")?;
+    let mut reader = BufReader::new(flate2::bufread::ZlibDecoder::new(BufReader::new(file)));
+    let mut buf = Vec::new();
+    reader
+        .read_until(0, &mut buf)
+        .map_err(|_| corrupt(format!("{} is not zlib compressed", object_path.display())))?;
+    let header = CStr::from_bytes_with_nul(&buf)
+        .map_err(|_| corrupt("object header should end with a nul byte"))?;
+    let header = header
+        .to_str()
+        .map_err(|_| corrupt("object header should be valid utf-8"))?;
+    let (ty, size) = header
+        .split_once(' ')
+        .ok_or_else(|| corrupt("object type and size should be separated by a space"))?;
+    let ty = ObjectType::try_from(ty)?;
+    let size: u64 = size
+        .parse()
+        .map_err(|_| corrupt("expected object size to be decimal encoded"))?;
+
+    Ok((ty, size, reader.take(size)))
+}
+
+/// A parsed commit object.
+#[derive(Debug, Clone)]
+pub struct Commit {
+    pub tree: ObjectId,
+    pub parents: Vec<ObjectId>,
+    pub author: String,
+    pub committer: String,
+    pub message: String,
+}
+
+impl Commit {
+    pub fn parse(contents: &[u8]) -> crate::Result<Commit> {
+        let text =
+            std::str::from_utf8(contents).map_err(|_| corrupt("commit should be valid utf-8"))?;
+        let (header, message) = text.split_once("\n\n").unwrap_or((text, ""));
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        for line in header.lines() {
+            match line.split_once(' ') {
+                Some(("tree", hash)) => tree = Some(hash.parse()?),
+                Some(("parent", hash)) => parents.push(hash.parse()?),
+                Some(("author", ident)) => author = Some(ident.to_owned()),
+                Some(("committer", ident)) => committer = Some(ident.to_owned()),
+                _ => (),
+            }
+        }
+
+        Ok(Commit {
+            tree: tree.ok_or_else(|| corrupt("commit has no tree"))?,
+            parents,
+            author: author.unwrap_or_default(),
+            committer: committer.unwrap_or_default(),
+            message: message.to_owned(),
+        })
+    }
+
+    /// Read and parse the commit identified by `hash`. The parents of a
+    /// shallow commit are hidden, as they are not in the repository.
+    pub fn read(hash: &ObjectId) -> crate::Result<Commit> {
+        let object = Object::read(hash)?;
+        crate::ensure!(object.ty == ObjectType::Commit, "{hash} is not a commit");
+        let mut commit = Commit::parse(&object.contents)?;
+        if shallow::read()?.contains(hash) {
+            commit.parents.clear();
+        }
+
+        Ok(commit)
+    }
+
+    /// Store the commit in `store` and return its hash.
+    pub fn write(&self, store: &dyn ObjectStore) -> crate::Result<ObjectId> {
+        let mut contents = format!("tree {}\n", self.tree);
+        for parent in &self.parents {
+            contents.push_str(&format!("parent {parent}\n"));
+        }
+        contents.push_str(&format!("author {}\n", self.author));
+        contents.push_str(&format!("committer {}\n", self.committer));
+        contents.push('\n');
+        contents.push_str(&self.message);
+
+        store.write(&Object {
+            ty: ObjectType::Commit,
+            contents: contents.into_bytes(),
+        })
+    }
+
+    /// Commit time in seconds since the epoch, taken from the committer line.
+    pub fn time(&self) -> i64 {
+        self.committer
+            .rsplit(' ')
+            .nth(1)
+            .and_then(|time| time.parse().ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Whether the commit `ancestor` is reachable from the commit `descendant`.
+pub fn is_ancestor(ancestor: &ObjectId, descendant: &ObjectId) -> crate::Result<bool> {
+    let mut pending = vec![*descendant];
+    let mut seen = HashSet::new();
+    while let Some(hash) = pending.pop() {
+        if hash == *ancestor {
+            return Ok(true);
+        }
+        if seen.insert(hash) {
+            pending.extend(Commit::read(&hash)?.parents);
+        }
     }
 
-    pub fn write_to_objects_store(self) -> anyhow::Result<String> {
-        let mut buf = Vec::new();
-        let hash = self.write(&mut buf)?;
-        let encoded = zlib_encode(&buf);
+    Ok(false)
+}
+
+/// Hashes of all objects reachable from `tips`, without descending into
+/// objects in `exclude`.
+pub fn reachable_from(
+    tips: impl IntoIterator<Item = ObjectId>,
+    exclude: &HashSet<ObjectId>,
+) -> crate::Result<HashSet<ObjectId>> {
+    let mut pending: Vec<ObjectId> = tips.into_iter().collect();
+    let mut reachable = HashSet::new();
+    let shallow = shallow::read()?;
+    let partial = crate::promisor::is_partial_clone()?;
+    while let Some(hash) = pending.pop() {
+        if hash.is_null() || exclude.contains(&hash) || reachable.contains(&hash) {
+            continue;
+        }
+        // Objects left out of a partial clone are not fetched just to be walked.
+        if partial && !Object::exists(&hash)? {
+            continue;
+        }
+        let object = Object::read(&hash).context(format!("read reachable object {hash}"))?;
+        if shallow.contains(&hash) {
+            pending.push(Commit::parse(&object.contents)?.tree);
+        } else {
+            pending.extend(object.references()?);
+        }
+        reachable.insert(hash);
+    }
+
+    Ok(reachable)
+}
+
+/// Walk the commits reachable from `tips` and from `known_tips`, which the
+/// other side of a push or fetch has, newest first until only commits
+/// reachable from `known_tips` are left to walk. The parents of `shallow`
+/// commits are not followed. Returns the commits found only reachable from
+/// `tips`, those found reachable from `known_tips`, and the latter that are
+/// parents of the former.
+#[allow(clippy::type_complexity)]
+pub fn walk_commits(
+    tips: &[ObjectId],
+    known_tips: &[ObjectId],
+    shallow: &HashSet<ObjectId>,
+) -> crate::Result<(Vec<ObjectId>, HashSet<ObjectId>, Vec<ObjectId>)> {
+    /// Queue `hash` unless it was seen already, marking it as known if
+    /// `is_known`.
+    fn enqueue(
+        hash: ObjectId,
+        is_known: bool,
+        known: &mut HashMap<ObjectId, bool>,
+        queue: &mut BinaryHeap<(i64, ObjectId)>,
+    ) -> crate::Result<()> {
+        if let Some(seen) = known.get_mut(&hash) {
+            *seen |= is_known;
+        } else {
+            queue.push((Commit::read(&hash)?.time(), hash));
+            known.insert(hash, is_known);
+        }
+        Ok(())
+    }
+
+    let mut known = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    for (tip, is_known) in known_tips
+        .iter()
+        .map(|tip| (tip, true))
+        .chain(tips.iter().map(|tip| (tip, false)))
+    {
+        let commit = crate::refs::peel_tag(tip)?.unwrap_or(*tip);
+        if Object::read_header(&commit)?.0 == ObjectType::Commit {
+            enqueue(commit, is_known, &mut known, &mut queue)?;
+        }
+    }
+
+    let mut new_commits = Vec::new();
+    while queue.iter().any(|(_, hash)| !known[hash]) {
+        let Some((_, hash)) = queue.pop() else {
+            break;
+        };
+        let is_known = known[&hash];
+        let parents = if shallow.contains(&hash) {
+            Vec::new()
+        } else {
+            Commit::read(&hash)?.parents
+        };
+        for parent in &parents {
+            enqueue(*parent, is_known, &mut known, &mut queue)?;
+        }
+        if !is_known {
+            new_commits.push((hash, parents));
+        }
+    }
 
-        let object_dir = &hash[..2];
-        let object_file = &hash[2..];
-        let object_file_path = format!(".git/objects/{}/{}", object_dir, object_file);
-        log::debug!("Writing blob to {}", object_file_path);
-        fs::create_dir_all(format!(".git/objects/{}", object_dir)).context("create object dir")?;
-        fs::write(object_file_path, encoded).context("write object to file")?;
+    let mut boundary = Vec::new();
+    for (_, parents) in &new_commits {
+        for parent in parents {
+            if known[parent] && !boundary.contains(parent) {
+                boundary.push(*parent);
+            }
+        }
+    }
+    let new_commits = new_commits
+        .into_iter()
+        .map(|(hash, _)| hash)
+        .filter(|hash| !known[hash])
+        .collect();
+    let known_commits = known
+        .into_iter()
+        .filter_map(|(hash, is_known)| is_known.then_some(hash))
+        .collect();
+
+    Ok((new_commits, known_commits, boundary))
+}
 
-        Ok(hash)
+/// Walk the tree `tree` and its subtrees, calling `visit` with the path of
+/// each entry not in `seen` yet and adding it there. Subtrees are only
+/// walked if `visit` returns `true` for them. Blobs are not read.
+pub fn walk_tree(
+    tree: &ObjectId,
+    prefix: &str,
+    seen: &mut HashSet<ObjectId>,
+    visit: &mut dyn FnMut(String, &TreeEntry) -> crate::Result<bool>,
+) -> crate::Result<()> {
+    for entry in read_tree(tree)? {
+        if entry.mode == FileMode::Gitlink || !seen.insert(entry.hash) {
+            continue;
+        }
+        let path = format!("{prefix}{}", entry.file);
+        let descend = visit(path.clone(), &entry)?;
+        if descend && entry.mode == FileMode::Directory {
+            walk_tree(&entry.hash, &format!("{path}/"), seen, visit)?;
+        }
     }
+
+    Ok(())
 }
 
-pub fn zlib_encode(data: &[u8]) -> Vec<u8> {
+pub fn zlib_encode(data: &[u8]) -> crate::Result<Vec<u8>> {
     let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::new(1));
-    encoder.write_all(data).unwrap();
-    let encoded = encoder.finish().unwrap();
+    encoder.write_all(data)?;
 
-    encoded
+    Ok(encoder.finish()?)
 }
 
-pub fn zlib_decode(data: &[u8]) -> Vec<u8> {
-    let mut decoder = flate2::bufread::ZlibDecoder::new(&data[..]);
+pub fn zlib_decode(data: &[u8]) -> crate::Result<Vec<u8>> {
+    let mut decoder = flate2::bufread::ZlibDecoder::new(data);
     let mut decoded: Vec<u8> = Vec::new();
-    decoder.read_to_end(&mut decoded).unwrap();
+    decoder
+        .read_to_end(&mut decoded)
+        .map_err(|err| corrupt(format!("invalid zlib stream: {err}")))?;
+
+    Ok(decoded)
+}
 
-    decoded
+fn corrupt(message: impl Into<String>) -> Error {
+    Error::CorruptObject(message.into())
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -96,19 +539,21 @@ pub enum FileMode {
     RegularFile,
     ExecutableFile,
     Directory,
+    /// A symbolic link, stored as a blob holding the link target.
+    Symlink,
+    /// A submodule, stored as the id of a commit in another repository.
+    Gitlink,
 }
 
 impl FileMode {
     pub fn parse(input: &[u8]) -> IResult<&[u8], FileMode> {
-        let (input, mode) = nom::branch::alt((tag("100644"), tag("40000"), tag("100755")))(input)?;
-        Ok((
-            input,
-            String::from_utf8(mode.to_vec())
-                .unwrap()
-                .as_str()
-                .try_into()
-                .unwrap(),
-        ))
+        nom::branch::alt((
+            nom::combinator::value(FileMode::RegularFile, tag("100644")),
+            nom::combinator::value(FileMode::Directory, tag("40000")),
+            nom::combinator::value(FileMode::ExecutableFile, tag("100755")),
+            nom::combinator::value(FileMode::Symlink, tag("120000")),
+            nom::combinator::value(FileMode::Gitlink, tag("160000")),
+        ))(input)
     }
 }
 
@@ -118,19 +563,23 @@ impl Display for FileMode {
             FileMode::RegularFile => f.write_str("100644"),
             FileMode::ExecutableFile => f.write_str("100755"),
             FileMode::Directory => f.write_str("40000"),
+            FileMode::Symlink => f.write_str("120000"),
+            FileMode::Gitlink => f.write_str("160000"),
         }
     }
 }
 
 impl TryFrom<&str> for FileMode {
-    type Error = String;
+    type Error = Error;
 
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
+    fn try_from(value: &str) -> crate::Result<Self> {
         match value {
             "100644" => Ok(FileMode::RegularFile),
             "100755" => Ok(FileMode::ExecutableFile),
             "40000" | "040000" => Ok(FileMode::Directory),
-            _ => Err(value.to_owned()),
+            "120000" => Ok(FileMode::Symlink),
+            "160000" => Ok(FileMode::Gitlink),
+            _ => Err(corrupt(format!("invalid file mode {value:?}"))),
         }
     }
 }
@@ -139,16 +588,20 @@ impl TryFrom<&str> for FileMode {
 pub enum ObjectType {
     Blob,
     Tree,
+    Commit,
+    Tag,
 }
 
 impl TryFrom<&str> for ObjectType {
-    type Error = &'static str;
+    type Error = Error;
 
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
+    fn try_from(value: &str) -> crate::Result<Self> {
         match value {
             "blob" => Ok(ObjectType::Blob),
             "tree" => Ok(ObjectType::Tree),
-            _ => Err("invalid variant"),
+            "commit" => Ok(ObjectType::Commit),
+            "tag" => Ok(ObjectType::Tag),
+            _ => Err(corrupt(format!("invalid object type {value:?}"))),
         }
     }
 }
@@ -158,6 +611,8 @@ impl Display for ObjectType {
         match self {
             ObjectType::Blob => f.write_str("blob"),
             ObjectType::Tree => f.write_str("tree"),
+            ObjectType::Commit => f.write_str("commit"),
+            ObjectType::Tag => f.write_str("tag"),
         }
     }
 }
@@ -165,8 +620,11 @@ impl Display for ObjectType {
 impl From<FileMode> for ObjectType {
     fn from(value: FileMode) -> Self {
         match value {
-            FileMode::RegularFile | FileMode::ExecutableFile => ObjectType::Blob,
+            FileMode::RegularFile | FileMode::ExecutableFile | FileMode::Symlink => {
+                ObjectType::Blob
+            }
             FileMode::Directory => ObjectType::Tree,
+            FileMode::Gitlink => ObjectType::Commit,
         }
     }
 }
@@ -175,21 +633,27 @@ impl From<FileMode> for ObjectType {
 pub struct TreeEntry {
     pub mode: FileMode,
     pub ty: ObjectType,
-    pub hash: String,
+    pub hash: ObjectId,
     pub file: String,
 }
 
 impl TreeEntry {
-    pub fn parse(input: &[u8]) -> IResult<&[u8], TreeEntry> {
+    /// Parse one entry of a tree whose object ids are in `format`.
+    pub fn parse(input: &[u8], format: ObjectFormat) -> IResult<&[u8], TreeEntry> {
         let (input, mode) = FileMode::parse(input)?;
         let (input, _) = nom::character::complete::space0(input)?;
-        let (input, file) = nom::bytes::complete::take_while(|s| s != '\0' as u8)(input)?;
+        let (input, file) = nom::combinator::map_res(
+            nom::bytes::complete::take_while(|s| s != b'\0'),
+            std::str::from_utf8,
+        )(input)?;
         let (input, _) = tag("\0")(input)?;
-        let (input, hash) = nom::bytes::complete::take(20usize)(input)?;
+        let (input, hash) = nom::combinator::map_res(
+            nom::bytes::complete::take(format.hash_len()),
+            ObjectId::from_bytes,
+        )(input)?;
 
         let ty = ObjectType::from(mode);
-        let hash = hex::encode(hash);
-        let file = String::from_utf8(file.to_vec()).unwrap();
+        let file = file.to_owned();
 
         Ok((
             input,
@@ -202,12 +666,20 @@ impl TreeEntry {
         ))
     }
 
-    pub fn write(&self, mut writer: impl Write) -> anyhow::Result<()> {
-        writer.write(self.mode.to_string().as_bytes())?;
-        writer.write(b" ")?;
-        writer.write(self.file.as_bytes())?;
-        writer.write(b"\0")?;
-        writer.write(&hex::decode(&self.hash)?)?;
+    /// Parse the entries of a tree's `contents`, `None` if it is malformed.
+    pub fn parse_all(contents: &[u8], format: ObjectFormat) -> Option<Vec<TreeEntry>> {
+        let (_, entries) =
+            nom::multi::many0(|input| TreeEntry::parse(input, format))(contents).ok()?;
+
+        Some(entries)
+    }
+
+    pub fn write(&self, mut writer: impl Write) -> crate::Result<()> {
+        writer.write_all(self.mode.to_string().as_bytes())?;
+        writer.write_all(b" ")?;
+        writer.write_all(self.file.as_bytes())?;
+        writer.write_all(b"\0")?;
+        writer.write_all(self.hash.as_bytes())?;
 
         Ok(())
     }
@@ -225,45 +697,138 @@ impl Display for TreeEntry {
     }
 }
 
-pub fn hash(data: &[u8]) -> String {
-    let mut hasher = Sha1::new();
-    hasher.update(&data);
-    let hashed_blob = hasher.finalize();
-    hex::encode(hashed_blob)
+/// Compare tree entry names the way git sorts trees: a directory's name
+/// compares as if it ended with `/`.
+// https://github.com/git/git/blob/11c821f2f2a31e70fb5cc449f9a29401c333aad2/tree.c#L99
+pub fn compare_tree_entry_names(
+    name1: &[u8],
+    is_dir1: bool,
+    name2: &[u8],
+    is_dir2: bool,
+) -> std::cmp::Ordering {
+    let min_len = name1.len().min(name2.len());
+
+    match name1[..min_len].cmp(&name2[..min_len]) {
+        std::cmp::Ordering::Equal => (),
+        ord => return ord,
+    };
+
+    let c1 = match name1.get(min_len).copied() {
+        Some(c) => Some(c),
+        _ if is_dir1 => Some(b'/'),
+        _ => None,
+    };
+    let c2 = match name2.get(min_len).copied() {
+        Some(c) => Some(c),
+        _ if is_dir2 => Some(b'/'),
+        _ => None,
+    };
+
+    c1.cmp(&c2)
+}
+
+/// Hash `data` with the repository's object format.
+pub fn hash(data: &[u8]) -> crate::Result<ObjectId> {
+    Ok(ObjectFormat::current()?.hash(data))
 }
 
 pub struct BlobObject {
     pub contents: Vec<u8>,
 }
 
-pub fn parse_tree_entries(input: &[u8]) -> IResult<&[u8], Vec<TreeEntry>> {
-    let (input, _) =
-        nom::sequence::tuple((tag("tree"), nom::number::complete::le_i32, tag("\0")))(input)?;
-    let (input, entries) = nom::multi::many0(TreeEntry::parse)(input)?;
+/// Read and parse the entries of the tree identified by `hash`.
+pub fn read_tree(hash: &ObjectId) -> crate::Result<Vec<TreeEntry>> {
+    let tree = Object::read(hash)?;
+    crate::ensure!(tree.ty == ObjectType::Tree, "{hash} is not a tree");
+    TreeEntry::parse_all(&tree.contents, hash.format())
+        .ok_or_else(|| corrupt(format!("invalid tree object {hash}")))
+}
 
-    Ok((input, entries))
+/// Store a tree made of `entries` in `store` and return its hash. The
+/// entries are sorted the way git expects.
+pub fn write_tree(store: &dyn ObjectStore, mut entries: Vec<TreeEntry>) -> crate::Result<ObjectId> {
+    entries.sort_by(|entry1, entry2| {
+        compare_tree_entry_names(
+            entry1.file.as_bytes(),
+            entry1.mode == FileMode::Directory,
+            entry2.file.as_bytes(),
+            entry2.mode == FileMode::Directory,
+        )
+    });
+    let mut contents = Vec::new();
+    for entry in &entries {
+        entry.write(&mut contents)?;
+    }
+
+    store.write(&Object {
+        ty: ObjectType::Tree,
+        contents,
+    })
 }
 
-pub fn file_to_blob_object(file: impl AsRef<Path>) -> BlobObject {
-    let contents = std::fs::read(file).unwrap();
+pub fn file_to_blob_object(file: impl AsRef<Path>) -> crate::Result<BlobObject> {
+    let file = file.as_ref();
+    let contents = std::fs::read(file).context(format!("read {}", file.display()))?;
     let size = contents.len().to_string();
 
     let mut blob = Vec::new();
     blob.extend_from_slice(&b"blob "[..]);
     blob.extend_from_slice(size.as_bytes());
-    blob.push('\0' as u8);
+    blob.push(b'\0');
     blob.extend_from_slice(&contents);
 
-    BlobObject { contents: blob }
+    Ok(BlobObject { contents: blob })
+}
+
+/// Path of the loose object `hash`.
+pub fn hash_to_path(hash: &ObjectId) -> PathBuf {
+    FileStore::new(git_dir().join("objects"), hash.format()).loose_path(hash)
 }
 
-pub fn hash_to_path(hash: &str) -> anyhow::Result<PathBuf> {
-    if hash.len() != 40 || hex::decode(hash).is_err() {
-        return Err(anyhow::anyhow!("invalid hash"));
+/// Find the git directory of the repository at `path`: its `.git`
+/// subdirectory, or `path` itself for a bare repository.
+pub fn find_git_dir(path: &Path) -> crate::Result<PathBuf> {
+    if path.join(".git").is_dir() {
+        Ok(path.join(".git"))
+    } else if path.join("objects").is_dir() && path.join("HEAD").is_file() {
+        Ok(path.to_owned())
+    } else {
+        Err(Error::Message(format!(
+            "{} does not appear to be a git repository",
+            path.display()
+        )))
     }
-    let prefix = &hash[..2];
-    let suffix = &hash[2..];
-    let path = PathBuf::from(format!(".git/objects/{}/{}", prefix, suffix));
+}
+
+/// Whether the git directory `git_dir` belongs to a repository without a working tree.
+pub fn is_bare(git_dir: &Path) -> bool {
+    git_dir.file_name().is_none_or(|name| name != ".git")
+}
 
-    Ok(path)
+/// The git directory set by [`enter_repository`], which takes precedence
+/// over `$GIT_DIR`.
+static ENTERED_GIT_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Make the repository at `path` the one all further operations of this
+/// process act on, like running git with `GIT_DIR` set. A process can only
+/// enter one repository.
+pub fn enter_repository(path: &Path) -> crate::Result<()> {
+    let git_dir = fs::canonicalize(find_git_dir(path)?).context("resolve git dir")?;
+    let entered = ENTERED_GIT_DIR.get_or_init(|| git_dir.clone());
+    crate::ensure!(
+        *entered == git_dir,
+        "already in repository {}",
+        entered.display()
+    );
+
+    Ok(())
+}
+
+/// The repository's git directory: the one entered with
+/// [`enter_repository`], else `$GIT_DIR` if set, otherwise `.git`.
+pub fn git_dir() -> PathBuf {
+    if let Some(git_dir) = ENTERED_GIT_DIR.get() {
+        return git_dir.clone();
+    }
+    std::env::var_os("GIT_DIR").map_or_else(|| PathBuf::from(".git"), PathBuf::from)
 }
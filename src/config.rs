@@ -0,0 +1,348 @@
+use crate::common::git_dir;
+use crate::error::Context;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone)]
+struct Section {
+    name: String,
+    subsection: Option<String>,
+    entries: Vec<(String, String)>,
+}
+
+/// Repository configuration in git's ini-like format.
+/// Keys are addressed as `section.key` or `section.subsection.key`.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    sections: Vec<Section>,
+}
+
+impl Config {
+    /// Read the repository's `config`, or an empty config if it does not exist.
+    pub fn read() -> crate::Result<Config> {
+        Config::read_from(git_dir().join("config"))
+    }
+
+    /// Read the configuration in effect for the repository: the system
+    /// config, the user's global config and the repository's `config`, later
+    /// files taking precedence. Only for looking up values, as writing it
+    /// back would copy the other files into the repository's.
+    pub fn read_all() -> crate::Result<Config> {
+        let mut config = Config::default();
+        for path in system_and_global_paths() {
+            config.sections.extend(Config::read_from(path)?.sections);
+        }
+        config.sections.extend(Config::read()?.sections);
+
+        Ok(config)
+    }
+
+    pub fn read_from(path: impl AsRef<Path>) -> crate::Result<Config> {
+        match fs::read_to_string(path.as_ref()) {
+            Ok(contents) => Config::parse(&contents),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(err).context(format!("read config {:?}", path.as_ref())),
+        }
+    }
+
+    pub fn parse(contents: &str) -> crate::Result<Config> {
+        let mut sections: Vec<Section> = Vec::new();
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[') {
+                let header = header.strip_suffix(']').context(format!(
+                    "config line {}: unterminated section",
+                    line_number + 1
+                ))?;
+                let (name, subsection) = match header.split_once(' ') {
+                    Some((name, subsection)) => {
+                        let subsection = subsection.trim().trim_matches('"').to_owned();
+                        (name, Some(subsection))
+                    }
+                    // Legacy `[section.subsection]` syntax.
+                    None => match header.split_once('.') {
+                        Some((name, subsection)) => (name, Some(subsection.to_owned())),
+                        None => (header, None),
+                    },
+                };
+                sections.push(Section {
+                    name: name.to_lowercase(),
+                    subsection,
+                    entries: Vec::new(),
+                });
+                continue;
+            }
+            let section = sections.last_mut().context(format!(
+                "config line {}: entry outside of section",
+                line_number + 1
+            ))?;
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), unquote(value.trim())),
+                // A key without a value means `true`.
+                None => (line, "true".to_owned()),
+            };
+            section.entries.push((key.to_lowercase(), value));
+        }
+
+        Ok(Config { sections })
+    }
+
+    /// Return the last value of `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_all(key).last().copied()
+    }
+
+    /// Return all values of the multi-valued `key` in order.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let Some((name, subsection, key)) = split_key(key) else {
+            return Vec::new();
+        };
+        self.sections
+            .iter()
+            .filter(|section| section.name == name && section.subsection.as_deref() == subsection)
+            .flat_map(|section| &section.entries)
+            .filter(|(entry_key, _)| *entry_key == key)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get(key)
+            .map(|value| matches!(value.to_lowercase().as_str(), "true" | "yes" | "on" | "1"))
+    }
+
+    /// Subsection names of all sections called `name`, e.g. the names of all remotes.
+    pub fn subsections(&self, name: &str) -> Vec<&str> {
+        let mut subsections: Vec<&str> = self
+            .sections
+            .iter()
+            .filter(|section| section.name == name)
+            .filter_map(|section| section.subsection.as_deref())
+            .collect();
+        subsections.dedup();
+        subsections
+    }
+
+    /// Set `key` to `value`, replacing all existing values.
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.unset(key);
+        self.add(key, value);
+    }
+
+    /// Add `value` to the multi-valued `key`.
+    pub fn add(&mut self, key: &str, value: &str) {
+        let Some((name, subsection, key)) = split_key(key) else {
+            return;
+        };
+        let position = self.sections.iter().rposition(|section| {
+            section.name == name && section.subsection.as_deref() == subsection
+        });
+        let section = match position {
+            Some(position) => &mut self.sections[position],
+            None => {
+                self.sections.push(Section {
+                    name: name.to_owned(),
+                    subsection: subsection.map(str::to_owned),
+                    entries: Vec::new(),
+                });
+                self.sections.last_mut().unwrap()
+            }
+        };
+        section.entries.push((key.to_owned(), value.to_owned()));
+    }
+
+    pub fn unset(&mut self, key: &str) {
+        let Some((name, subsection, key)) = split_key(key) else {
+            return;
+        };
+        for section in &mut self.sections {
+            if section.name == name && section.subsection.as_deref() == subsection {
+                section.entries.retain(|(entry_key, _)| *entry_key != key);
+            }
+        }
+    }
+
+    pub fn write(&self) -> crate::Result<()> {
+        self.write_to(git_dir().join("config"))
+    }
+
+    pub fn write_to(&self, path: impl AsRef<Path>) -> crate::Result<()> {
+        let mut contents = String::new();
+        for section in &self.sections {
+            match &section.subsection {
+                Some(subsection) => {
+                    contents.push_str(&format!("[{} \"{}\"]\n", section.name, subsection))
+                }
+                None => contents.push_str(&format!("[{}]\n", section.name)),
+            }
+            for (key, value) in &section.entries {
+                contents.push_str(&format!("\t{} = {}\n", key, quote(value)));
+            }
+        }
+        fs::write(path, contents).context("write config")?;
+
+        Ok(())
+    }
+}
+
+/// The system config, `$GIT_CONFIG_SYSTEM` or `/etc/gitconfig`, followed by
+/// the global ones, `$GIT_CONFIG_GLOBAL` or both
+/// `$XDG_CONFIG_HOME/git/config` and `~/.gitconfig`.
+fn system_and_global_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if std::env::var_os("GIT_CONFIG_NOSYSTEM").is_none() {
+        paths.push(
+            std::env::var_os("GIT_CONFIG_SYSTEM")
+                .map_or_else(|| PathBuf::from("/etc/gitconfig"), PathBuf::from),
+        );
+    }
+    if let Some(global) = std::env::var_os("GIT_CONFIG_GLOBAL") {
+        paths.push(PathBuf::from(global));
+        return paths;
+    }
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let xdg_config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".config")));
+    paths.extend(xdg_config.map(|dir| dir.join("git/config")));
+    paths.extend(home.map(|home| home.join(".gitconfig")));
+
+    paths
+}
+
+/// Split `section[.subsection].key` into its parts, lowercasing the
+/// case-insensitive section name and key.
+fn split_key(key: &str) -> Option<(String, Option<&str>, String)> {
+    let (section, key) = key.split_once('.')?;
+    let (subsection, key) = match key.rsplit_once('.') {
+        Some((subsection, key)) => (Some(subsection), key),
+        None => (None, key),
+    };
+
+    Some((section.to_lowercase(), subsection, key.to_lowercase()))
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_quotes = false;
+    for (idx, c) in line.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '#' | ';' if !in_quotes => return &line[..idx],
+            _ => (),
+        }
+    }
+    line
+}
+
+fn unquote(value: &str) -> String {
+    let mut unquoted = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => (),
+            '\\' => match chars.next() {
+                Some('n') => unquoted.push('\n'),
+                Some('t') => unquoted.push('\t'),
+                Some(c) => unquoted.push(c),
+                None => (),
+            },
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+fn quote(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+    if value.starts_with(' ') || value.ends_with(' ') || value.contains(['#', ';']) {
+        format!("\"{escaped}\"")
+    } else {
+        escaped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sections_and_values() {
+        let config = Config::parse(
+            r#"
+# comment
+[Core]
+	bare = false ; trailing comment
+	FileMode
+[remote "origin"]
+	url = "https://example.com/repo.git"
+	fetch = +refs/heads/*:refs/remotes/origin/*
+	fetch = +refs/tags/*:refs/tags/*
+[branch.main]
+	remote = origin
+[alias]
+	lg = "log --oneline # not a comment"
+	tab = a\tb\n
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.get_bool("core.bare"), Some(false));
+        assert_eq!(config.get_bool("CORE.filemode"), Some(true));
+        assert_eq!(
+            config.get("remote.origin.url"),
+            Some("https://example.com/repo.git")
+        );
+        assert_eq!(
+            config.get_all("remote.origin.fetch"),
+            [
+                "+refs/heads/*:refs/remotes/origin/*",
+                "+refs/tags/*:refs/tags/*"
+            ]
+        );
+        assert_eq!(config.get("branch.main.remote"), Some("origin"));
+        assert_eq!(
+            config.get("alias.lg"),
+            Some("log --oneline # not a comment")
+        );
+        assert_eq!(config.get("alias.tab"), Some("a\tb\n"));
+        assert_eq!(
+            config.get("remote.Origin.url"),
+            None,
+            "subsections are case-sensitive"
+        );
+        assert_eq!(config.get("core"), None);
+        assert_eq!(config.subsections("remote"), ["origin"]);
+    }
+
+    #[test]
+    fn later_values_win() {
+        let config = Config::parse("[user]\nname = A\n[user]\nname = B\n").unwrap();
+        assert_eq!(config.get("user.name"), Some("B"));
+        assert_eq!(config.get_all("user.name"), ["A", "B"]);
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        assert!(Config::parse("key = value\n").is_err());
+        assert!(Config::parse("[core\n").is_err());
+    }
+
+    #[test]
+    fn sets_and_unsets_values() {
+        let mut config = Config::parse("[remote \"origin\"]\n\turl = a\n").unwrap();
+        config.add("remote.origin.fetch", "x");
+        config.add("remote.origin.fetch", "y");
+        config.set("remote.origin.url", "b");
+        config.set("core.bare", "true");
+        assert_eq!(config.get_all("remote.origin.fetch"), ["x", "y"]);
+        assert_eq!(config.get_all("remote.origin.url"), ["b"]);
+        assert_eq!(config.get_bool("core.bare"), Some(true));
+        config.unset("remote.origin.fetch");
+        assert!(config.get_all("remote.origin.fetch").is_empty());
+    }
+}
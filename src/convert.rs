@@ -0,0 +1,149 @@
+use crate::attributes::{AttributeValue, Attributes};
+use crate::config::Config;
+use crate::error::Context;
+use std::{
+    collections::hash_map::RandomState,
+    fs::{self, File, OpenOptions},
+    hash::{BuildHasher, Hasher},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+/// Convert the blob `contents` for display in a diff of the file at `path`,
+/// with the `textconv` command of its `diff` driver. Contents without a
+/// configured command are returned unchanged.
+pub fn textconv(contents: Vec<u8>, path: &str) -> crate::Result<Vec<u8>> {
+    let attributes = Attributes::read(path)?;
+    let Some(AttributeValue::Value(driver)) = attributes.get(path, "diff") else {
+        return Ok(contents);
+    };
+    let config = Config::read_all()?;
+    let Some(command) = config.get(&format!("diff.{driver}.textconv")) else {
+        return Ok(contents);
+    };
+
+    // Like git, the command reads the contents from a temporary file
+    // passed as its argument.
+    let (tmp_path, mut tmp_file) = create_temp_file("mygit-textconv")?;
+    let written = tmp_file.write_all(&contents);
+    drop(tmp_file);
+    if let Err(err) = written {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(err).context("write textconv input");
+    }
+    log::debug!("Running textconv {command:?} for {path}");
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{command} \"$@\""))
+        .arg(command)
+        .arg(&tmp_path)
+        .stderr(Stdio::inherit())
+        .output();
+    let _ = fs::remove_file(&tmp_path);
+    let output = output.context(format!("run textconv {command}"))?;
+    crate::ensure!(
+        output.status.success(),
+        "textconv {command} failed for {path}"
+    );
+
+    Ok(output.stdout)
+}
+
+/// Create a new file in the temporary directory, readable only by the
+/// current user. The file is created exclusively under a random name, so
+/// another user cannot have created it in advance or pointed a symlink there.
+fn create_temp_file(prefix: &str) -> crate::Result<(PathBuf, File)> {
+    for _ in 0..16 {
+        // `RandomState` is seeded randomly for each instance.
+        let random = RandomState::new().build_hasher().finish();
+        let path = std::env::temp_dir().join(format!("{prefix}-{random:016x}"));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+        {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err).context(format!("create {}", path.display())),
+        }
+    }
+
+    crate::bail!("could not create a temporary file")
+}
+
+/// Convert the blob `contents` to what is checked out at `path`: run the
+/// `smudge` command of its `filter` driver, then convert line endings to
+/// CRLF for `eol=crlf`, or for text with `core.autocrlf` set.
+pub fn to_working_tree(mut contents: Vec<u8>, path: &str) -> crate::Result<Vec<u8>> {
+    let attributes = Attributes::read(path)?;
+    let config = Config::read_all()?;
+
+    if let Some(AttributeValue::Value(driver)) = attributes.get(path, "filter") {
+        if let Some(command) = config.get(&format!("filter.{driver}.smudge")) {
+            match smudge(command, &contents, path) {
+                Ok(smudged) => contents = smudged,
+                // Like git, fall back to the unfiltered contents unless the
+                // filter is required.
+                Err(err) if config.get_bool(&format!("filter.{driver}.required")) != Some(true) => {
+                    eprintln!("error: {err}");
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    let text = attributes.get(path, "text");
+    let crlf = text != Some(&AttributeValue::Unset)
+        && match attributes.get(path, "eol") {
+            Some(AttributeValue::Value(eol)) => eol == "crlf",
+            // Without attributes, files that look binary are left alone.
+            _ => {
+                config.get_bool("core.autocrlf") == Some(true)
+                    && (text == Some(&AttributeValue::Set) || !contents.contains(&0))
+            }
+        };
+    if crlf {
+        let mut converted = Vec::with_capacity(contents.len());
+        for (idx, &byte) in contents.iter().enumerate() {
+            if byte == b'\n' && (idx == 0 || contents[idx - 1] != b'\r') {
+                converted.push(b'\r');
+            }
+            converted.push(byte);
+        }
+        contents = converted;
+    }
+
+    Ok(contents)
+}
+
+/// Run the filter `command`, with `%f` replaced by the quoted `path`, on
+/// `contents`.
+fn smudge(command: &str, contents: &[u8], path: &str) -> crate::Result<Vec<u8>> {
+    let quoted = format!("'{}'", path.replace('\'', "'\\''"));
+    let script = command.replace("%f", &quoted);
+    log::debug!("Running smudge filter {script:?} for {path}");
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&script)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context(format!("run filter {command}"))?;
+    let mut stdin = child.stdin.take().context("filter stdin")?;
+    // Write from another thread so that a filter producing output before
+    // reading all its input cannot deadlock.
+    let input = contents.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().context("read filter output")?;
+    // Filters that ignore their input may exit before reading it.
+    let _ = writer.join();
+    crate::ensure!(
+        output.status.success(),
+        "external filter '{command}' failed for {path}"
+    );
+
+    Ok(output.stdout)
+}
@@ -0,0 +1,305 @@
+use crate::config::Config;
+use crate::error::Context;
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    process::{Command, Stdio},
+};
+
+/// Credentials for a URL, exchanged with credential helpers as `key=value` lines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Credential {
+    pub protocol: String,
+    /// Host name, with the port if it is not the default one.
+    pub host: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Credential {
+    /// The protocol and host of `url`, and the username and password embedded
+    /// in it if any.
+    pub fn from_url(url: &reqwest::Url) -> Credential {
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => String::new(),
+        };
+        Credential {
+            protocol: url.scheme().to_owned(),
+            host,
+            username: Some(percent_decode(url.username())).filter(|name| !name.is_empty()),
+            password: url.password().map(percent_decode),
+        }
+    }
+
+    /// Whether both the username and the password are known.
+    pub fn is_complete(&self) -> bool {
+        self.username.is_some() && self.password.is_some()
+    }
+
+    /// Parse the `key=value` lines written by a helper, up to an empty line.
+    /// Returns the credential and whether the helper asked to stop with `quit`.
+    pub fn parse(input: impl BufRead) -> crate::Result<(Credential, bool)> {
+        let mut credential = Credential::default();
+        let mut quit = false;
+        for line in input.lines() {
+            let line = line.context("read credential")?;
+            if line.is_empty() {
+                break;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .context(format!("invalid credential line {line:?}"))?;
+            match key {
+                "protocol" => credential.protocol = value.to_owned(),
+                "host" => credential.host = value.to_owned(),
+                "username" => credential.username = Some(value.to_owned()),
+                "password" => credential.password = Some(value.to_owned()),
+                "quit" => quit = matches!(value, "1" | "true"),
+                // Unknown attributes are ignored, as git does.
+                _ => (),
+            }
+        }
+
+        Ok((credential, quit))
+    }
+
+    pub fn write_to(&self, mut writer: impl Write) -> crate::Result<()> {
+        let mut lines = format!("protocol={}\nhost={}\n", self.protocol, self.host);
+        if let Some(username) = &self.username {
+            lines.push_str(&format!("username={username}\n"));
+        }
+        if let Some(password) = &self.password {
+            lines.push_str(&format!("password={password}\n"));
+        }
+        lines.push('\n');
+        writer
+            .write_all(lines.as_bytes())
+            .context("write credential")
+    }
+
+    /// Whether `self`, a stored credential, answers a query for `query`.
+    pub fn matches(&self, query: &Credential) -> bool {
+        self.protocol == query.protocol
+            && self.host == query.host
+            && query
+                .username
+                .as_ref()
+                .is_none_or(|username| self.username.as_ref() == Some(username))
+    }
+
+    /// Complete the username and password by asking the configured helpers,
+    /// then the user.
+    pub fn fill(&mut self) -> crate::Result<()> {
+        for helper in helpers(self)? {
+            if self.is_complete() {
+                break;
+            }
+            let Some((answer, quit)) = run_helper(&helper, "get", self)? else {
+                continue;
+            };
+            self.username = self.username.take().or(answer.username);
+            self.password = self.password.take().or(answer.password);
+            crate::ensure!(!quit, "credential helper '{helper}' told us to quit");
+        }
+
+        let url = format!("{}://{}", self.protocol, self.host);
+        if self.username.is_none() {
+            self.username = Some(prompt(&format!("Username for '{url}': "), true)?);
+        }
+        if self.password.is_none() {
+            let username = self.username.as_deref().unwrap_or_default();
+            let url = format!("{}://{username}@{}", self.protocol, self.host);
+            self.password = Some(prompt(&format!("Password for '{url}': "), false)?);
+        }
+
+        Ok(())
+    }
+
+    /// Tell the helpers the credential worked so they can store it.
+    pub fn approve(&self) -> crate::Result<()> {
+        for helper in helpers(self)? {
+            run_helper(&helper, "store", self)?;
+        }
+        Ok(())
+    }
+
+    /// Tell the helpers the credential was rejected so they can forget it.
+    pub fn reject(&self) -> crate::Result<()> {
+        for helper in helpers(self)? {
+            run_helper(&helper, "erase", self)?;
+        }
+        Ok(())
+    }
+}
+
+/// The helpers configured by `credential.helper` and by
+/// `credential.<url>.helper` for URLs `credential` falls under, in order. An
+/// empty value clears the helpers configured before it.
+fn helpers(credential: &Credential) -> crate::Result<Vec<String>> {
+    let config = Config::read_all()?;
+    let url = format!("{}://{}", credential.protocol, credential.host);
+    let mut helpers = Vec::new();
+    let mut keys = vec!["credential.helper".to_owned()];
+    for subsection in config.subsections("credential") {
+        let prefix = subsection.trim_end_matches('/');
+        if url == prefix || url.starts_with(&format!("{prefix}/")) {
+            keys.push(format!("credential.{subsection}.helper"));
+        }
+    }
+    for key in keys {
+        for helper in config.get_all(&key) {
+            if helper.is_empty() {
+                helpers.clear();
+            } else {
+                helpers.push(helper.to_owned());
+            }
+        }
+    }
+
+    Ok(helpers)
+}
+
+/// Run `helper` with `action` (`get`, `store` or `erase`), passing it
+/// `credential`. Returns what a `get` answered, or `None` if the helper failed.
+///
+/// A helper starting with `!` is a shell snippet, an absolute path is run as
+/// is, `cache` is the built-in cache and any other name `<name>` runs
+/// `git-credential-<name>`. Arguments may follow the name.
+fn run_helper(
+    helper: &str,
+    action: &str,
+    credential: &Credential,
+) -> crate::Result<Option<(Credential, bool)>> {
+    let mut command = if helper == "cache" || helper.starts_with("cache ") {
+        let mut command = Command::new(std::env::current_exe()?);
+        command.arg("credential-cache");
+        command.args(helper["cache".len()..].split_whitespace());
+        command.arg(action);
+        command
+    } else {
+        let script = if let Some(script) = helper.strip_prefix('!') {
+            script.to_owned()
+        } else if helper.starts_with('/') {
+            helper.to_owned()
+        } else {
+            format!("git-credential-{helper}")
+        };
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("{script} \"$@\""))
+            .arg(script)
+            .arg(action);
+        command
+    };
+    log::debug!("Running credential helper {helper:?} {action}");
+    let child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            eprintln!("warning: cannot run credential helper '{helper}': {err}");
+            return Ok(None);
+        }
+    };
+    let mut stdin = child.stdin.take().context("helper stdin")?;
+    // Helpers that ignore their input may exit before reading it.
+    let _ = credential.write_to(&mut stdin);
+    drop(stdin);
+    let stdout = child.stdout.take().context("helper stdout")?;
+    let answer = if action == "get" {
+        Some(Credential::parse(BufReader::new(stdout))?)
+    } else {
+        None
+    };
+    let status = child.wait()?;
+    if !status.success() {
+        log::debug!("credential helper {helper:?} failed: {status}");
+        return Ok(None);
+    }
+
+    Ok(answer)
+}
+
+/// Ask the user for a value with `GIT_ASKPASS` or `SSH_ASKPASS`, or on the
+/// terminal, not echoing it unless `echo` is set.
+fn prompt(message: &str, echo: bool) -> crate::Result<String> {
+    let askpass = ["GIT_ASKPASS", "SSH_ASKPASS"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()));
+    if let Some(askpass) = askpass {
+        let output = Command::new(&askpass)
+            .arg(message)
+            .stderr(Stdio::inherit())
+            .output()
+            .context(format!("run {askpass}"))?;
+        crate::ensure!(
+            output.status.success(),
+            "could not read {}",
+            message.trim_end_matches(": ")
+        );
+        let value = String::from_utf8(output.stdout).context("askpass output utf-8")?;
+        return Ok(value.trim_end_matches(['\r', '\n']).to_owned());
+    }
+
+    let disabled = std::env::var("GIT_TERMINAL_PROMPT").is_ok_and(|value| value == "0");
+    let tty = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .ok()
+        .filter(|_| !disabled);
+    let Some(mut tty) = tty else {
+        crate::bail!(
+            "could not read {}: terminal prompts disabled",
+            message.trim_end_matches(": ")
+        );
+    };
+    tty.write_all(message.as_bytes())?;
+    let set_echo = |setting: &str| -> crate::Result<()> {
+        Command::new("stty")
+            .arg(setting)
+            .stdin(fs::File::open("/dev/tty")?)
+            .status()
+            .context("run stty")?;
+        Ok(())
+    };
+    if !echo {
+        set_echo("-echo")?;
+    }
+    let mut value = String::new();
+    let read = BufReader::new(&tty).read_line(&mut value);
+    if !echo {
+        set_echo("echo")?;
+        tty.write_all(b"\n")?;
+    }
+    read.context("read from terminal")?;
+
+    Ok(value.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+/// Decode the `%XX` escapes of a URL component.
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let escaped = (bytes[idx] == b'%')
+            .then(|| input.get(idx + 1..idx + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                idx += 3;
+            }
+            None => {
+                decoded.push(bytes[idx]);
+                idx += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
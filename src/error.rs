@@ -0,0 +1,152 @@
+use std::{fmt, io};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors returned by the library.
+#[derive(Debug)]
+pub enum Error {
+    /// No object with this hash is in the loose objects or the packs.
+    ObjectNotFound(String),
+    /// An object, pack or index that cannot be decoded.
+    CorruptObject(String),
+    /// A string that should be an object name but is not one.
+    InvalidHash(String),
+    /// An abbreviated object name matching more than one object.
+    AmbiguousObject(String),
+    Io(io::Error),
+    /// The other side of a connection sent something unexpected.
+    Protocol(String),
+    /// A ref that cannot be updated because it changed, or clashes with another ref.
+    RefConflict(String),
+    /// Any other failure, described by its message.
+    Message(String),
+    /// A failure in a dependency such as the HTTP client.
+    Other(Box<dyn std::error::Error + Send + Sync>),
+    /// `source` annotated with what was being done when it happened.
+    Context {
+        context: String,
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// The error under any context added to it, to match on its kind.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context { source, .. } => source.root(),
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    /// The alternate form `{:#}` appends the messages of the sources.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ObjectNotFound(hash) => write!(f, "object {hash} not found")?,
+            Error::InvalidHash(hash) => write!(f, "invalid object name {hash:?}")?,
+            Error::Io(err) => write!(f, "{err}")?,
+            Error::Other(err) => write!(f, "{err}")?,
+            Error::CorruptObject(message)
+            | Error::AmbiguousObject(message)
+            | Error::Protocol(message)
+            | Error::RefConflict(message)
+            | Error::Message(message)
+            | Error::Context {
+                context: message, ..
+            } => f.write_str(message)?,
+        }
+        if f.alternate() {
+            let mut source = std::error::Error::source(self);
+            while let Some(err) = source {
+                write!(f, ": {err}")?;
+                source = err.source();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => err.source(),
+            Error::Other(err) => err.source(),
+            Error::Context { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+macro_rules! from_other {
+    ($($ty:ty),* $(,)?) => {
+        $(impl From<$ty> for Error {
+            fn from(err: $ty) -> Self {
+                Error::Other(Box::new(err))
+            }
+        })*
+    };
+}
+
+from_other!(
+    flate2::DecompressError,
+    hex::FromHexError,
+    reqwest::Error,
+    std::array::TryFromSliceError,
+    std::ffi::FromBytesWithNulError,
+    std::num::ParseIntError,
+    std::path::StripPrefixError,
+    std::str::Utf8Error,
+    std::string::FromUtf8Error,
+    std::time::SystemTimeError,
+);
+
+/// Adds context to the errors of a `Result`, or turns a `None` into an error,
+/// like `anyhow::Context`.
+pub trait Context<T> {
+    fn context(self, context: impl fmt::Display) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for std::result::Result<T, E> {
+    fn context(self, context: impl fmt::Display) -> Result<T> {
+        self.map_err(|err| Error::Context {
+            context: context.to_string(),
+            source: Box::new(err.into()),
+        })
+    }
+}
+
+impl<T> Context<T> for Option<T> {
+    fn context(self, context: impl fmt::Display) -> Result<T> {
+        self.ok_or_else(|| Error::Message(context.to_string()))
+    }
+}
+
+/// Return an error built from a format string: an `Error::Message`, or the
+/// variant named first as in `bail!(Protocol, "...")`.
+macro_rules! bail {
+    ($variant:ident, $($arg:tt)+) => {
+        return Err($crate::error::Error::$variant(format!($($arg)+)))
+    };
+    ($($arg:tt)+) => {
+        return Err($crate::error::Error::Message(format!($($arg)+)))
+    };
+}
+
+/// Return an error as `bail!` does unless `cond` holds.
+macro_rules! ensure {
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            $crate::bail!($($arg)+);
+        }
+    };
+}
+
+pub(crate) use {bail, ensure};
@@ -1,2 +1,21 @@
+pub mod attributes;
+pub mod bundle;
 pub mod commands;
 pub mod common;
+pub mod config;
+pub mod convert;
+pub mod credential;
+pub mod error;
+pub mod object_id;
+pub mod object_store;
+pub mod pack;
+pub mod pktline;
+pub mod promisor;
+pub mod refs;
+pub mod refspec;
+pub mod revision;
+pub mod shallow;
+pub mod transport;
+
+pub(crate) use error::{bail, ensure};
+pub use error::{Error, Result};
@@ -2,10 +2,17 @@ use clap::Args;
 use clap::Parser;
 use clap::Subcommand;
 use mygit::commands::*;
+use mygit::common::Object;
+use mygit::object_id::{ObjectFormat, ObjectId};
+use mygit::object_store;
+use mygit::promisor::ObjectFilter;
+use mygit::revision;
+use mygit::transport;
 #[allow(unused_imports)]
 use std::env;
 #[allow(unused_imports)]
 use std::fs;
+use std::io::Write;
 use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
@@ -18,13 +25,24 @@ struct Cli {
 #[derive(Debug, Subcommand)]
 enum Command {
     /// Initialize empty Git repository
-    Init,
+    Init {
+        /// Hash function used to name objects
+        #[arg(long, default_value_t = ObjectFormat::Sha1)]
+        object_format: ObjectFormat,
+    },
     /// Inspect Git objects
     CatFile {
         #[command(flatten)]
         flag: CatFileFlag,
-        // Hash of blob object
-        object_hash: String,
+        /// With --batch or --batch-check, show all objects instead of those named on stdin
+        #[arg(long)]
+        batch_all_objects: bool,
+        /// Path the blob is converted for with --textconv or --filters, if not named as <rev>:<path>
+        #[arg(long)]
+        path: Option<String>,
+        /// Object name: a revision such as a hash, abbreviated hash or ref, or <rev>:<path>
+        #[arg(required_unless_present_any = ["batch", "batch_check"])]
+        object: Option<String>,
     },
     /// Create a Git object
     HashObject {
@@ -36,28 +54,261 @@ enum Command {
     },
     /// Inspect a tree object
     LsTree {
+        /// Recurse into subtrees
+        #[arg(short)]
+        recursive: bool,
+        /// Show trees even when recursing into them
+        #[arg(short = 't')]
+        show_trees: bool,
+        /// Show only trees
+        #[arg(short = 'd')]
+        only_trees: bool,
+        /// Show the size of blobs
+        #[arg(short, long)]
+        long: bool,
         /// List only filenames
-        #[arg(long)]
+        #[arg(long, alias = "name-status")]
         name_only: bool,
-        /// Hash of tree object
+        /// Abbreviate object names to this many hex digits
+        #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "7")]
+        abbrev: Option<usize>,
+        /// Output format using %(objectmode), %(objecttype), %(objectname), %(objectsize), %(objectsize:padded) and %(path)
+        #[arg(long, conflicts_with_all = ["long", "name_only"])]
+        format: Option<String>,
+        /// Terminate entries with NUL and do not quote paths
+        #[arg(short = 'z')]
+        null_terminated: bool,
+        /// Tree, commit or tag, optionally followed by `:<path>`
         object: String,
+        /// Only show entries at or below these paths
+        paths: Vec<String>,
     },
     /// Write tree object for staging area
     WriteTree,
     /// Commit tree object
     CommitTree {
         /// Tree SHA
-        commit_hash: String,
+        commit_hash: ObjectId,
         /// Parent commit
         #[arg(short)]
-        parent_commit_hash: String,
+        parent_commit_hash: ObjectId,
         /// Commit message
         #[arg(short)]
         message: String,
     },
+    /// Clone a repository into a new directory
     Clone {
+        /// Repository URL
         repo_url: String,
+        /// Directory to clone into
+        directory: Option<PathBuf>,
+        /// Hard-link objects from a repository on the same filesystem
+        #[arg(short, long)]
+        local: bool,
+        /// Borrow the objects of a local repository instead of copying them
+        #[arg(short, long)]
+        shared: bool,
+        /// Borrow objects from this repository instead of fetching them
+        #[arg(long, value_name = "REPOSITORY")]
+        reference: Vec<PathBuf>,
+        #[command(flatten)]
+        shallow: ShallowArgs,
+        /// Make a partial clone leaving out objects (`blob:none`, `blob:limit=<n>`, `tree:<depth>`)
+        #[arg(long, value_name = "FILTER_SPEC")]
+        filter: Option<String>,
     },
+    /// Pack reachable objects into a pack
+    Repack {
+        /// Pack all reachable objects into a single pack
+        #[arg(short)]
+        all: bool,
+        /// Remove redundant packs and packed loose objects
+        #[arg(short)]
+        delete: bool,
+        /// Like `-a`, but keep unreachable objects of removed packs as loose objects
+        #[arg(short = 'A')]
+        unpack_unreachable: bool,
+    },
+    /// Pack refs and objects and prune unreachable objects
+    Gc {
+        /// Prune unreachable loose objects older than this (e.g. `now`, `2.weeks.ago`, `never`)
+        #[arg(long, default_value = "2.weeks.ago")]
+        prune: String,
+    },
+    /// Verify the connectivity and validity of objects
+    Fsck,
+    /// Update remote refs along with associated objects
+    Push {
+        /// Remote name or URL
+        remote: String,
+        /// Refs to push, e.g. `main`, `+main:other` or `:deleted`
+        refspecs: Vec<String>,
+        /// Allow non-fast-forward updates
+        #[arg(short, long)]
+        force: bool,
+        /// Only overwrite remote refs that match the expected value
+        #[arg(long, value_name = "REF[:EXPECT]", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+        force_with_lease: Option<String>,
+    },
+    /// Download objects and refs from another repository
+    Fetch {
+        /// Remote name or URL
+        #[arg(default_value = "origin")]
+        remote: String,
+        /// Refs to fetch, e.g. `main` or `+refs/heads/*:refs/remotes/origin/*`
+        refspecs: Vec<String>,
+        /// Remove remote-tracking refs that no longer exist on the remote
+        #[arg(short, long)]
+        prune: bool,
+        /// Fetch all tags
+        #[arg(short, long)]
+        tags: bool,
+        #[command(flatten)]
+        shallow: ShallowArgs,
+        /// Fetch this many more commits below the current shallow boundary
+        #[arg(long, value_name = "N", conflicts_with = "depth")]
+        deepen: Option<usize>,
+        /// Fetch the complete history of a shallow repository
+        #[arg(long, conflicts_with_all = ["depth", "deepen"])]
+        unshallow: bool,
+    },
+    /// Send objects requested by a fetching client over stdin and stdout
+    UploadPack {
+        #[command(flatten)]
+        server: ServerArgs,
+    },
+    /// Receive objects and ref updates pushed by a client over stdin and stdout
+    ReceivePack {
+        #[command(flatten)]
+        server: ServerArgs,
+    },
+    /// Serve repositories over smart HTTP
+    Serve {
+        /// Address to listen on, e.g. `127.0.0.1:8080`
+        #[arg(long, value_name = "ADDR")]
+        http: String,
+        /// Repository, or directory of repositories, to serve
+        #[arg(default_value = ".")]
+        directory: PathBuf,
+        /// Require basic authentication with these credentials
+        #[arg(long, value_name = "USER:PASSWORD")]
+        auth: Option<String>,
+        /// Refuse pushes
+        #[arg(long)]
+        read_only: bool,
+    },
+    /// Serve repositories over the git:// protocol
+    Daemon {
+        /// Address to listen on
+        #[arg(long, value_name = "ADDR", default_value = "0.0.0.0:9418")]
+        listen: String,
+        /// Repository, or directory of repositories, to serve
+        #[arg(default_value = ".")]
+        directory: PathBuf,
+        /// Serve repositories without a `git-daemon-export-ok` file
+        #[arg(long)]
+        export_all: bool,
+        /// Accept pushes
+        #[arg(long)]
+        enable_receive_pack: bool,
+    },
+    /// Credential helper caching credentials in memory
+    CredentialCache {
+        /// Forget credentials after this many seconds
+        #[arg(long, default_value_t = 900)]
+        timeout: u64,
+        /// Socket of the cache daemon
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        /// `get`, `store`, `erase` or `exit`
+        action: String,
+    },
+    /// Hold the credential cache, started by `credential-cache`
+    #[command(name = "credential-cache--daemon", hide = true)]
+    CredentialCacheDaemon {
+        /// Socket to listen on
+        socket: PathBuf,
+    },
+    /// Move objects and refs by archive
+    Bundle {
+        #[clap(subcommand)]
+        command: BundleCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum BundleCommand {
+    /// Create a bundle of the given refs and the history they need
+    Create {
+        /// Bundle format version, by default 2, or 3 in a SHA-256 repository
+        #[arg(long, value_parser = clap::value_parser!(u32).range(2..=3))]
+        version: Option<u32>,
+        /// Bundle file to write
+        file: PathBuf,
+        /// Refs to include, `^<rev>` or `<rev>..<rev>` to leave out history, `--all`, `--branches` or `--tags`
+        #[arg(required = true, allow_hyphen_values = true)]
+        revs: Vec<String>,
+    },
+    /// Check that a bundle is valid and applies to the repository
+    Verify {
+        /// Bundle file
+        file: PathBuf,
+    },
+    /// List the refs in a bundle
+    ListHeads {
+        /// Bundle file
+        file: PathBuf,
+        /// Only list these refs
+        refnames: Vec<String>,
+    },
+    /// Store the objects in a bundle without updating refs
+    Unbundle {
+        /// Bundle file
+        file: PathBuf,
+        /// Only report these refs
+        refnames: Vec<String>,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct ShallowArgs {
+    /// Only fetch this many commits from the tip of each ref
+    #[arg(long, value_name = "N")]
+    depth: Option<usize>,
+    /// Only fetch commits newer than this date (seconds since the epoch or e.g. `2.weeks.ago`)
+    #[arg(long, value_name = "DATE")]
+    shallow_since: Option<String>,
+    /// Do not fetch commits reachable from this remote branch or tag
+    #[arg(long, value_name = "REF")]
+    shallow_exclude: Vec<String>,
+}
+
+impl ShallowArgs {
+    fn deepen(&self) -> anyhow::Result<transport::Deepen> {
+        let since = self
+            .shallow_since
+            .as_deref()
+            .map(fetch::parse_since)
+            .transpose()?;
+        Ok(transport::Deepen {
+            depth: self.depth,
+            since,
+            not: self.shallow_exclude.clone(),
+            relative: false,
+        })
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ServerArgs {
+    /// Handle a single request without advertising refs first, as over HTTP
+    #[arg(long)]
+    stateless_rpc: bool,
+    /// Only advertise refs and exit
+    #[arg(long)]
+    advertise_refs: bool,
+    /// Repository to serve
+    directory: PathBuf,
 }
 
 #[derive(Debug, Args)]
@@ -66,6 +317,27 @@ pub struct CatFileFlag {
     /// Pretty-print object's contents
     #[arg(short)]
     pretty: bool,
+    /// Show the object's type
+    #[arg(short = 't')]
+    ty: bool,
+    /// Show the object's size
+    #[arg(short)]
+    size: bool,
+    /// Exit with zero status if the object exists, non-zero otherwise
+    #[arg(short)]
+    exists: bool,
+    /// Show the blob converted by the textconv command of its path's diff driver
+    #[arg(long)]
+    textconv: bool,
+    /// Show the blob as checked out, converted by its path's filters
+    #[arg(long)]
+    filters: bool,
+    /// Show the info and contents of the objects named on stdin
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, require_equals = true)]
+    batch: Option<Option<String>>,
+    /// Show the info of the objects named on stdin
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, require_equals = true)]
+    batch_check: Option<Option<String>>,
 }
 
 fn main() -> anyhow::Result<()> {
@@ -73,23 +345,88 @@ fn main() -> anyhow::Result<()> {
 
     let args = Cli::parse();
     match args.command {
-        Command::Init => init::run(),
-        Command::CatFile { flag, object_hash } => {
-            anyhow::ensure!(flag.pretty, "-p must be used");
-            cat_file::run(&object_hash)?;
+        Command::Init { object_format } => init::run(object_format)?,
+        Command::CatFile {
+            flag,
+            batch_all_objects,
+            path,
+            object,
+        } => {
+            let batch = match (flag.batch, flag.batch_check) {
+                (Some(format), _) => Some((format, true)),
+                (_, Some(format)) => Some((format, false)),
+                _ => None,
+            };
+            if let Some((format, contents)) = batch {
+                anyhow::ensure!(object.is_none(), "--batch takes object names on stdin");
+                let format = format.as_deref().unwrap_or(cat_file::DEFAULT_BATCH_FORMAT);
+                let stdout = std::io::stdout().lock();
+                if batch_all_objects {
+                    cat_file::batch_all_objects(stdout, format, contents)?;
+                } else {
+                    cat_file::batch(std::io::stdin().lock(), stdout, format, contents)?;
+                }
+            } else {
+                anyhow::ensure!(
+                    !batch_all_objects,
+                    "--batch-all-objects requires --batch or --batch-check"
+                );
+                let Some(object) = object else {
+                    anyhow::bail!("object name required");
+                };
+                let object_hash = revision::resolve_object(&object)?;
+                if flag.exists {
+                    if !Object::exists(&object_hash)? {
+                        std::process::exit(1);
+                    }
+                } else if flag.ty {
+                    println!("{}", cat_file::object_type(&object_hash)?);
+                } else if flag.size {
+                    println!("{}", cat_file::object_size(&object_hash)?);
+                } else if flag.textconv || flag.filters {
+                    let Some(path) = path.as_deref().or(revision::object_path(&object)) else {
+                        anyhow::bail!("--textconv and --filters require <rev>:<path> or --path");
+                    };
+                    let conversion = if flag.textconv {
+                        cat_file::Conversion::Textconv
+                    } else {
+                        cat_file::Conversion::Filters
+                    };
+                    cat_file::run_converted(&object_hash, path, conversion)?;
+                } else {
+                    cat_file::run(&object_hash)?;
+                }
+            }
         }
         Command::HashObject { write, file } => {
             let hash = hash_object::run(write, file)?;
             println!("{}", hash);
         }
-        Command::LsTree { name_only, object } => {
-            let tree_entries = ls_tree::run(&object)?;
-            for entry in tree_entries {
-                if name_only {
-                    println!("{}", entry.file);
-                } else {
-                    println!("{}", entry)
-                }
+        Command::LsTree {
+            recursive,
+            show_trees,
+            only_trees,
+            long,
+            name_only,
+            abbrev,
+            format,
+            null_terminated,
+            object,
+            paths,
+        } => {
+            let format = match (&format, long, name_only) {
+                (Some(format), _, _) => format.as_str(),
+                (None, true, _) => ls_tree::LONG_FORMAT,
+                (None, _, true) => ls_tree::NAME_ONLY_FORMAT,
+                _ => ls_tree::DEFAULT_FORMAT,
+            };
+            let terminator = if null_terminated { b'\0' } else { b'\n' };
+            let entries = ls_tree::run(&object, &paths, recursive, show_trees, only_trees)?;
+            let mut stdout = std::io::stdout().lock();
+            for entry in entries {
+                let line = ls_tree::format_entry(&entry, format, abbrev, !null_terminated)?;
+                stdout.write_all(&line)?;
+                stdout.write_all(&[terminator])?;
             }
         }
         Command::WriteTree => {
@@ -101,13 +438,195 @@ fn main() -> anyhow::Result<()> {
             parent_commit_hash,
             message,
         } => {
-            commit_tree::run(&commit_hash, &parent_commit_hash, &message)?;
+            let store = object_store::repository()?;
+            let hash = commit_tree::run(&store, &commit_hash, &parent_commit_hash, &message)?;
+            println!("{hash}");
         }
         Command::Clone {
-            repo_url: repository_url,
+            repo_url,
+            directory,
+            local,
+            shared,
+            reference,
+            shallow,
+            filter,
         } => {
-            clone::clone(repository_url);
+            let filter = filter.as_deref().map(ObjectFilter::parse).transpose()?;
+            clone::run(
+                &repo_url,
+                directory.as_deref(),
+                local,
+                shared,
+                &reference,
+                &shallow.deepen()?,
+                filter.as_ref(),
+            )?;
         }
+        Command::Repack {
+            all,
+            delete,
+            unpack_unreachable,
+        } => {
+            repack::run(all || unpack_unreachable, delete, unpack_unreachable)?;
+        }
+        Command::Gc { prune } => {
+            let prune = gc::parse_expiry(&prune)?;
+            gc::run(prune)?;
+        }
+        Command::Fsck => {
+            let issues = fsck::run()?;
+            for issue in &issues {
+                println!("{}", issue);
+            }
+            anyhow::ensure!(
+                !issues.iter().any(|issue| issue.is_error()),
+                "object store is corrupt"
+            );
+        }
+        Command::Push {
+            remote,
+            refspecs,
+            force,
+            force_with_lease,
+        } => {
+            let (url, updates) = push::run(&remote, &refspecs, force, force_with_lease.as_deref())?;
+            println!("To {}", url);
+            for update in &updates {
+                println!("{}", update);
+            }
+            anyhow::ensure!(
+                !updates.iter().any(|update| update.is_error()),
+                "failed to push some refs to {}",
+                url
+            );
+        }
+        Command::Fetch {
+            remote,
+            refspecs,
+            prune,
+            tags,
+            shallow,
+            deepen,
+            unshallow,
+        } => {
+            let mut options = shallow.deepen()?;
+            if let Some(depth) = deepen {
+                options.depth = Some(depth);
+                options.relative = true;
+            }
+            if unshallow {
+                anyhow::ensure!(
+                    mygit::shallow::is_shallow(),
+                    "--unshallow on a complete repository does not make sense"
+                );
+                options.depth = Some(transport::INFINITE_DEPTH);
+            }
+            let (url, fetched) = fetch::run(&remote, &refspecs, prune, tags, &options)?;
+            let changed: Vec<_> = fetched
+                .iter()
+                .filter(|fetched| fetched.status != fetch::FetchStatus::UpToDate)
+                .collect();
+            if !changed.is_empty() {
+                println!("From {}", url);
+            }
+            for fetched in &changed {
+                println!("{}", fetched);
+            }
+            anyhow::ensure!(
+                !fetched.iter().any(|fetched| fetched.is_error()),
+                "some local refs could not be updated"
+            );
+        }
+        Command::UploadPack { server } => {
+            upload_pack::run(
+                &server.directory,
+                server.stateless_rpc,
+                server.advertise_refs,
+            )?;
+        }
+        Command::ReceivePack { server } => {
+            receive_pack::run(
+                &server.directory,
+                server.stateless_rpc,
+                server.advertise_refs,
+            )?;
+        }
+        Command::Serve {
+            http,
+            directory,
+            auth,
+            read_only,
+        } => {
+            serve::run(&http, &directory, auth.as_deref(), read_only)?;
+        }
+        Command::Daemon {
+            listen,
+            directory,
+            export_all,
+            enable_receive_pack,
+        } => {
+            daemon::run(&listen, &directory, export_all, enable_receive_pack)?;
+        }
+        Command::CredentialCache {
+            timeout,
+            socket,
+            action,
+        } => {
+            let socket = match socket {
+                Some(socket) => socket,
+                None => credential_cache::default_socket()?,
+            };
+            credential_cache::run(&action, timeout, &socket)?;
+        }
+        Command::CredentialCacheDaemon { socket } => {
+            credential_cache::daemon(&socket)?;
+        }
+        Command::Bundle { command } => match command {
+            BundleCommand::Create {
+                version,
+                file,
+                revs,
+            } => {
+                bundle::create(&file, &revs, version)?;
+            }
+            BundleCommand::Verify { file } => {
+                let bundle = bundle::verify(&file)?;
+                println!("The bundle contains {}:", count_refs(bundle.refs.len()));
+                for (hash, name) in &bundle.refs {
+                    println!("{hash} {name}");
+                }
+                if bundle.prerequisites.is_empty() {
+                    println!("The bundle records a complete history.");
+                } else {
+                    println!(
+                        "The bundle requires {}:",
+                        count_refs(bundle.prerequisites.len())
+                    );
+                    for (hash, comment) in &bundle.prerequisites {
+                        println!("{hash} {comment}");
+                    }
+                }
+                eprintln!("{} is okay", file.display());
+            }
+            BundleCommand::ListHeads { file, refnames } => {
+                for (hash, name) in bundle::list_heads(&file, &refnames)? {
+                    println!("{hash} {name}");
+                }
+            }
+            BundleCommand::Unbundle { file, refnames } => {
+                for (hash, name) in bundle::unbundle(&file, &refnames)? {
+                    println!("{hash} {name}");
+                }
+            }
+        },
     };
     Ok(())
 }
+
+fn count_refs(count: usize) -> String {
+    if count == 1 {
+        "this ref".to_owned()
+    } else {
+        format!("these {count} refs")
+    }
+}
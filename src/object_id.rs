@@ -0,0 +1,299 @@
+use crate::common::git_dir;
+use crate::config::Config;
+use crate::error::{Context, Error};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::{fmt, io, path::PathBuf, str::FromStr, sync::Mutex};
+
+/// The binary name of an object, a SHA-1 or SHA-256 hash depending on the
+/// repository's object format.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ObjectId {
+    Sha1([u8; 20]),
+    Sha256([u8; 32]),
+}
+
+impl ObjectId {
+    /// The id made of the raw hash `bytes`, e.g. as stored in trees and packs.
+    /// The format is chosen by the length.
+    pub fn from_bytes(bytes: &[u8]) -> crate::Result<ObjectId> {
+        match bytes.len() {
+            20 => Ok(ObjectId::Sha1(bytes.try_into()?)),
+            32 => Ok(ObjectId::Sha256(bytes.try_into()?)),
+            _ => Err(Error::InvalidHash(hex::encode(bytes))),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            ObjectId::Sha1(bytes) => bytes,
+            ObjectId::Sha256(bytes) => bytes,
+        }
+    }
+
+    pub fn format(&self) -> ObjectFormat {
+        match self {
+            ObjectId::Sha1(_) => ObjectFormat::Sha1,
+            ObjectId::Sha256(_) => ObjectFormat::Sha256,
+        }
+    }
+
+    /// Whether this is the all-zero id, used on the wire and in reflogs for
+    /// a ref that does not exist.
+    pub fn is_null(&self) -> bool {
+        self.as_bytes().iter().all(|&byte| byte == 0)
+    }
+
+    /// The first `len` hex digits of the id.
+    pub fn abbrev(&self, len: usize) -> String {
+        let mut hex = self.to_string();
+        hex.truncate(len);
+        hex
+    }
+
+    /// Whether the hex form of the id starts with `prefix`, an abbreviated
+    /// object name. Matching ignores case.
+    pub fn has_prefix(&self, prefix: &str) -> bool {
+        let bytes = self.as_bytes();
+        prefix.len() <= bytes.len() * 2
+            && prefix.bytes().enumerate().all(|(idx, c)| {
+                let nibble = (bytes[idx / 2] >> if idx % 2 == 0 { 4 } else { 0 }) & 0xf;
+                char::from_digit(nibble.into(), 16) == Some(c.to_ascii_lowercase() as char)
+            })
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = Error;
+
+    /// Parse the 40 (SHA-1) or 64 (SHA-256) hex digits of a full object name.
+    fn from_str(hex: &str) -> crate::Result<ObjectId> {
+        let invalid = |_| Error::InvalidHash(hex.to_owned());
+        match hex.len() {
+            40 => {
+                let mut bytes = [0; 20];
+                hex::decode_to_slice(hex, &mut bytes).map_err(invalid)?;
+                Ok(ObjectId::Sha1(bytes))
+            }
+            64 => {
+                let mut bytes = [0; 32];
+                hex::decode_to_slice(hex, &mut bytes).map_err(invalid)?;
+                Ok(ObjectId::Sha256(bytes))
+            }
+            _ => Err(Error::InvalidHash(hex.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.as_bytes() {
+            write!(f, "{byte:02x}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ObjectId({self})")
+    }
+}
+
+/// The hash function a repository names its objects with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ObjectFormat {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl ObjectFormat {
+    /// The object format of the current repository, set by
+    /// `extensions.objectFormat` and SHA-1 if that is missing. A config that
+    /// cannot be read or names an unknown format is an error rather than
+    /// taken as SHA-1, as reading a SHA-256 repository as SHA-1 would garble
+    /// every object name.
+    pub fn current() -> crate::Result<ObjectFormat> {
+        // The format cannot change once a repository exists, so it is read
+        // once per git directory.
+        static CURRENT: Mutex<Option<(PathBuf, ObjectFormat)>> = Mutex::new(None);
+
+        let git_dir = git_dir();
+        let mut current = CURRENT.lock().unwrap();
+        match &*current {
+            Some((dir, format)) if *dir == git_dir => Ok(*format),
+            _ => {
+                let format = Config::read()
+                    .and_then(|config| ObjectFormat::from_config(&config))
+                    .context("read object format")?;
+                // Not cached before the repository is created, e.g. by clone.
+                if git_dir.is_dir() {
+                    *current = Some((git_dir, format));
+                }
+                Ok(format)
+            }
+        }
+    }
+
+    /// The object format set in the repository configuration `config`.
+    pub fn from_config(config: &Config) -> crate::Result<ObjectFormat> {
+        config
+            .get("extensions.objectFormat")
+            .map_or(Ok(ObjectFormat::Sha1), str::parse)
+    }
+
+    /// Length of an object id in bytes.
+    pub fn hash_len(&self) -> usize {
+        match self {
+            ObjectFormat::Sha1 => 20,
+            ObjectFormat::Sha256 => 32,
+        }
+    }
+
+    /// Length of an object id in hex digits.
+    pub fn hex_len(&self) -> usize {
+        self.hash_len() * 2
+    }
+
+    /// The all-zero id of this format.
+    pub fn null(&self) -> ObjectId {
+        match self {
+            ObjectFormat::Sha1 => ObjectId::Sha1([0; 20]),
+            ObjectFormat::Sha256 => ObjectId::Sha256([0; 32]),
+        }
+    }
+
+    /// Hash `data`, e.g. an object with its header or the contents of a pack.
+    pub fn hash(&self, data: &[u8]) -> ObjectId {
+        match self {
+            ObjectFormat::Sha1 => ObjectId::Sha1(Sha1::digest(data).into()),
+            ObjectFormat::Sha256 => ObjectId::Sha256(Sha256::digest(data).into()),
+        }
+    }
+
+    /// A hasher for data that is not in memory at once, e.g. a large blob.
+    pub fn hasher(&self) -> Hasher {
+        match self {
+            ObjectFormat::Sha1 => Hasher::Sha1(Sha1::new()),
+            ObjectFormat::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+}
+
+/// Incremental hashing with the hash function of an [`ObjectFormat`]. Data is
+/// fed with [`io::Write`].
+#[derive(Clone)]
+pub enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub fn finish(self) -> ObjectId {
+        match self {
+            Hasher::Sha1(hasher) => ObjectId::Sha1(hasher.finalize().into()),
+            Hasher::Sha256(hasher) => ObjectId::Sha256(hasher.finalize().into()),
+        }
+    }
+}
+
+impl io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Hasher::Sha1(hasher) => hasher.update(buf),
+            Hasher::Sha256(hasher) => hasher.update(buf),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl FromStr for ObjectFormat {
+    type Err = Error;
+
+    fn from_str(name: &str) -> crate::Result<ObjectFormat> {
+        match name {
+            "sha1" => Ok(ObjectFormat::Sha1),
+            "sha256" => Ok(ObjectFormat::Sha256),
+            _ => crate::bail!("unknown object format {name:?}"),
+        }
+    }
+}
+
+impl fmt::Display for ObjectFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjectFormat::Sha1 => write!(f, "sha1"),
+            ObjectFormat::Sha256 => write!(f, "sha256"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1: &str = "ce013625030ba8dba906f756967f9e9ca394464a";
+    const SHA256: &str = "2cf8d83d9ee29543b34a87727421fdecb7e3f3a183d337639025de576db9ebb4";
+
+    #[test]
+    fn parses_full_object_names() {
+        let sha1: ObjectId = SHA1.parse().unwrap();
+        assert_eq!(sha1.format(), ObjectFormat::Sha1);
+        assert_eq!(sha1.to_string(), SHA1);
+        assert_eq!(ObjectId::from_bytes(sha1.as_bytes()).unwrap(), sha1);
+        let sha256: ObjectId = SHA256.parse().unwrap();
+        assert_eq!(sha256.format(), ObjectFormat::Sha256);
+        assert_eq!(sha256.to_string(), SHA256);
+        assert_eq!(
+            SHA1.to_uppercase().parse::<ObjectId>().unwrap(),
+            sha1,
+            "hex digits are case-insensitive"
+        );
+
+        for invalid in [
+            "",
+            "ce0136",
+            &SHA1[1..],
+            &format!("{SHA1}0"),
+            &SHA1.replace('c', "g"),
+        ] {
+            assert!(
+                matches!(invalid.parse::<ObjectId>(), Err(Error::InvalidHash(_))),
+                "{invalid:?}"
+            );
+        }
+        assert!(ObjectId::from_bytes(&[0; 19]).is_err());
+    }
+
+    #[test]
+    fn hashes_blobs() {
+        assert_eq!(
+            ObjectFormat::Sha1.hash(b"blob 6\0hello\n").to_string(),
+            SHA1
+        );
+        assert_eq!(
+            ObjectFormat::Sha256.hash(b"blob 6\0hello\n").to_string(),
+            SHA256
+        );
+        assert!(ObjectFormat::Sha256.null().is_null());
+        assert_eq!(ObjectFormat::Sha1.null().to_string(), "0".repeat(40));
+    }
+
+    #[test]
+    fn matches_prefixes() {
+        let hash: ObjectId = SHA1.parse().unwrap();
+        for prefix in ["", "c", "ce0", "CE01362", &SHA1[..39], SHA1] {
+            assert!(hash.has_prefix(prefix), "{prefix:?}");
+        }
+        for prefix in ["e", "ce1", "ce013g", &format!("{SHA1}0")] {
+            assert!(!hash.has_prefix(prefix), "{prefix:?}");
+        }
+        assert_eq!(hash.abbrev(7), "ce01362");
+    }
+}
@@ -0,0 +1,557 @@
+use crate::common::{git_dir, inflate_loose, Object, ObjectType};
+use crate::error::Context;
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::pack::{self, Pack};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/// Alternates are followed this many levels deep, like git does.
+const MAX_ALTERNATE_DEPTH: usize = 5;
+
+/// A database of objects addressed by their hash.
+pub trait ObjectStore {
+    /// Read the object `hash`, `None` if it is not in the store. The hash is
+    /// not verified.
+    fn read(&self, hash: &ObjectId) -> crate::Result<Option<Object>>;
+
+    /// Store `object` and return its hash.
+    fn write(&self, object: &Object) -> crate::Result<ObjectId>;
+
+    /// Store an object of type `ty` whose `size` bytes of contents are read
+    /// from `reader`. Backends that can are expected to stream it rather
+    /// than hold it in memory.
+    fn write_stream(
+        &self,
+        ty: ObjectType,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> crate::Result<ObjectId> {
+        let mut contents = Vec::new();
+        reader.take(size).read_to_end(&mut contents)?;
+        crate::ensure!(
+            contents.len() as u64 == size,
+            "object contents ended after {} of {size} bytes",
+            contents.len()
+        );
+
+        self.write(&Object { ty, contents })
+    }
+
+    /// Hashes of all objects in the store.
+    fn list(&self) -> crate::Result<Vec<ObjectId>>;
+
+    /// Hashes of the objects whose names start with the lowercase hex digits
+    /// `prefix`, e.g. to resolve an abbreviated name.
+    fn find_prefix(&self, prefix: &str) -> crate::Result<Vec<ObjectId>> {
+        let mut hashes = self.list()?;
+        hashes.retain(|hash| hash.has_prefix(prefix));
+
+        Ok(hashes)
+    }
+
+    /// Type and size of the object `hash`, reading as little of its
+    /// contents as the backend allows.
+    fn read_header(&self, hash: &ObjectId) -> crate::Result<Option<(ObjectType, u64)>> {
+        Ok(self
+            .read(hash)?
+            .map(|object| (object.ty, object.contents.len() as u64)))
+    }
+
+    fn exists(&self, hash: &ObjectId) -> crate::Result<bool> {
+        Ok(self.read_header(hash)?.is_some())
+    }
+}
+
+/// The object store of the current repository: its own objects followed by
+/// those of its alternates. New objects are written to its own.
+pub fn repository() -> crate::Result<LayeredStore> {
+    let format = ObjectFormat::current()?;
+    let layers = object_dirs()?
+        .into_iter()
+        .map(|dir| Box::new(FileStore::new(dir, format)) as Box<dyn ObjectStore>)
+        .collect();
+
+    Ok(LayeredStore::new(format, layers))
+}
+
+/// The objects directory of the current repository followed by those of its
+/// alternates.
+pub fn object_dirs() -> crate::Result<Vec<PathBuf>> {
+    let objects_dir = git_dir().join("objects");
+    let mut dirs = vec![objects_dir.clone()];
+    dirs.extend(alternates(&objects_dir)?);
+
+    Ok(dirs)
+}
+
+/// Objects directories the repository with the objects directory
+/// `objects_dir` borrows objects from: those listed in its
+/// `info/alternates`, relative to `objects_dir` unless absolute, and in
+/// `GIT_ALTERNATE_OBJECT_DIRECTORIES`, followed by their own alternates.
+/// Directories that do not exist are skipped.
+pub fn alternates(objects_dir: &Path) -> crate::Result<Vec<PathBuf>> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    let mut seen = vec![fs::canonicalize(objects_dir).unwrap_or_else(|_| objects_dir.to_owned())];
+    let mut pending: Vec<(PathBuf, usize)> = read_alternates(objects_dir)?
+        .into_iter()
+        .map(|dir| (dir, 1))
+        .collect();
+    if let Some(env) = std::env::var_os("GIT_ALTERNATE_OBJECT_DIRECTORIES") {
+        pending.extend(std::env::split_paths(&env).map(|dir| (dir, 1)));
+    }
+    pending.reverse();
+    while let Some((dir, depth)) = pending.pop() {
+        let Ok(canonical) = fs::canonicalize(&dir) else {
+            log::warn!("object directory {} does not exist", dir.display());
+            continue;
+        };
+        if seen.contains(&canonical) {
+            continue;
+        }
+        seen.push(canonical);
+        if depth < MAX_ALTERNATE_DEPTH {
+            let nested = read_alternates(&dir)?;
+            pending.extend(nested.into_iter().rev().map(|nested| (nested, depth + 1)));
+        } else {
+            log::warn!("{}: ignoring alternates nested too deeply", dir.display());
+        }
+        dirs.push(dir);
+    }
+
+    Ok(dirs)
+}
+
+/// Add `alternate`, an objects directory, to the alternates of the current
+/// repository.
+pub fn add_alternate(alternate: &Path) -> crate::Result<()> {
+    let path = git_dir().join("objects/info/alternates");
+    let mut contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).context("read alternates"),
+    };
+    let alternate = alternate.to_str().context("alternate path utf-8")?;
+    contents.push_str(&format!("{alternate}\n"));
+    fs::create_dir_all(path.parent().context("alternates dir")?)?;
+    fs::write(&path, contents).context("write alternates")?;
+
+    Ok(())
+}
+
+/// The directories listed in `objects_dir/info/alternates`, skipping
+/// comments and blank lines.
+fn read_alternates(objects_dir: &Path) -> crate::Result<Vec<PathBuf>> {
+    let contents = match fs::read_to_string(objects_dir.join("info/alternates")) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).context("read alternates"),
+    };
+
+    Ok(contents
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| objects_dir.join(line))
+        .collect())
+}
+
+/// The on-disk object store of a repository: loose objects in
+/// `objects/xx/...` and the packs in `objects/pack`.
+pub struct FileStore {
+    objects_dir: PathBuf,
+    format: ObjectFormat,
+}
+
+impl FileStore {
+    /// The store in `objects_dir`, whose objects are hashed with `format`.
+    pub fn new(objects_dir: impl Into<PathBuf>, format: ObjectFormat) -> FileStore {
+        FileStore {
+            objects_dir: objects_dir.into(),
+            format,
+        }
+    }
+
+    /// The object store of the current repository.
+    pub fn current() -> crate::Result<FileStore> {
+        Ok(FileStore::new(
+            git_dir().join("objects"),
+            ObjectFormat::current()?,
+        ))
+    }
+
+    /// Path of the loose object `hash`.
+    pub fn loose_path(&self, hash: &ObjectId) -> PathBuf {
+        let hex = hash.to_string();
+
+        self.objects_dir.join(&hex[..2]).join(&hex[2..])
+    }
+
+    pub fn packs(&self) -> crate::Result<Vec<Pack>> {
+        Pack::all_in(&self.objects_dir.join("pack"), self.format)
+    }
+
+    /// Write `object` as a loose object, even if it is also packed.
+    pub fn write_loose(&self, object: &Object) -> crate::Result<ObjectId> {
+        let size = object.contents.len() as u64;
+        self.write_loose_stream(object.ty, size, &mut &object.contents[..], false)
+    }
+
+    /// The object is hashed and compressed in one pass into a temporary
+    /// file, which is renamed once its name is known. An existing loose
+    /// copy is kept, as is a packed one if `skip_packed`.
+    fn write_loose_stream(
+        &self,
+        ty: ObjectType,
+        size: u64,
+        reader: &mut dyn Read,
+        skip_packed: bool,
+    ) -> crate::Result<ObjectId> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let tmp_path = self.objects_dir.join(format!(
+            "tmp_obj_{}_{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = fs::File::create(&tmp_path).context("create temporary object file")?;
+        let mut encoder = flate2::write::ZlibEncoder::new(file, flate2::Compression::new(1));
+        let hash = match Object::stream(self.format, ty, size, reader, &mut encoder)
+            .and_then(|hash| Ok((hash, encoder.finish()?)))
+        {
+            Ok((hash, _)) => hash,
+            Err(err) => {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(err);
+            }
+        };
+
+        let object_file_path = self.loose_path(&hash);
+        log::debug!("Writing {ty} to {:?}", object_file_path);
+        let packed = skip_packed && self.packs()?.iter().any(|pack| pack.contains(&hash));
+        if packed || object_file_path.exists() {
+            // Objects never change, so an existing copy is kept.
+            fs::remove_file(&tmp_path).context("remove temporary object file")?;
+        } else {
+            if let Some(object_dir) = object_file_path.parent() {
+                fs::create_dir_all(object_dir).context("create object dir")?;
+            }
+            fs::rename(&tmp_path, object_file_path).context("write object to file")?;
+        }
+
+        Ok(hash)
+    }
+}
+
+impl ObjectStore for FileStore {
+    fn read(&self, hash: &ObjectId) -> crate::Result<Option<Object>> {
+        let object_path = self.loose_path(hash);
+        if object_path.exists() {
+            return Object::read_loose(object_path).map(Some);
+        }
+        for pack in self.packs()? {
+            if let Some(object) = pack.read(hash)? {
+                return Ok(Some(object));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn write(&self, object: &Object) -> crate::Result<ObjectId> {
+        let hash = object.hash_with(self.format)?;
+        if self.exists(&hash)? {
+            return Ok(hash);
+        }
+        let size = object.contents.len() as u64;
+        self.write_loose_stream(object.ty, size, &mut &object.contents[..], true)
+    }
+
+    /// Objects already stored, loose or packed, are hashed but not written.
+    fn write_stream(
+        &self,
+        ty: ObjectType,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> crate::Result<ObjectId> {
+        self.write_loose_stream(ty, size, reader, true)
+    }
+
+    fn list(&self) -> crate::Result<Vec<ObjectId>> {
+        let mut hashes = BTreeSet::new();
+        if self.objects_dir.is_dir() {
+            hashes.extend(pack::loose_objects_in(&self.objects_dir)?);
+        }
+        for pack in self.packs()? {
+            hashes.extend(pack.hashes());
+        }
+
+        Ok(hashes.into_iter().collect())
+    }
+
+    /// Only the loose objects directory for the first two digits and the
+    /// matching part of each pack index are searched.
+    fn find_prefix(&self, prefix: &str) -> crate::Result<Vec<ObjectId>> {
+        let Some(dir_name) = prefix.get(..2) else {
+            let mut hashes = self.list()?;
+            hashes.retain(|hash| hash.has_prefix(prefix));
+            return Ok(hashes);
+        };
+        let mut hashes = BTreeSet::new();
+        match fs::read_dir(self.objects_dir.join(dir_name)) {
+            Ok(entries) => {
+                for entry in entries {
+                    let file = entry?.file_name();
+                    let Some(suffix) = file.to_str() else {
+                        continue;
+                    };
+                    if let Ok(hash) = format!("{dir_name}{suffix}").parse::<ObjectId>() {
+                        if hash.has_prefix(prefix) {
+                            hashes.insert(hash);
+                        }
+                    }
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err).context("read objects dir"),
+        }
+        for pack in self.packs()? {
+            hashes.extend(pack.index.find_prefix(prefix));
+        }
+
+        Ok(hashes.into_iter().collect())
+    }
+
+    fn read_header(&self, hash: &ObjectId) -> crate::Result<Option<(ObjectType, u64)>> {
+        let object_path = self.loose_path(hash);
+        if object_path.exists() {
+            let (ty, size, _) = inflate_loose(&object_path)?;
+            return Ok(Some((ty, size)));
+        }
+        for pack in self.packs()? {
+            if let Some(header) = pack.read_header(hash)? {
+                return Ok(Some(header));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn exists(&self, hash: &ObjectId) -> crate::Result<bool> {
+        if self.loose_path(hash).exists() {
+            return Ok(true);
+        }
+
+        Ok(self.packs()?.iter().any(|pack| pack.contains(hash)))
+    }
+}
+
+/// Objects kept in memory only, e.g. to build trees and commits without
+/// touching the disk.
+pub struct MemoryStore {
+    format: ObjectFormat,
+    objects: Mutex<HashMap<ObjectId, Object>>,
+}
+
+impl MemoryStore {
+    /// An empty store hashing objects with `format`.
+    pub fn new(format: ObjectFormat) -> MemoryStore {
+        MemoryStore {
+            format,
+            objects: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl ObjectStore for MemoryStore {
+    fn read(&self, hash: &ObjectId) -> crate::Result<Option<Object>> {
+        Ok(self.objects.lock().unwrap().get(hash).cloned())
+    }
+
+    fn write(&self, object: &Object) -> crate::Result<ObjectId> {
+        let hash = object.hash_with(self.format)?;
+        self.objects
+            .lock()
+            .unwrap()
+            .entry(hash)
+            .or_insert_with(|| object.clone());
+
+        Ok(hash)
+    }
+
+    fn list(&self) -> crate::Result<Vec<ObjectId>> {
+        let mut hashes: Vec<ObjectId> = self.objects.lock().unwrap().keys().copied().collect();
+        hashes.sort();
+
+        Ok(hashes)
+    }
+
+    fn exists(&self, hash: &ObjectId) -> crate::Result<bool> {
+        Ok(self.objects.lock().unwrap().contains_key(hash))
+    }
+}
+
+/// A stack of stores read in order, with writes of objects none of them has
+/// going to the first one. E.g. a [`MemoryStore`] over the repository's
+/// [`FileStore`] builds new objects on top of the existing ones without
+/// writing them to disk.
+pub struct LayeredStore {
+    format: ObjectFormat,
+    layers: Vec<Box<dyn ObjectStore>>,
+}
+
+impl LayeredStore {
+    /// Layers whose objects are hashed with `format`, first to last.
+    pub fn new(format: ObjectFormat, layers: Vec<Box<dyn ObjectStore>>) -> LayeredStore {
+        LayeredStore { format, layers }
+    }
+}
+
+impl ObjectStore for LayeredStore {
+    fn read(&self, hash: &ObjectId) -> crate::Result<Option<Object>> {
+        for layer in &self.layers {
+            if let Some(object) = layer.read(hash)? {
+                return Ok(Some(object));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn write(&self, object: &Object) -> crate::Result<ObjectId> {
+        let hash = object.hash_with(self.format)?;
+        if self.exists(&hash)? {
+            return Ok(hash);
+        }
+        self.layers
+            .first()
+            .context("layered object store has no layers")?
+            .write(object)
+    }
+
+    fn write_stream(
+        &self,
+        ty: ObjectType,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> crate::Result<ObjectId> {
+        self.layers
+            .first()
+            .context("layered object store has no layers")?
+            .write_stream(ty, size, reader)
+    }
+
+    fn list(&self) -> crate::Result<Vec<ObjectId>> {
+        let mut hashes = BTreeSet::new();
+        for layer in &self.layers {
+            hashes.extend(layer.list()?);
+        }
+
+        Ok(hashes.into_iter().collect())
+    }
+
+    fn find_prefix(&self, prefix: &str) -> crate::Result<Vec<ObjectId>> {
+        let mut hashes = BTreeSet::new();
+        for layer in &self.layers {
+            hashes.extend(layer.find_prefix(prefix)?);
+        }
+
+        Ok(hashes.into_iter().collect())
+    }
+
+    fn read_header(&self, hash: &ObjectId) -> crate::Result<Option<(ObjectType, u64)>> {
+        for layer in &self.layers {
+            if let Some(header) = layer.read_header(hash)? {
+                return Ok(Some(header));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn exists(&self, hash: &ObjectId) -> crate::Result<bool> {
+        for layer in &self.layers {
+            if layer.exists(hash)? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{write_tree, Commit, FileMode, TreeEntry};
+
+    #[test]
+    fn builds_commit_in_memory() {
+        let store = MemoryStore::new(ObjectFormat::Sha1);
+        let blob = store
+            .write_stream(ObjectType::Blob, 6, &mut &b"hello\n"[..])
+            .unwrap();
+        assert_eq!(blob.to_string(), "ce013625030ba8dba906f756967f9e9ca394464a");
+        let tree = write_tree(
+            &store,
+            vec![TreeEntry {
+                mode: FileMode::RegularFile,
+                ty: ObjectType::Blob,
+                hash: blob,
+                file: "hello.txt".to_owned(),
+            }],
+        )
+        .unwrap();
+        assert_eq!(tree.to_string(), "aaa96ced2d9a1c8e72c56b253a0e2fe78393feb7");
+        let ident = "John Doe <john@doe.com> 1710605448 +0100";
+        let commit = Commit {
+            tree,
+            parents: Vec::new(),
+            author: ident.to_owned(),
+            committer: ident.to_owned(),
+            message: "Initial commit\n".to_owned(),
+        };
+        let hash = commit.write(&store).unwrap();
+        assert_eq!(hash.to_string(), "8a1dd8eebc01a095d75439b086ffc7119dfca805");
+
+        assert_eq!(store.list().unwrap().len(), 3);
+        let object = store.read(&hash).unwrap().unwrap();
+        assert_eq!(object.ty, ObjectType::Commit);
+        assert_eq!(Commit::parse(&object.contents).unwrap().tree, tree);
+    }
+
+    #[test]
+    fn layered_store_writes_to_first_layer() {
+        let bottom = MemoryStore::new(ObjectFormat::Sha1);
+        let blob = Object {
+            ty: ObjectType::Blob,
+            contents: b"hello\n".to_vec(),
+        };
+        let hash = bottom.write(&blob).unwrap();
+        let layered = LayeredStore::new(
+            ObjectFormat::Sha1,
+            vec![
+                Box::new(MemoryStore::new(ObjectFormat::Sha1)),
+                Box::new(bottom),
+            ],
+        );
+        assert!(layered.exists(&hash).unwrap());
+        assert_eq!(layered.write(&blob).unwrap(), hash);
+        assert!(!layered.layers[0].exists(&hash).unwrap());
+
+        let tree = Object {
+            ty: ObjectType::Tree,
+            contents: Vec::new(),
+        };
+        let tree = layered.write(&tree).unwrap();
+        assert_eq!(layered.list().unwrap().len(), 2);
+        assert!(layered.layers[0].exists(&tree).unwrap());
+        assert!(!layered.layers[1].exists(&tree).unwrap());
+    }
+}
@@ -0,0 +1,1218 @@
+use crate::common::{git_dir, zlib_encode, Object, ObjectType};
+use crate::error::{Context, Error};
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::object_store::{self, ObjectStore};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Directory holding the packs of the object store.
+pub fn pack_dir() -> PathBuf {
+    git_dir().join("objects/pack")
+}
+
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+const OBJ_TAG: u8 = 4;
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+/// Parsed version 2 pack index (`.idx` file).
+#[derive(Debug)]
+pub struct PackIndex {
+    /// Object hashes, sorted.
+    pub hashes: Vec<ObjectId>,
+    pub crcs: Vec<u32>,
+    pub offsets: Vec<u64>,
+    pub pack_checksum: ObjectId,
+}
+
+impl PackIndex {
+    /// Parse an index of objects named with the object format `format`.
+    pub fn parse(data: &[u8], format: ObjectFormat) -> crate::Result<PackIndex> {
+        let hash_len = format.hash_len();
+        crate::ensure!(
+            data.len() >= 8 + 256 * 4 + 2 * hash_len && data[..4] == b"\xfftOc"[..],
+            CorruptObject,
+            "unsupported pack index"
+        );
+        let version = u32::from_be_bytes(data[4..8].try_into()?);
+        crate::ensure!(
+            version == 2,
+            CorruptObject,
+            "unsupported pack index version {version}"
+        );
+
+        let fanout = &data[8..8 + 256 * 4];
+        let count = u32::from_be_bytes(fanout[255 * 4..].try_into()?) as usize;
+        let mut pos = 8 + 256 * 4;
+        crate::ensure!(
+            data.len() >= pos + count * (hash_len + 8) + 2 * hash_len,
+            CorruptObject,
+            "truncated pack index"
+        );
+
+        let hashes = data[pos..pos + count * hash_len]
+            .chunks_exact(hash_len)
+            .map(ObjectId::from_bytes)
+            .collect::<crate::Result<_>>()?;
+        pos += count * hash_len;
+        let crcs = data[pos..pos + count * 4]
+            .chunks_exact(4)
+            .map(|c| u32::from_be_bytes(c.try_into().unwrap()))
+            .collect();
+        pos += count * 4;
+        let small_offsets: Vec<u32> = data[pos..pos + count * 4]
+            .chunks_exact(4)
+            .map(|c| u32::from_be_bytes(c.try_into().unwrap()))
+            .collect();
+        pos += count * 4;
+        let large_offsets = &data[pos..data.len() - 2 * hash_len];
+        let offsets = small_offsets
+            .into_iter()
+            .map(|offset| {
+                if offset & 0x8000_0000 == 0 {
+                    return Ok(offset as u64);
+                }
+                let idx = (offset & 0x7fff_ffff) as usize * 8;
+                let large = large_offsets
+                    .get(idx..idx + 8)
+                    .context("large offset out of range")?;
+                Ok(u64::from_be_bytes(large.try_into().unwrap()))
+            })
+            .collect::<crate::Result<Vec<u64>>>()?;
+        let pack_checksum =
+            ObjectId::from_bytes(&data[data.len() - 2 * hash_len..data.len() - hash_len])?;
+
+        Ok(PackIndex {
+            hashes,
+            crcs,
+            offsets,
+            pack_checksum,
+        })
+    }
+
+    /// Hashes in the index starting with the hex digits `prefix`, found by
+    /// binary search as the hashes are sorted.
+    pub fn find_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = ObjectId> + 'a {
+        let start = self.hashes.partition_point(|hash| {
+            let hex = hash.to_string();
+            hex[..prefix.len().min(hex.len())] < *prefix
+        });
+        self.hashes[start..]
+            .iter()
+            .take_while(|hash| hash.has_prefix(prefix))
+            .copied()
+    }
+
+    /// Return the offset of the object identified by `hash` in the pack.
+    pub fn find(&self, hash: &ObjectId) -> Option<u64> {
+        self.hashes
+            .binary_search(hash)
+            .ok()
+            .map(|idx| self.offsets[idx])
+    }
+
+    /// Serialize a version 2 index for the given `(hash, crc, offset)` entries.
+    pub fn write(
+        mut entries: Vec<(ObjectId, u32, u64)>,
+        pack_checksum: &ObjectId,
+        mut writer: impl Write,
+    ) -> crate::Result<()> {
+        entries.sort_by_key(|entry| entry.0);
+
+        let mut buf = Vec::new();
+        buf.extend(b"\xfftOc");
+        buf.extend(2u32.to_be_bytes());
+        let mut fanout = [0u32; 256];
+        for (hash, _, _) in &entries {
+            fanout[hash.as_bytes()[0] as usize] += 1;
+        }
+        let mut total = 0;
+        for count in fanout {
+            total += count;
+            buf.extend(total.to_be_bytes());
+        }
+        for (hash, _, _) in &entries {
+            buf.extend(hash.as_bytes());
+        }
+        for (_, crc, _) in &entries {
+            buf.extend(crc.to_be_bytes());
+        }
+        let mut large_offsets = Vec::new();
+        for (_, _, offset) in &entries {
+            if *offset < 0x8000_0000 {
+                buf.extend((*offset as u32).to_be_bytes());
+            } else {
+                buf.extend((0x8000_0000 | large_offsets.len() as u32).to_be_bytes());
+                large_offsets.push(*offset);
+            }
+        }
+        for offset in large_offsets {
+            buf.extend(offset.to_be_bytes());
+        }
+        buf.extend(pack_checksum.as_bytes());
+        let checksum = pack_checksum.format().hash(&buf);
+        buf.extend(checksum.as_bytes());
+
+        writer.write_all(&buf).context("write pack index")?;
+
+        Ok(())
+    }
+}
+
+/// A pack file together with its index.
+pub struct Pack {
+    pub pack_path: PathBuf,
+    pub index: Arc<PackIndex>,
+}
+
+impl Pack {
+    /// Open the pack belonging to the index at `idx_path`, whose objects are
+    /// named with the object format `format`.
+    pub fn open(idx_path: impl AsRef<Path>, format: ObjectFormat) -> crate::Result<Pack> {
+        static INDEXES: Mutex<Option<HashMap<PathBuf, Arc<PackIndex>>>> = Mutex::new(None);
+
+        let idx_path = idx_path.as_ref().to_path_buf();
+        let pack_path = idx_path.with_extension("pack");
+        // Pack names are derived from their contents, so a cached index never goes stale.
+        let mut cache = INDEXES.lock().unwrap();
+        let cache = cache.get_or_insert_with(HashMap::new);
+        let index = match cache.get(&idx_path) {
+            Some(index) => index.clone(),
+            None => {
+                let data = fs::read(&idx_path).context(format!("read {:?}", idx_path))?;
+                let index = Arc::new(PackIndex::parse(&data, format)?);
+                cache.insert(idx_path, index.clone());
+                index
+            }
+        };
+
+        Ok(Pack { pack_path, index })
+    }
+
+    /// Open all packs in the object store.
+    pub fn all() -> crate::Result<Vec<Pack>> {
+        Pack::all_in(&pack_dir(), ObjectFormat::current()?)
+    }
+
+    /// Open all packs in the pack directory `pack_dir`, whose objects are
+    /// named with the object format `format`.
+    pub fn all_in(pack_dir: &Path, format: ObjectFormat) -> crate::Result<Vec<Pack>> {
+        let entries = match fs::read_dir(pack_dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err).context("read pack directory"),
+        };
+        let mut packs = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "idx")
+                && path.with_extension("pack").exists()
+            {
+                packs.push(Pack::open(path, format)?);
+            }
+        }
+
+        Ok(packs)
+    }
+
+    /// Whether the pack was fetched from a promisor remote, so that objects it
+    /// references may legitimately be missing.
+    pub fn is_promisor(&self) -> bool {
+        self.pack_path.with_extension("promisor").exists()
+    }
+
+    /// Hashes of all objects in the pack.
+    pub fn hashes(&self) -> impl Iterator<Item = ObjectId> + '_ {
+        self.index.hashes.iter().copied()
+    }
+
+    pub fn contains(&self, object_hash: &ObjectId) -> bool {
+        self.index.find(object_hash).is_some()
+    }
+
+    /// Read the object identified by `object_hash` if it is in this pack.
+    pub fn read(&self, object_hash: &ObjectId) -> crate::Result<Option<Object>> {
+        let Some(offset) = self.index.find(object_hash) else {
+            return Ok(None);
+        };
+        let mut file = fs::File::open(&self.pack_path).context("open pack file")?;
+
+        self.read_at(&mut file, offset).map(Some)
+    }
+
+    /// Type and size of the object identified by `object_hash` if it is in
+    /// this pack. Only entry headers, and for deltified objects the start of
+    /// the delta, are read.
+    pub fn read_header(&self, object_hash: &ObjectId) -> crate::Result<Option<(ObjectType, u64)>> {
+        let Some(offset) = self.index.find(object_hash) else {
+            return Ok(None);
+        };
+        let mut file = fs::File::open(&self.pack_path).context("open pack file")?;
+
+        self.read_header_at(&mut file, offset).map(Some)
+    }
+
+    fn read_header_at(&self, file: &mut fs::File, offset: u64) -> crate::Result<(ObjectType, u64)> {
+        let (object_type, size, base, reader) = self.read_entry_header_at(file, offset)?;
+        let Some(base) = base else {
+            return Ok((object_type_from_pack(object_type)?, size as u64));
+        };
+
+        // A delta starts with the sizes of its base and result; each takes
+        // at most ten bytes.
+        let mut delta = Vec::new();
+        flate2::bufread::ZlibDecoder::new(reader)
+            .take(size.min(20) as u64)
+            .read_to_end(&mut delta)
+            .context("inflate pack entry")?;
+        let mut pos = 0;
+        delta_varint(&delta, &mut pos)?;
+        let result_size = delta_varint(&delta, &mut pos)?;
+        let ty = match base {
+            DeltaBase::Offset(base_offset) => self.read_header_at(file, base_offset)?.0,
+            DeltaBase::Hash(base_hash) => match self.index.find(&base_hash) {
+                Some(base_offset) => self.read_header_at(file, base_offset)?.0,
+                None => Object::read_header(&base_hash)?.0,
+            },
+        };
+
+        Ok((ty, result_size as u64))
+    }
+
+    /// Read the header of the entry at `offset`: its pack type, size and
+    /// delta base. Returns a reader positioned at its compressed data.
+    fn read_entry_header_at<'a>(
+        &self,
+        file: &'a mut fs::File,
+        offset: u64,
+    ) -> crate::Result<(u8, usize, Option<DeltaBase>, BufReader<&'a mut fs::File>)> {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(file);
+        let mut header = Vec::new();
+        loop {
+            let mut byte = [0];
+            reader
+                .read_exact(&mut byte)
+                .context("read pack entry header")?;
+            header.push(byte[0]);
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let (object_type, _, size) = read_entry_header(&header)?;
+
+        let base = match object_type {
+            OBJ_OFS_DELTA => {
+                let mut byte = [0];
+                reader.read_exact(&mut byte)?;
+                let mut base_offset = (byte[0] & 0x7f) as u64;
+                while byte[0] & 0x80 != 0 {
+                    reader.read_exact(&mut byte)?;
+                    base_offset = ((base_offset + 1) << 7) | (byte[0] & 0x7f) as u64;
+                }
+                Some(DeltaBase::Offset(
+                    offset
+                        .checked_sub(base_offset)
+                        .context("delta base offset out of range")?,
+                ))
+            }
+            OBJ_REF_DELTA => {
+                let mut base_hash = vec![0; self.index.pack_checksum.format().hash_len()];
+                reader.read_exact(&mut base_hash)?;
+                Some(DeltaBase::Hash(ObjectId::from_bytes(&base_hash)?))
+            }
+            _ => None,
+        };
+
+        Ok((object_type, size, base, reader))
+    }
+
+    fn read_at(&self, file: &mut fs::File, offset: u64) -> crate::Result<Object> {
+        let (object_type, size, base, reader) = self.read_entry_header_at(file, offset)?;
+        // The size comes from the pack, so let the buffer grow as data is inflated.
+        let mut data = Vec::new();
+        flate2::bufread::ZlibDecoder::new(reader)
+            .take(size as u64)
+            .read_to_end(&mut data)
+            .context("inflate pack entry")?;
+        crate::ensure!(
+            data.len() == size,
+            CorruptObject,
+            "pack entry size mismatch"
+        );
+
+        let object = match base {
+            None => Object {
+                ty: object_type_from_pack(object_type)?,
+                contents: data,
+            },
+            Some(DeltaBase::Offset(base_offset)) => {
+                let base = self.read_at(file, base_offset)?;
+                Object {
+                    ty: base.ty,
+                    contents: apply_delta(&base.contents, &data)?,
+                }
+            }
+            Some(DeltaBase::Hash(base_hash)) => {
+                let base = match self.index.find(&base_hash) {
+                    Some(base_offset) => self.read_at(file, base_offset)?,
+                    None => Object::read(&base_hash)?,
+                };
+                Object {
+                    ty: base.ty,
+                    contents: apply_delta(&base.contents, &data)?,
+                }
+            }
+        };
+
+        Ok(object)
+    }
+}
+
+enum DeltaBase {
+    Offset(u64),
+    Hash(ObjectId),
+}
+
+/// Index the pack stream `data` (e.g. received from a remote) and store it with
+/// its index in the object store. Delta bases missing from the pack are looked up
+/// in the object store and appended to the stored pack, which is then
+/// self-contained; git calls this fixing a thin pack. Returns the pack's name.
+pub fn index_pack(data: &[u8]) -> crate::Result<String> {
+    let store = object_store::repository()?;
+    index_pack_in(&pack_dir(), ObjectFormat::current()?, &store, data)
+}
+
+/// Like [`index_pack`], but store the pack of objects named with `format`
+/// in `pack_dir`, looking up the bases of a thin pack in `bases`.
+pub fn index_pack_in(
+    pack_dir: &Path,
+    format: ObjectFormat,
+    bases: &dyn ObjectStore,
+    data: &[u8],
+) -> crate::Result<String> {
+    let hash_len = format.hash_len();
+    crate::ensure!(
+        data.len() >= 12 + hash_len && data[..4] == b"PACK"[..],
+        CorruptObject,
+        "invalid pack header"
+    );
+    let version = u32::from_be_bytes(data[4..8].try_into()?);
+    crate::ensure!(
+        version == 2 || version == 3,
+        CorruptObject,
+        "unsupported pack version {version}"
+    );
+    let count = u32::from_be_bytes(data[8..12].try_into()?) as usize;
+    let (contents, checksum) = data.split_at(data.len() - hash_len);
+    crate::ensure!(
+        format.hash(contents).as_bytes() == checksum,
+        CorruptObject,
+        "pack checksum mismatch"
+    );
+
+    struct Entry {
+        offset: u64,
+        crc: u32,
+        object_type: u8,
+        base: Option<DeltaBase>,
+        data: Vec<u8>,
+    }
+
+    // Every entry takes at least two bytes, which bounds a forged count.
+    let mut entries = Vec::with_capacity(count.min(data.len() / 2));
+    let mut pos = 12;
+    for _ in 0..count {
+        let offset = pos;
+        let header_len = data[pos..]
+            .iter()
+            .position(|b| b & 0x80 == 0)
+            .context("truncated pack entry header")?
+            + 1;
+        let (object_type, _, size) = read_entry_header(&data[pos..pos + header_len])?;
+        pos += header_len;
+        let base = match object_type {
+            OBJ_OFS_DELTA => {
+                let mut c = *data.get(pos).context("truncated pack")?;
+                pos += 1;
+                let mut base_offset = (c & 0x7f) as u64;
+                while c & 0x80 != 0 {
+                    c = *data.get(pos).context("truncated pack")?;
+                    pos += 1;
+                    base_offset = ((base_offset + 1) << 7) | (c & 0x7f) as u64;
+                }
+                Some(DeltaBase::Offset(
+                    (offset as u64)
+                        .checked_sub(base_offset)
+                        .context("delta base offset out of range")?,
+                ))
+            }
+            OBJ_REF_DELTA => {
+                let base_hash = data.get(pos..pos + hash_len).context("truncated pack")?;
+                pos += hash_len;
+                Some(DeltaBase::Hash(ObjectId::from_bytes(base_hash)?))
+            }
+            _ => None,
+        };
+
+        // The entry's size is that of the inflated data, so inflate to find where it ends.
+        // Reading one byte more than the size checks that the data ends there.
+        let input = &data[pos..data.len() - hash_len];
+        let mut inflated = Vec::with_capacity(size.min(input.len()));
+        let mut decoder = flate2::read::ZlibDecoder::new(input);
+        (&mut decoder)
+            .take(size as u64 + 1)
+            .read_to_end(&mut inflated)
+            .context("inflate pack entry")?;
+        crate::ensure!(
+            inflated.len() == size,
+            CorruptObject,
+            "pack entry size mismatch"
+        );
+        pos += decoder.total_in() as usize;
+
+        let mut crc = flate2::Crc::new();
+        crc.update(&data[offset..pos]);
+        entries.push(Entry {
+            offset: offset as u64,
+            crc: crc.sum(),
+            object_type,
+            base,
+            data: inflated,
+        });
+    }
+    crate::ensure!(
+        pos == data.len() - hash_len,
+        CorruptObject,
+        "trailing data after pack entries"
+    );
+
+    // Resolve deltas until every entry has a hash. Bases may come later in the pack.
+    let by_offset: HashMap<u64, usize> = entries
+        .iter()
+        .enumerate()
+        .map(|(idx, entry)| (entry.offset, idx))
+        .collect();
+    let mut resolved: Vec<Option<(ObjectType, ObjectId)>> = vec![None; entries.len()];
+    let mut by_hash: HashMap<ObjectId, usize> = HashMap::new();
+    // Bases of a thin pack that are not in the pack, by hash.
+    let mut thin_bases: BTreeMap<ObjectId, Object> = BTreeMap::new();
+    loop {
+        let mut progress = false;
+        for idx in 0..entries.len() {
+            if resolved[idx].is_some() {
+                continue;
+            }
+            let object = match &entries[idx].base {
+                None => Object {
+                    ty: object_type_from_pack(entries[idx].object_type)?,
+                    contents: std::mem::take(&mut entries[idx].data),
+                },
+                Some(base) => {
+                    let base_idx = match base {
+                        DeltaBase::Offset(base_offset) => Some(
+                            *by_offset
+                                .get(base_offset)
+                                .context("delta base offset is not an entry")?,
+                        ),
+                        DeltaBase::Hash(base_hash) => by_hash.get(base_hash).copied(),
+                    };
+                    let base = match (base_idx, base) {
+                        (Some(base_idx), _) => match &resolved[base_idx] {
+                            Some((ty, _)) => Object {
+                                ty: *ty,
+                                contents: entries[base_idx].data.clone(),
+                            },
+                            None => continue,
+                        },
+                        // Thin packs refer to bases we already have.
+                        (None, DeltaBase::Hash(base_hash)) => match bases.read(base_hash)? {
+                            Some(base) => {
+                                let hash = base.hash_with(format)?;
+                                crate::ensure!(
+                                    hash == *base_hash,
+                                    CorruptObject,
+                                    "hash mismatch: object {base_hash} hashes to {hash}"
+                                );
+                                thin_bases.insert(*base_hash, base.clone());
+                                base
+                            }
+                            None => continue,
+                        },
+                        (None, _) => continue,
+                    };
+                    Object {
+                        ty: base.ty,
+                        contents: apply_delta(&base.contents, &entries[idx].data)?,
+                    }
+                }
+            };
+            let hash = object.hash_with(format)?;
+            entries[idx].data = object.contents;
+            by_hash.insert(hash, idx);
+            resolved[idx] = Some((object.ty, hash));
+            progress = true;
+        }
+        if !progress {
+            break;
+        }
+    }
+    crate::ensure!(
+        resolved.iter().all(Option::is_some),
+        CorruptObject,
+        "pack contains deltas with missing bases"
+    );
+
+    let mut index_entries: Vec<(ObjectId, u32, u64)> = entries
+        .iter()
+        .zip(resolved.into_iter().flatten())
+        .map(|(entry, (_, hash))| (hash, entry.crc, entry.offset))
+        .collect();
+    if thin_bases.is_empty() {
+        return store_pack(pack_dir, format, data, index_entries);
+    }
+
+    // Deltas may only refer to objects in the same pack, so append the
+    // bases and rewrite the object count and the checksum.
+    log::debug!("Completing thin pack with {} bases", thin_bases.len());
+    let mut data = contents.to_vec();
+    let count = (count + thin_bases.len()) as u32;
+    data[8..12].copy_from_slice(&count.to_be_bytes());
+    for (hash, base) in thin_bases {
+        let offset = data.len() as u64;
+        let crc = write_entry(&base, &mut data)?;
+        index_entries.push((hash, crc, offset));
+    }
+    let checksum = format.hash(&data);
+    data.extend(checksum.as_bytes());
+
+    store_pack(pack_dir, format, &data, index_entries)
+}
+
+/// Read exactly one pack stream from `reader`, leaving any data after it unread.
+/// Used where a pack is followed by more protocol traffic, e.g. a push.
+pub fn read_pack_stream(reader: &mut impl BufRead) -> crate::Result<Vec<u8>> {
+    fn read_byte(reader: &mut impl BufRead, data: &mut Vec<u8>) -> crate::Result<u8> {
+        let mut byte = [0];
+        reader.read_exact(&mut byte).context("truncated pack")?;
+        data.push(byte[0]);
+        Ok(byte[0])
+    }
+
+    let mut data = vec![0; 12];
+    reader.read_exact(&mut data).context("read pack header")?;
+    crate::ensure!(
+        data[..4] == b"PACK"[..],
+        CorruptObject,
+        "invalid pack header"
+    );
+    let count = u32::from_be_bytes(data[8..12].try_into()?);
+    let mut out = vec![0; 8192];
+    for _ in 0..count {
+        let mut byte = read_byte(reader, &mut data)?;
+        let object_type = (byte >> 4) & 7;
+        while byte & 0x80 != 0 {
+            byte = read_byte(reader, &mut data)?;
+        }
+        match object_type {
+            OBJ_OFS_DELTA => while read_byte(reader, &mut data)? & 0x80 != 0 {},
+            OBJ_REF_DELTA => {
+                for _ in 0..ObjectFormat::current()?.hash_len() {
+                    read_byte(reader, &mut data)?;
+                }
+            }
+            _ => (),
+        }
+
+        // Only the zlib stream knows where it ends, so inflate and discard.
+        let mut decompress = flate2::Decompress::new(true);
+        loop {
+            let buf = reader.fill_buf().context("read pack")?;
+            crate::ensure!(!buf.is_empty(), CorruptObject, "truncated pack");
+            let total_in = decompress.total_in();
+            let status = decompress
+                .decompress(buf, &mut out, flate2::FlushDecompress::None)
+                .context("inflate pack entry")?;
+            let consumed = (decompress.total_in() - total_in) as usize;
+            data.extend_from_slice(&buf[..consumed]);
+            reader.consume(consumed);
+            if status == flate2::Status::StreamEnd {
+                break;
+            }
+        }
+    }
+    let mut checksum = vec![0; ObjectFormat::current()?.hash_len()];
+    reader
+        .read_exact(&mut checksum)
+        .context("read pack checksum")?;
+    data.extend(checksum);
+
+    Ok(data)
+}
+
+/// Mark the pack `name` as fetched from a promisor remote.
+pub fn mark_promisor(name: &str) -> crate::Result<()> {
+    fs::write(pack_dir().join(format!("pack-{name}.promisor")), "").context("write promisor file")
+}
+
+/// Read the object identified by `object_hash` from any pack in the object store.
+pub fn read_packed_object(object_hash: &ObjectId) -> crate::Result<Option<Object>> {
+    for pack in Pack::all()? {
+        if let Some(object) = pack.read(object_hash)? {
+            return Ok(Some(object));
+        }
+    }
+
+    Ok(None)
+}
+
+fn object_type_from_pack(object_type: u8) -> crate::Result<ObjectType> {
+    match object_type {
+        OBJ_COMMIT => Ok(ObjectType::Commit),
+        OBJ_TREE => Ok(ObjectType::Tree),
+        OBJ_BLOB => Ok(ObjectType::Blob),
+        OBJ_TAG => Ok(ObjectType::Tag),
+        _ => Err(Error::CorruptObject(format!(
+            "invalid pack object type {object_type}"
+        ))),
+    }
+}
+
+fn object_type_to_pack(ty: ObjectType) -> u8 {
+    match ty {
+        ObjectType::Commit => OBJ_COMMIT,
+        ObjectType::Tree => OBJ_TREE,
+        ObjectType::Blob => OBJ_BLOB,
+        ObjectType::Tag => OBJ_TAG,
+    }
+}
+
+/// Parse the type and size of the pack entry at the start of `buf`.
+/// Returns `(object type, header length, size)`.
+pub fn read_entry_header(buf: &[u8]) -> crate::Result<(u8, usize, usize)> {
+    let mut idx = 0;
+
+    let mut c = *buf.first().context("truncated pack entry header")?;
+    idx += 1;
+
+    let object_type = (c >> 4) & 0x7;
+
+    let mut size = (c & 0x0f) as usize;
+    let mut shift = 4;
+
+    while c & 0x80 != 0 {
+        crate::ensure!(
+            shift < usize::BITS,
+            CorruptObject,
+            "pack entry size too large"
+        );
+        c = *buf.get(idx).context("truncated pack entry header")?;
+        idx += 1;
+        size |= ((c & 0x7f) as usize) << shift;
+        shift += 7;
+    }
+
+    Ok((object_type, idx, size))
+}
+
+fn write_entry_header(object_type: u8, size: usize, buf: &mut Vec<u8>) {
+    let mut c = (object_type << 4) | (size & 0x0f) as u8;
+    let mut size = size >> 4;
+    while size != 0 {
+        buf.push(c | 0x80);
+        c = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    buf.push(c);
+}
+
+/// Parse one of the sizes at the start of a delta at `pos`, advancing it.
+fn delta_varint(delta: &[u8], pos: &mut usize) -> crate::Result<usize> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        crate::ensure!(shift < usize::BITS, CorruptObject, "delta size too large");
+        let c = *delta.get(*pos).context("truncated delta")?;
+        *pos += 1;
+        value |= ((c & 0x7f) as usize) << shift;
+        shift += 7;
+        if c & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+/// Apply the git delta `delta` to `base`.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> crate::Result<Vec<u8>> {
+    let mut pos = 0;
+    let base_size = delta_varint(delta, &mut pos)?;
+    crate::ensure!(
+        base_size == base.len(),
+        CorruptObject,
+        "delta base size mismatch"
+    );
+    let result_size = delta_varint(delta, &mut pos)?;
+
+    // The result size comes from the delta, so only reserve what the inputs bound.
+    let mut result = Vec::with_capacity(result_size.min(base.len() + delta.len()));
+    while let Some(&op) = delta.get(pos) {
+        pos += 1;
+        if op & 0x80 != 0 {
+            // Copy from base.
+            let mut offset = 0;
+            let mut size = 0;
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    offset |= (*delta.get(pos).context("truncated delta")? as usize) << (i * 8);
+                    pos += 1;
+                }
+            }
+            for i in 0..3 {
+                if op & (0x10 << i) != 0 {
+                    size |= (*delta.get(pos).context("truncated delta")? as usize) << (i * 8);
+                    pos += 1;
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+            let chunk = base
+                .get(offset..offset + size)
+                .context("delta copy out of range")?;
+            result.extend_from_slice(chunk);
+        } else if op != 0 {
+            // Insert literal data.
+            let chunk = delta
+                .get(pos..pos + op as usize)
+                .context("truncated delta")?;
+            result.extend_from_slice(chunk);
+            pos += op as usize;
+        } else {
+            crate::bail!(CorruptObject, "invalid delta opcode");
+        }
+    }
+    crate::ensure!(
+        result.len() == result_size,
+        CorruptObject,
+        "delta result size mismatch"
+    );
+
+    Ok(result)
+}
+
+/// Block size for matching `target` against `base` in [`create_delta`].
+const DELTA_BLOCK: usize = 16;
+
+/// Create a git delta that turns `base` into `target`, for use with
+/// [`apply_delta`].
+pub fn create_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    fn varint(mut value: usize, delta: &mut Vec<u8>) {
+        while value >= 0x80 {
+            delta.push((value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+        delta.push(value as u8);
+    }
+    fn insert(literal: &[u8], delta: &mut Vec<u8>) {
+        for chunk in literal.chunks(0x7f) {
+            delta.push(chunk.len() as u8);
+            delta.extend_from_slice(chunk);
+        }
+    }
+    fn copy(mut offset: usize, mut size: usize, delta: &mut Vec<u8>) {
+        while size > 0 {
+            let len = size.min(0xffffff);
+            let mut op = 0x80;
+            let mut args = Vec::with_capacity(7);
+            for i in 0..4 {
+                let byte = (offset >> (i * 8)) as u8;
+                if byte != 0 {
+                    op |= 1 << i;
+                    args.push(byte);
+                }
+            }
+            for i in 0..3 {
+                let byte = (len >> (i * 8)) as u8;
+                if byte != 0 {
+                    op |= 0x10 << i;
+                    args.push(byte);
+                }
+            }
+            delta.push(op);
+            delta.extend(args);
+            offset += len;
+            size -= len;
+        }
+    }
+
+    let mut delta = Vec::new();
+    varint(base.len(), &mut delta);
+    varint(target.len(), &mut delta);
+
+    // Offsets of the aligned blocks in `base`, keyed by their contents.
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for (idx, block) in base.chunks_exact(DELTA_BLOCK).enumerate() {
+        blocks.entry(block).or_insert(idx * DELTA_BLOCK);
+    }
+
+    let mut literal_start = 0;
+    let mut pos = 0;
+    while pos + DELTA_BLOCK <= target.len() {
+        let Some(&block_start) = blocks.get(&target[pos..pos + DELTA_BLOCK]) else {
+            pos += 1;
+            continue;
+        };
+        // Extend the match backwards into the pending literal and forwards
+        // as far as both sides agree.
+        let mut start = pos;
+        let mut base_start = block_start;
+        while start > literal_start && base_start > 0 && target[start - 1] == base[base_start - 1] {
+            start -= 1;
+            base_start -= 1;
+        }
+        let mut end = pos + DELTA_BLOCK;
+        let mut base_end = block_start + DELTA_BLOCK;
+        while end < target.len() && base_end < base.len() && target[end] == base[base_end] {
+            end += 1;
+            base_end += 1;
+        }
+        insert(&target[literal_start..start], &mut delta);
+        copy(base_start, end - start, &mut delta);
+        literal_start = end;
+        pos = end;
+    }
+    insert(&target[literal_start..], &mut delta);
+
+    delta
+}
+
+/// Write `objects` as a new pack with index into the object store.
+/// Returns the hex-encoded pack checksum, which is also the pack's name.
+pub fn write_pack(objects: &[(ObjectId, Object)]) -> crate::Result<String> {
+    write_pack_to(&pack_dir(), objects)
+}
+
+/// Like [`write_pack`], but into the pack directory `pack_dir` of another repository.
+pub fn write_pack_to(pack_dir: &Path, objects: &[(ObjectId, Object)]) -> crate::Result<String> {
+    let (buf, entries) = encode(objects, &HashMap::new())?;
+    store_pack(pack_dir, ObjectFormat::current()?, &buf, entries)
+}
+
+/// Write the pack `data` and an index for `entries` into `pack_dir`.
+fn store_pack(
+    pack_dir: &Path,
+    format: ObjectFormat,
+    data: &[u8],
+    entries: Vec<(ObjectId, u32, u64)>,
+) -> crate::Result<String> {
+    let checksum = ObjectId::from_bytes(&data[data.len() - format.hash_len()..])?;
+    let name = checksum.to_string();
+    fs::create_dir_all(pack_dir).context("create pack dir")?;
+    let pack_path = pack_dir.join(format!("pack-{name}.pack"));
+    log::debug!("Writing pack to {:?}", pack_path);
+    let mut idx = Vec::new();
+    PackIndex::write(entries, &checksum, &mut idx)?;
+
+    // Readers only look for packs through their index, so the pack is moved
+    // into place first and the index last, each only once fully on disk.
+    let tmp_pack = write_temp_file(pack_dir, "pack", data).context("write pack file")?;
+    let tmp_idx = match write_temp_file(pack_dir, "idx", &idx) {
+        Ok(path) => path,
+        Err(err) => {
+            let _ = fs::remove_file(&tmp_pack);
+            return Err(err).context("write pack index");
+        }
+    };
+    let renamed = fs::rename(&tmp_pack, &pack_path)
+        .context("rename pack file")
+        .and_then(|()| {
+            fs::rename(&tmp_idx, pack_path.with_extension("idx")).context("rename pack index")
+        });
+    if let Err(err) = renamed {
+        let _ = fs::remove_file(&tmp_pack);
+        let _ = fs::remove_file(&tmp_idx);
+        return Err(err);
+    }
+
+    Ok(name)
+}
+
+/// Write `data` to a new temporary file in `dir` and sync it to disk.
+fn write_temp_file(dir: &Path, kind: &str, data: &[u8]) -> crate::Result<PathBuf> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let path = dir.join(format!(
+        "tmp_{kind}_{}_{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let written = fs::File::create(&path).and_then(|mut file| {
+        file.write_all(data)?;
+        file.sync_all()
+    });
+    if let Err(err) = written {
+        let _ = fs::remove_file(&path);
+        return Err(err.into());
+    }
+
+    Ok(path)
+}
+
+/// Encode `objects` as a pack stream, e.g. for sending to a remote.
+pub fn encode_pack(objects: &[(ObjectId, Object)]) -> crate::Result<Vec<u8>> {
+    Ok(encode(objects, &HashMap::new())?.0)
+}
+
+/// Write a pack of the objects `hashes` to `writer`, reading each from the
+/// object store only as it is written rather than holding them all.
+pub fn write_pack_stream(hashes: &[ObjectId], writer: &mut impl Write) -> crate::Result<()> {
+    let mut hasher = ObjectFormat::current()?.hasher();
+    let mut buf = Vec::new();
+    buf.extend(b"PACK");
+    buf.extend(2u32.to_be_bytes());
+    buf.extend((hashes.len() as u32).to_be_bytes());
+    for hash in hashes {
+        let object = Object::read(hash).context(format!("read object {hash}"))?;
+        write_entry(&object, &mut buf)?;
+        hasher.write_all(&buf)?;
+        writer.write_all(&buf)?;
+        buf.clear();
+    }
+    hasher.write_all(&buf)?;
+    writer.write_all(&buf)?;
+    writer.write_all(hasher.finish().as_bytes())?;
+
+    Ok(())
+}
+
+/// Encode `objects` as a thin pack. An object with an entry in `bases` is
+/// sent as a delta against that base when this is smaller; the base itself is
+/// not included, as the receiver is expected to have it already.
+pub fn encode_thin_pack(
+    objects: &[(ObjectId, Object)],
+    bases: &HashMap<ObjectId, ObjectId>,
+) -> crate::Result<Vec<u8>> {
+    Ok(encode(objects, bases)?.0)
+}
+
+/// Encode `objects` as a pack, returning the pack and its index entries.
+/// Objects with an entry in `bases` are deltified against it if that helps.
+#[allow(clippy::type_complexity)]
+fn encode(
+    objects: &[(ObjectId, Object)],
+    bases: &HashMap<ObjectId, ObjectId>,
+) -> crate::Result<(Vec<u8>, Vec<(ObjectId, u32, u64)>)> {
+    let mut buf = Vec::new();
+    buf.extend(b"PACK");
+    buf.extend(2u32.to_be_bytes());
+    buf.extend((objects.len() as u32).to_be_bytes());
+
+    let mut entries = Vec::with_capacity(objects.len());
+    for (hash, object) in objects {
+        let offset = buf.len() as u64;
+        let delta = match bases.get(hash) {
+            Some(base_hash) => {
+                let base = Object::read(base_hash)?;
+                let delta = create_delta(&base.contents, &object.contents);
+                (base.ty == object.ty && delta.len() < object.contents.len() / 2)
+                    .then_some((base_hash, delta))
+            }
+            None => None,
+        };
+        let crc = match delta {
+            Some((base_hash, delta)) => write_delta_entry(base_hash, &delta, &mut buf)?,
+            None => write_entry(object, &mut buf)?,
+        };
+        entries.push((*hash, crc, offset));
+    }
+    let checksum = ObjectFormat::current()?.hash(&buf);
+    buf.extend(checksum.as_bytes());
+
+    Ok((buf, entries))
+}
+
+/// Append `object` to the pack `buf` as an undeltified entry and return the
+/// entry's CRC-32.
+fn write_entry(object: &Object, buf: &mut Vec<u8>) -> crate::Result<u32> {
+    let offset = buf.len();
+    write_entry_header(object_type_to_pack(object.ty), object.contents.len(), buf);
+    buf.extend(zlib_encode(&object.contents)?);
+
+    let mut crc = flate2::Crc::new();
+    crc.update(&buf[offset..]);
+
+    Ok(crc.sum())
+}
+
+/// Append `delta` against the object `base` to the pack `buf` as a
+/// REF_DELTA entry and return the entry's CRC-32.
+fn write_delta_entry(base: &ObjectId, delta: &[u8], buf: &mut Vec<u8>) -> crate::Result<u32> {
+    let offset = buf.len();
+    write_entry_header(OBJ_REF_DELTA, delta.len(), buf);
+    buf.extend(base.as_bytes());
+    buf.extend(zlib_encode(delta)?);
+
+    let mut crc = flate2::Crc::new();
+    crc.update(&buf[offset..]);
+
+    Ok(crc.sum())
+}
+
+/// Hashes of all loose objects in the object store.
+pub fn loose_objects() -> crate::Result<Vec<ObjectId>> {
+    loose_objects_in(&git_dir().join("objects"))
+}
+
+/// Hashes of all loose objects in the objects directory `objects_dir`.
+pub fn loose_objects_in(objects_dir: &Path) -> crate::Result<Vec<ObjectId>> {
+    let mut hashes = Vec::new();
+    for dir in fs::read_dir(objects_dir).context("read objects dir")? {
+        let dir = dir?;
+        let prefix = dir.file_name();
+        let Some(prefix) = prefix.to_str() else {
+            continue;
+        };
+        if prefix.len() != 2 || hex::decode(prefix).is_err() {
+            continue;
+        }
+        for file in fs::read_dir(dir.path())? {
+            let file = file?.file_name();
+            let Some(suffix) = file.to_str() else {
+                continue;
+            };
+            if let Ok(object_hash) = format!("{prefix}{suffix}").parse() {
+                hashes.push(object_hash);
+            }
+        }
+    }
+
+    Ok(hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::{FileStore, ObjectStore};
+
+    fn blob(contents: &[u8]) -> (ObjectId, Object) {
+        let object = Object {
+            ty: ObjectType::Blob,
+            contents: contents.to_vec(),
+        };
+        (object.hash_with(ObjectFormat::Sha1).unwrap(), object)
+    }
+
+    fn assert_contains(pack: &Pack, (hash, object): &(ObjectId, Object)) {
+        let read = pack.read(hash).unwrap().expect("object in pack");
+        assert_eq!(read.ty, object.ty);
+        assert_eq!(read.contents, object.contents);
+        let header = (object.ty, object.contents.len() as u64);
+        assert_eq!(pack.read_header(hash).unwrap(), Some(header));
+    }
+
+    /// A SHA-1 pack of `count` entries written by `write_entries`.
+    fn sha1_pack(count: u32, write_entries: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+        let mut pack = b"PACK".to_vec();
+        pack.extend(2u32.to_be_bytes());
+        pack.extend(count.to_be_bytes());
+        write_entries(&mut pack);
+        let checksum = ObjectFormat::Sha1.hash(&pack);
+        pack.extend(checksum.as_bytes());
+        pack
+    }
+
+    #[test]
+    fn packs_index_and_read_back() {
+        let objects_dir =
+            std::env::temp_dir().join(format!("mygit-pack-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&objects_dir);
+        let pack_dir = objects_dir.join("pack");
+        fs::create_dir_all(&pack_dir).unwrap();
+        let store = FileStore::new(&objects_dir, ObjectFormat::Sha1);
+
+        let lines: Vec<u8> = (1..=2000)
+            .flat_map(|n| format!("{n}\n").into_bytes())
+            .collect();
+        let base = blob(&lines);
+        let small = blob(b"hello\n");
+        let empty = blob(b"");
+        let tree = Object {
+            ty: ObjectType::Tree,
+            contents: [&b"100644 hello\0"[..], small.0.as_bytes()].concat(),
+        };
+        let tree = (tree.hash_with(ObjectFormat::Sha1).unwrap(), tree);
+        let objects = vec![base.clone(), small, empty, tree];
+        let data = sha1_pack(objects.len() as u32, |pack| {
+            for (_, object) in &objects {
+                write_entry(object, pack).unwrap();
+            }
+        });
+        let name = index_pack_in(&pack_dir, ObjectFormat::Sha1, &store, &data).unwrap();
+        let idx_path = pack_dir.join(format!("pack-{name}.idx"));
+        let pack = Pack::open(idx_path, ObjectFormat::Sha1).unwrap();
+        assert_eq!(pack.hashes().count(), objects.len());
+        for object in &objects {
+            assert_contains(&pack, object);
+        }
+
+        // A thin pack with a delta against an object only in the store is
+        // stored with that base appended.
+        fs::remove_dir_all(&pack_dir).unwrap();
+        store.write(&base.1).unwrap();
+        let target = blob(&[&lines[..], b"2001\n"].concat());
+        let delta = create_delta(&base.1.contents, &target.1.contents);
+        let thin = sha1_pack(1, |pack| {
+            write_delta_entry(&base.0, &delta, pack).unwrap();
+        });
+        assert_eq!(read_entry_header(&thin[12..]).unwrap().0, OBJ_REF_DELTA);
+        assert!(thin.len() < 100, "thin pack is {} bytes", thin.len());
+        let name = index_pack_in(&pack_dir, ObjectFormat::Sha1, &store, &thin).unwrap();
+        let idx_path = pack_dir.join(format!("pack-{name}.idx"));
+        let pack = Pack::open(idx_path, ObjectFormat::Sha1).unwrap();
+        assert_contains(&pack, &target);
+        assert_contains(&pack, &base);
+
+        fs::remove_dir_all(&objects_dir).unwrap();
+    }
+
+    #[test]
+    fn create_delta_round_trips() {
+        let base: Vec<u8> = (1..=2000)
+            .flat_map(|n| format!("{n}\n").into_bytes())
+            .collect();
+        let mut target = b"prefix\n".to_vec();
+        target.extend(&base[..4000]);
+        target.extend(b"inserted\n");
+        target.extend(&base[4000..]);
+        let delta = create_delta(&base, &target);
+        assert!(delta.len() < 64, "delta is {} bytes", delta.len());
+        assert_eq!(apply_delta(&base, &delta).unwrap(), target);
+
+        for (base, target) in [(&b""[..], &b"abc"[..]), (b"abc", b""), (b"short", b"short")] {
+            assert_eq!(
+                apply_delta(base, &create_delta(base, target)).unwrap(),
+                target
+            );
+        }
+        let large = vec![7u8; 0x30000];
+        let delta = create_delta(&large, &large);
+        assert_eq!(apply_delta(&large, &delta).unwrap(), large);
+    }
+
+    #[test]
+    fn oversized_and_truncated_sizes_are_rejected() {
+        assert_eq!(read_entry_header(&[0x35]).unwrap(), (OBJ_BLOB, 1, 5));
+        assert!(read_entry_header(&[0xb5]).is_err());
+        assert!(read_entry_header(&[0xff; 16]).is_err());
+        assert!(apply_delta(b"", &[0xff; 16]).is_err());
+    }
+}
@@ -0,0 +1,370 @@
+use crate::error::Context;
+use std::{
+    io::{Read, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Largest payload that fits in a single pkt-line.
+pub const MAX_DATA_LEN: usize = 65516;
+
+/// A single packet of the pkt-line format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Data(Vec<u8>),
+    /// `0000`: end of a message.
+    Flush,
+    /// `0001`: separates sections of a protocol v2 message.
+    Delimiter,
+    /// `0002`: end of a stateless protocol v2 response.
+    ResponseEnd,
+}
+
+impl Packet {
+    /// Payload of a data packet as text without its trailing newline.
+    pub fn as_line(&self) -> Option<&str> {
+        match self {
+            Packet::Data(data) => std::str::from_utf8(data)
+                .ok()
+                .map(|line| line.strip_suffix('\n').unwrap_or(line)),
+            _ => None,
+        }
+    }
+}
+
+/// Reads packets from a pkt-line stream.
+pub struct PktLineReader<R> {
+    reader: R,
+}
+
+impl<R: Read> PktLineReader<R> {
+    pub fn new(reader: R) -> PktLineReader<R> {
+        PktLineReader { reader }
+    }
+
+    /// Read the next packet, or `None` at the end of the stream.
+    pub fn read_packet(&mut self) -> crate::Result<Option<Packet>> {
+        let mut len = [0; 4];
+        match self.reader.read_exact(&mut len) {
+            Ok(()) => (),
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err).context("read pkt-line length"),
+        }
+        let len = std::str::from_utf8(&len)
+            .ok()
+            .and_then(|len| usize::from_str_radix(len, 16).ok())
+            .context(format!(
+                "invalid pkt-line length {:?}",
+                String::from_utf8_lossy(&len)
+            ))?;
+        let packet = match len {
+            0 => Packet::Flush,
+            1 => Packet::Delimiter,
+            2 => Packet::ResponseEnd,
+            3 => crate::bail!(Protocol, "invalid pkt-line length 3"),
+            len => {
+                let mut data = vec![0; len - 4];
+                self.reader
+                    .read_exact(&mut data)
+                    .context("truncated pkt-line")?;
+                Packet::Data(data)
+            }
+        };
+
+        Ok(Some(packet))
+    }
+
+    /// Read data packets as text lines until a non-data packet or the end of the stream.
+    /// Returns the lines and the packet that ended them.
+    pub fn read_lines(&mut self) -> crate::Result<(Vec<String>, Option<Packet>)> {
+        let mut lines = Vec::new();
+        loop {
+            match self.read_packet()? {
+                Some(packet @ Packet::Data(_)) => {
+                    let line = packet.as_line().context("pkt-line should be utf-8")?;
+                    lines.push(line.to_owned());
+                }
+                end => return Ok((lines, end)),
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Writes packets in pkt-line format.
+pub struct PktLineWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> PktLineWriter<W> {
+    pub fn new(writer: W) -> PktLineWriter<W> {
+        PktLineWriter { writer }
+    }
+
+    /// Write `data`, split across as many packets as needed.
+    pub fn write_data(&mut self, data: &[u8]) -> crate::Result<()> {
+        for chunk in data.chunks(MAX_DATA_LEN) {
+            write!(self.writer, "{:04x}", chunk.len() + 4)?;
+            self.writer.write_all(chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Write `line` followed by a newline as a single packet.
+    pub fn write_line(&mut self, line: &str) -> crate::Result<()> {
+        crate::ensure!(line.len() < MAX_DATA_LEN, "pkt-line too long");
+        self.write_data(format!("{line}\n").as_bytes())
+    }
+
+    pub fn write_packet(&mut self, packet: &Packet) -> crate::Result<()> {
+        match packet {
+            Packet::Data(data) => self.write_data(data),
+            Packet::Flush => self.write_flush(),
+            Packet::Delimiter => self.write_delimiter(),
+            Packet::ResponseEnd => self.write_response_end(),
+        }
+    }
+
+    pub fn write_flush(&mut self) -> crate::Result<()> {
+        self.writer.write_all(b"0000")?;
+        Ok(())
+    }
+
+    pub fn write_delimiter(&mut self) -> crate::Result<()> {
+        self.writer.write_all(b"0001")?;
+        Ok(())
+    }
+
+    pub fn write_response_end(&mut self) -> crate::Result<()> {
+        self.writer.write_all(b"0002")?;
+        Ok(())
+    }
+
+    /// Write `data` on side-band `band` (1: data, 2: progress, 3: error).
+    pub fn write_band(&mut self, band: u8, data: &[u8]) -> crate::Result<()> {
+        for chunk in data.chunks(MAX_DATA_LEN - 1) {
+            write!(self.writer, "{:04x}", chunk.len() + 5)?;
+            self.writer.write_all(&[band])?;
+            self.writer.write_all(chunk)?;
+        }
+        Ok(())
+    }
+
+    /// A writer sending everything written to it on side-band `band`.
+    pub fn band(&mut self, band: u8) -> BandWriter<'_, W> {
+        BandWriter { writer: self, band }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Writes data on one side-band of a [`PktLineWriter`], a packet per write.
+pub struct BandWriter<'a, W> {
+    writer: &'a mut PktLineWriter<W>,
+    band: u8,
+}
+
+impl<W: Write> Write for BandWriter<'_, W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.writer
+            .write_band(self.band, data)
+            .map_err(std::io::Error::other)?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.writer.flush()
+    }
+}
+
+/// Demultiplexes a side-band-64k stream: band 1 is returned through `Read`, band 2
+/// is passed to the progress callback and band 3 ends the stream with an error.
+/// Reading stops at a flush packet or the end of the underlying stream.
+pub struct SidebandReader<R, F> {
+    reader: PktLineReader<R>,
+    progress: F,
+    buf: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<R: Read, F: FnMut(&[u8])> SidebandReader<R, F> {
+    pub fn new(reader: PktLineReader<R>, progress: F) -> Self {
+        SidebandReader {
+            reader,
+            progress,
+            buf: Vec::new(),
+            pos: 0,
+            done: false,
+        }
+    }
+
+    /// Fill the buffer with the next band 1 packet. Returns `false` at the end of the stream.
+    fn fill(&mut self) -> crate::Result<bool> {
+        while self.pos == self.buf.len() && !self.done {
+            match self.reader.read_packet()? {
+                Some(Packet::Data(data)) => match data.split_first() {
+                    Some((1, payload)) => {
+                        self.buf = payload.to_vec();
+                        self.pos = 0;
+                    }
+                    Some((2, progress)) => (self.progress)(progress),
+                    Some((3, error)) => {
+                        crate::bail!(
+                            Protocol,
+                            "remote error: {}",
+                            String::from_utf8_lossy(error).trim_end()
+                        )
+                    }
+                    _ => crate::bail!(Protocol, "invalid side-band packet"),
+                },
+                Some(Packet::Flush) | Some(Packet::ResponseEnd) | None => self.done = true,
+                Some(Packet::Delimiter) => {
+                    crate::bail!(Protocol, "unexpected delimiter in side-band stream")
+                }
+            }
+        }
+
+        Ok(self.pos < self.buf.len())
+    }
+}
+
+impl<R: Read, F: FnMut(&[u8])> Read for SidebandReader<R, F> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        if !self.fill().map_err(std::io::Error::other)? {
+            return Ok(0);
+        }
+        let len = out.len().min(self.buf.len() - self.pos);
+        out[..len].copy_from_slice(&self.buf[self.pos..self.pos + len]);
+        self.pos += len;
+
+        Ok(len)
+    }
+}
+
+/// Progress callback that forwards remote messages to stderr, prefixing
+/// each line with `remote: ` like git does.
+pub fn print_progress(message: &[u8]) {
+    static AT_LINE_START: AtomicBool = AtomicBool::new(true);
+
+    let mut stderr = std::io::stderr();
+    // Messages are split arbitrarily across packets; lines end in `\n` or `\r`.
+    for segment in message.split_inclusive(|&b| b == b'\n' || b == b'\r') {
+        if AT_LINE_START.load(Ordering::Relaxed) {
+            let _ = stderr.write_all(b"remote: ");
+        }
+        let _ = stderr.write_all(segment);
+        AT_LINE_START.store(
+            segment.ends_with(b"\n") || segment.ends_with(b"\r"),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_and_reads_packets() {
+        let mut writer = PktLineWriter::new(Vec::new());
+        writer.write_line("want abc").unwrap();
+        writer.write_delimiter().unwrap();
+        writer.write_data(b"\0bin").unwrap();
+        writer.write_flush().unwrap();
+        writer.write_response_end().unwrap();
+        let data = writer.into_inner();
+        assert_eq!(data, b"000dwant abc\n00010008\0bin00000002");
+
+        let mut reader = PktLineReader::new(&data[..]);
+        assert_eq!(
+            reader.read_lines().unwrap(),
+            (vec!["want abc".to_owned()], Some(Packet::Delimiter))
+        );
+        assert_eq!(
+            reader.read_packet().unwrap(),
+            Some(Packet::Data(b"\0bin".to_vec()))
+        );
+        assert_eq!(reader.read_packet().unwrap(), Some(Packet::Flush));
+        assert_eq!(reader.read_packet().unwrap(), Some(Packet::ResponseEnd));
+        assert_eq!(reader.read_packet().unwrap(), None);
+    }
+
+    #[test]
+    fn splits_long_data() {
+        let data = vec![b'x'; MAX_DATA_LEN + 10];
+        let mut writer = PktLineWriter::new(Vec::new());
+        writer.write_data(&data).unwrap();
+        assert!(writer.write_line(&"x".repeat(MAX_DATA_LEN)).is_err());
+        let written = writer.into_inner();
+        assert_eq!(&written[..4], b"fff0");
+
+        let mut reader = PktLineReader::new(&written[..]);
+        let mut read = Vec::new();
+        while let Some(Packet::Data(packet)) = reader.read_packet().unwrap() {
+            read.extend(packet);
+        }
+        assert_eq!(read, data);
+    }
+
+    #[test]
+    fn rejects_invalid_packets() {
+        for data in [&b"00zz"[..], b"0003", b"0009abc"] {
+            assert!(PktLineReader::new(data).read_packet().is_err(), "{data:?}");
+        }
+        assert_eq!(PktLineReader::new(&b"00"[..]).read_packet().unwrap(), None);
+    }
+
+    #[test]
+    fn demultiplexes_side_band() {
+        let mut writer = PktLineWriter::new(Vec::new());
+        writer.write_band(1, b"hello ").unwrap();
+        writer.write_band(2, b"progress\r").unwrap();
+        writer.write_band(1, b"world").unwrap();
+        writer.write_flush().unwrap();
+        writer.write_band(1, b"after flush").unwrap();
+        let data = writer.into_inner();
+
+        let mut progress = Vec::new();
+        let mut output = String::new();
+        let mut rest = &data[..];
+        SidebandReader::new(PktLineReader::new(&mut rest), |message: &[u8]| {
+            progress.extend_from_slice(message)
+        })
+        .read_to_string(&mut output)
+        .unwrap();
+        assert_eq!(output, "hello world");
+        assert_eq!(progress, b"progress\r");
+        // Reading stops at the flush packet.
+        assert_eq!(
+            PktLineReader::new(rest).read_packet().unwrap(),
+            Some(Packet::Data(b"\x01after flush".to_vec()))
+        );
+    }
+
+    #[test]
+    fn side_band_error_fails_read() {
+        let mut writer = PktLineWriter::new(Vec::new());
+        writer.write_band(1, b"partial").unwrap();
+        writer.write_band(3, b"access denied\n").unwrap();
+        let data = writer.into_inner();
+
+        let mut output = Vec::new();
+        let err = SidebandReader::new(PktLineReader::new(&data[..]), |_: &[u8]| ())
+            .read_to_end(&mut output)
+            .unwrap_err();
+        assert_eq!(output, b"partial");
+        assert!(
+            err.to_string().contains("remote error: access denied"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use crate::common::ObjectType;
+use crate::config::Config;
+use crate::error::Context;
+use crate::object_id::ObjectId;
+use crate::transport::{FetchRequest, Transport};
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Set while objects are fetched lazily, so that objects missing during the
+/// fetch itself are not fetched recursively.
+static FETCHING: AtomicBool = AtomicBool::new(false);
+
+/// Which objects a partial clone leaves out, as given to `--filter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectFilter {
+    /// `blob:none`: omit all blobs.
+    BlobNone,
+    /// `blob:limit=<n>`: omit blobs of at least `n` bytes.
+    BlobLimit(u64),
+    /// `tree:<depth>`: omit trees and blobs at least `depth` levels below a
+    /// commit's root tree, which is at depth 0.
+    TreeDepth(usize),
+}
+
+impl ObjectFilter {
+    pub fn parse(spec: &str) -> crate::Result<ObjectFilter> {
+        if spec == "blob:none" {
+            return Ok(ObjectFilter::BlobNone);
+        }
+        if let Some(limit) = spec.strip_prefix("blob:limit=") {
+            let (digits, unit) = match limit.char_indices().last() {
+                Some((idx, 'k' | 'K')) => (&limit[..idx], 1 << 10),
+                Some((idx, 'm' | 'M')) => (&limit[..idx], 1 << 20),
+                Some((idx, 'g' | 'G')) => (&limit[..idx], 1 << 30),
+                _ => (limit, 1),
+            };
+            let limit: u64 = digits
+                .parse()
+                .context(format!("invalid filter-spec '{spec}'"))?;
+            return Ok(ObjectFilter::BlobLimit(limit * unit));
+        }
+        if let Some(depth) = spec.strip_prefix("tree:") {
+            let depth = depth
+                .parse()
+                .context(format!("invalid filter-spec '{spec}'"))?;
+            return Ok(ObjectFilter::TreeDepth(depth));
+        }
+        crate::bail!("invalid filter-spec '{spec}'")
+    }
+
+    /// Whether an object of type `ty` and `size` bytes, found `depth` levels
+    /// below a root tree, passes the filter.
+    pub fn includes(&self, ty: ObjectType, size: u64, depth: usize) -> bool {
+        match (self, ty) {
+            (ObjectFilter::BlobNone, ObjectType::Blob) => false,
+            (ObjectFilter::BlobLimit(limit), ObjectType::Blob) => size < *limit,
+            (ObjectFilter::TreeDepth(max), ObjectType::Tree | ObjectType::Blob) => depth < *max,
+            _ => true,
+        }
+    }
+}
+
+impl Display for ObjectFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjectFilter::BlobNone => write!(f, "blob:none"),
+            ObjectFilter::BlobLimit(limit) => write!(f, "blob:limit={limit}"),
+            ObjectFilter::TreeDepth(depth) => write!(f, "tree:{depth}"),
+        }
+    }
+}
+
+/// The remote that promises the objects left out of a partial clone, named
+/// by `extensions.partialClone`.
+pub fn remote() -> crate::Result<Option<String>> {
+    Ok(Config::read()?
+        .get("extensions.partialclone")
+        .map(str::to_owned))
+}
+
+/// Whether objects may be missing because the repository is a partial clone.
+pub fn is_partial_clone() -> crate::Result<bool> {
+    Ok(remote()?.is_some())
+}
+
+/// Fetch the missing objects `hashes` from the promisor remote. Objects they
+/// reference are fetched too, except blobs.
+pub fn fetch_missing(hashes: &[ObjectId]) -> crate::Result<()> {
+    if hashes.is_empty() {
+        return Ok(());
+    }
+    let config = Config::read()?;
+    let remote = config
+        .get("extensions.partialclone")
+        .context("not a partial clone")?;
+    let url = config
+        .get(&format!("remote.{remote}.url"))
+        .context(format!("promisor remote {remote} has no url"))?;
+    log::debug!("Fetching {} missing objects from {url}", hashes.len());
+
+    FETCHING.store(true, Ordering::SeqCst);
+    let result = Transport::connect(url).and_then(|mut transport| {
+        let request = FetchRequest {
+            wants: hashes.to_vec(),
+            filter: Some(ObjectFilter::BlobNone),
+            ..Default::default()
+        };
+        transport.fetch(&request, |_| ())
+    });
+    FETCHING.store(false, Ordering::SeqCst);
+
+    result.context(format!("fetch missing objects from {remote}"))
+}
+
+/// Fetch the missing object `hash` if the repository is a partial clone and
+/// no lazy fetch is already running. Returns whether it was fetched.
+pub fn lazy_fetch(hash: &ObjectId) -> crate::Result<bool> {
+    if FETCHING.load(Ordering::SeqCst) || !is_partial_clone()? {
+        return Ok(false);
+    }
+    fetch_missing(&[*hash])?;
+
+    Ok(true)
+}
@@ -0,0 +1,336 @@
+use crate::common::{git_dir, Object, ObjectType};
+use crate::error::Context;
+use crate::object_id::ObjectId;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Resolve `name` (e.g. `HEAD` or `refs/heads/main`) to an object hash,
+/// following symbolic refs. Returns `None` if the ref does not exist.
+pub fn read_ref(name: &str) -> crate::Result<Option<ObjectId>> {
+    read_ref_in(&git_dir(), name)
+}
+
+/// Like [`read_ref`], for the repository whose git directory is `git_dir`.
+pub fn read_ref_in(git_dir: &Path, name: &str) -> crate::Result<Option<ObjectId>> {
+    let mut name = name.to_owned();
+    // Symbolic refs may point to other symbolic refs; git limits the depth to 5.
+    for _ in 0..5 {
+        let path = git_dir.join(&name);
+        if path.is_file() {
+            let contents = fs::read_to_string(&path).context(format!("read ref {name}"))?;
+            let contents = contents.trim_end();
+            match contents.strip_prefix("ref: ") {
+                Some(target) => name = target.to_owned(),
+                None => {
+                    let hash = contents.parse().context(format!("invalid ref {name}"))?;
+                    return Ok(Some(hash));
+                }
+            }
+        } else {
+            let hash = read_packed_refs_in(git_dir)?
+                .into_iter()
+                .find(|(packed_name, _)| *packed_name == name)
+                .map(|(_, hash)| hash);
+            return Ok(hash);
+        }
+    }
+
+    crate::bail!("symbolic ref {name} nested too deeply")
+}
+
+/// Return all refs under `refs/` as `(name, hash)` pairs sorted by name.
+/// Loose refs take precedence over packed ones.
+pub fn list_refs() -> crate::Result<Vec<(String, ObjectId)>> {
+    list_refs_in(&git_dir())
+}
+
+/// Like [`list_refs`], for the repository whose git directory is `git_dir`.
+pub fn list_refs_in(git_dir: &Path) -> crate::Result<Vec<(String, ObjectId)>> {
+    let mut refs = read_packed_refs_in(git_dir)?;
+    for name in loose_ref_names(git_dir)? {
+        if let Some(hash) = read_ref_in(git_dir, &name)? {
+            refs.retain(|(packed_name, _)| *packed_name != name);
+            refs.push((name, hash));
+        }
+    }
+    refs.sort();
+
+    Ok(refs)
+}
+
+/// Check that `name` is a valid ref name, following git's
+/// `check-ref-format` rules, so that it cannot address a file outside the
+/// refs. Names outside `refs/` are limited to ones like `HEAD` or `FETCH_HEAD`.
+pub fn check_ref_format(name: &str) -> crate::Result<()> {
+    let valid = if name.contains('/') {
+        name.split('/').all(|component| {
+            !component.is_empty() && !component.starts_with('.') && !component.ends_with(".lock")
+        }) && !name.contains("..")
+            && !name.contains("@{")
+            && !name.ends_with('.')
+            && !name
+                .chars()
+                .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+    } else {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_uppercase() || c == '_')
+    };
+    crate::ensure!(valid, "invalid ref name '{name}'");
+
+    Ok(())
+}
+
+/// Point the loose ref `name` at `hash`.
+pub fn update_ref(name: &str, hash: &ObjectId) -> crate::Result<()> {
+    update_ref_in(&git_dir(), name, hash)
+}
+
+/// Like [`update_ref`], for the repository whose git directory is `git_dir`.
+pub fn update_ref_in(git_dir: &Path, name: &str, hash: &ObjectId) -> crate::Result<()> {
+    check_ref_format(name)?;
+    let path = git_dir.join(name);
+    // `refs/heads/a` and `refs/heads/a/b` cannot both exist.
+    crate::ensure!(
+        !path.is_dir(),
+        RefConflict,
+        "cannot lock ref '{name}': there are refs under '{name}/'"
+    );
+    if let Some(existing) = Path::new(name)
+        .ancestors()
+        .skip(1)
+        .find(|prefix| git_dir.join(prefix).is_file())
+    {
+        crate::bail!(
+            RefConflict,
+            "cannot lock ref '{name}': '{}' exists",
+            existing.display()
+        );
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("create ref dir")?;
+    }
+    fs::write(&path, format!("{hash}\n")).context(format!("write ref {name}"))?;
+
+    Ok(())
+}
+
+/// Make `name` a symbolic ref pointing at the ref `target`.
+pub fn update_symref(name: &str, target: &str) -> crate::Result<()> {
+    check_ref_format(name)?;
+    check_ref_format(target)?;
+    let path = git_dir().join(name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("create ref dir")?;
+    }
+    fs::write(&path, format!("ref: {target}\n")).context(format!("write ref {name}"))?;
+
+    Ok(())
+}
+
+/// Return the ref the loose symbolic ref `name` points at, `None` if it is
+/// missing or not symbolic.
+pub fn read_symref(name: &str) -> crate::Result<Option<String>> {
+    read_symref_in(&git_dir(), name)
+}
+
+/// Like [`read_symref`], for the repository whose git directory is `git_dir`.
+pub fn read_symref_in(git_dir: &Path, name: &str) -> crate::Result<Option<String>> {
+    let path = git_dir.join(name);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(&path).context(format!("read ref {name}"))?;
+
+    Ok(contents.trim_end().strip_prefix("ref: ").map(str::to_owned))
+}
+
+/// Remove `name` from both the loose refs and `packed-refs`.
+pub fn delete_ref(name: &str) -> crate::Result<()> {
+    delete_ref_in(&git_dir(), name)
+}
+
+/// Like [`delete_ref`], for the repository whose git directory is `git_dir`.
+pub fn delete_ref_in(git_dir: &Path, name: &str) -> crate::Result<()> {
+    check_ref_format(name)?;
+    let path = git_dir.join(name);
+    if path.is_file() {
+        fs::remove_file(&path).context(format!("remove ref {name}"))?;
+    }
+    let mut packed = read_packed_refs_in(git_dir)?;
+    let len = packed.len();
+    packed.retain(|(packed_name, _)| packed_name != name);
+    if packed.len() != len {
+        write_packed_refs(git_dir, &packed)?;
+    }
+
+    Ok(())
+}
+
+/// Move all loose refs into `packed-refs` and delete the loose files.
+/// Symbolic refs stay loose.
+pub fn pack_refs() -> crate::Result<()> {
+    let mut symrefs = Vec::new();
+    for name in loose_ref_names(&git_dir())? {
+        if read_symref(&name)?.is_some() {
+            symrefs.push(name);
+        }
+    }
+    let mut refs = list_refs()?;
+    refs.retain(|(name, _)| !symrefs.contains(name));
+    write_packed_refs(&git_dir(), &refs)?;
+    for name in loose_ref_names(&git_dir())? {
+        if symrefs.contains(&name) {
+            continue;
+        }
+        fs::remove_file(git_dir().join(&name)).context(format!("remove ref {name}"))?;
+    }
+    remove_empty_dirs(&git_dir().join("refs"))?;
+
+    Ok(())
+}
+
+pub fn read_packed_refs() -> crate::Result<Vec<(String, ObjectId)>> {
+    read_packed_refs_in(&git_dir())
+}
+
+fn read_packed_refs_in(git_dir: &Path) -> crate::Result<Vec<(String, ObjectId)>> {
+    let contents = match fs::read_to_string(git_dir.join("packed-refs")) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).context("read packed-refs"),
+    };
+    let refs = contents
+        .lines()
+        // Skip the header and peeled tag lines.
+        .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+        .filter_map(|line| {
+            let (hash, name) = line.split_once(' ')?;
+            Some((name.to_owned(), hash.parse().ok()?))
+        })
+        .collect();
+
+    Ok(refs)
+}
+
+fn write_packed_refs(git_dir: &Path, refs: &[(String, ObjectId)]) -> crate::Result<()> {
+    let mut contents = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+    for (name, hash) in refs {
+        contents.push_str(&format!("{hash} {name}\n"));
+        if let Some(peeled) = peel_tag(hash)? {
+            contents.push_str(&format!("^{peeled}\n"));
+        }
+    }
+    let path = git_dir.join("packed-refs");
+    let tmp_path = git_dir.join("packed-refs.lock");
+    fs::write(&tmp_path, contents).context("write packed-refs")?;
+    fs::rename(&tmp_path, path).context("rename packed-refs")?;
+
+    Ok(())
+}
+
+/// If `hash` is an annotated tag, return the non-tag object it ultimately points to.
+pub fn peel_tag(hash: &ObjectId) -> crate::Result<Option<ObjectId>> {
+    let mut current = *hash;
+    let mut peeled = None;
+    while let Ok(object) = Object::read(&current) {
+        if object.ty != ObjectType::Tag {
+            break;
+        }
+        match object.references()?.into_iter().next() {
+            Some(target) => {
+                peeled = Some(target);
+                current = target;
+            }
+            None => break,
+        }
+    }
+
+    Ok(peeled)
+}
+
+fn loose_ref_names(git_dir: &Path) -> crate::Result<Vec<String>> {
+    fn walk(git_dir: &Path, dir: &Path, names: &mut Vec<String>) -> crate::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                walk(git_dir, &path, names)?;
+            } else if path.extension().is_none_or(|ext| ext != "lock") {
+                let name = path
+                    .strip_prefix(git_dir)?
+                    .to_str()
+                    .context("ref name utf-8")?;
+                names.push(name.to_owned());
+            }
+        }
+        Ok(())
+    }
+
+    let mut names = Vec::new();
+    let refs_dir = git_dir.join("refs");
+    if refs_dir.is_dir() {
+        walk(git_dir, &refs_dir, &mut names)?;
+    }
+
+    Ok(names)
+}
+
+/// Remove empty directories below `dir`, keeping `dir` itself and
+/// the `refs/heads` and `refs/tags` directories git always creates.
+fn remove_empty_dirs(dir: &Path) -> crate::Result<()> {
+    let subdirs: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir())
+        .collect();
+    for subdir in subdirs {
+        remove_empty_dirs(&subdir)?;
+        let keep = subdir.ends_with("refs/heads") || subdir.ends_with("refs/tags");
+        if !keep && fs::read_dir(&subdir)?.next().is_none() {
+            fs::remove_dir(&subdir)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_ref_names() {
+        for valid in [
+            "HEAD",
+            "FETCH_HEAD",
+            "refs/heads/main",
+            "refs/tags/v1.0",
+            "refs/heads/a-b/c_d",
+        ] {
+            assert!(check_ref_format(valid).is_ok(), "{valid:?}");
+        }
+        for invalid in [
+            "",
+            "config",
+            "refs/../../hooks/x",
+            "refs/heads/../../../etc",
+            "refs/heads/.hidden",
+            "refs/heads/main.lock",
+            "refs//heads",
+            "refs/heads/",
+            "/refs/heads/main",
+            "refs/heads/main.",
+            "refs/heads/a b",
+            "refs/heads/a\tb",
+            "refs/heads/a~1",
+            "refs/heads/a^",
+            "refs/heads/a:b",
+            "refs/heads/a?",
+            "refs/heads/a*",
+            "refs/heads/a[b",
+            "refs/heads/a\\b",
+            "refs/heads/a@{1}",
+        ] {
+            assert!(check_ref_format(invalid).is_err(), "{invalid:?}");
+        }
+    }
+}
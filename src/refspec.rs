@@ -0,0 +1,152 @@
+use std::fmt::Display;
+
+/// A refspec such as `+refs/heads/*:refs/remotes/origin/*`, `main` or `:refs/heads/old`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refspec {
+    /// Update the destination even if it is not a fast-forward.
+    pub force: bool,
+    /// Source ref. Empty for a delete (`:dst`).
+    pub src: String,
+    /// Destination ref. `None` if the refspec has no `:`.
+    pub dst: Option<String>,
+}
+
+impl Refspec {
+    pub fn parse(spec: &str) -> crate::Result<Refspec> {
+        let (force, spec) = match spec.strip_prefix('+') {
+            Some(spec) => (true, spec),
+            None => (false, spec),
+        };
+        let (src, dst) = match spec.split_once(':') {
+            Some((src, dst)) => (src.to_owned(), Some(dst.to_owned())),
+            None => (spec.to_owned(), None),
+        };
+        crate::ensure!(
+            src.matches('*').count() == dst.as_deref().unwrap_or(&src).matches('*').count()
+                && src.matches('*').count() <= 1,
+            "invalid refspec {spec:?}"
+        );
+
+        Ok(Refspec { force, src, dst })
+    }
+
+    pub fn is_glob(&self) -> bool {
+        self.src.contains('*')
+    }
+
+    pub fn is_delete(&self) -> bool {
+        self.src.is_empty()
+    }
+
+    /// If `name` matches the source side, return the corresponding destination.
+    pub fn map_src(&self, name: &str) -> Option<String> {
+        let dst = self.dst.as_deref()?;
+        map_glob(&self.src, dst, name)
+    }
+
+    /// If `name` matches the destination side, return the corresponding source.
+    pub fn map_dst(&self, name: &str) -> Option<String> {
+        let dst = self.dst.as_deref()?;
+        map_glob(dst, &self.src, name)
+    }
+}
+
+impl Display for Refspec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.force {
+            f.write_str("+")?;
+        }
+        f.write_str(&self.src)?;
+        if let Some(dst) = &self.dst {
+            write!(f, ":{dst}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Match `name` against `pattern` (which may contain one `*`) and
+/// substitute the matched part into `replacement`.
+fn map_glob(pattern: &str, replacement: &str, name: &str) -> Option<String> {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => {
+            let matched = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            Some(replacement.replacen('*', matched, 1))
+        }
+        None => (pattern == name).then(|| replacement.to_owned()),
+    }
+}
+
+/// Expand a short ref name such as `main` or `v1.0` to the full name of an
+/// existing local ref, trying the same locations as git.
+pub fn expand_local(name: &str) -> crate::Result<Option<String>> {
+    for candidate in [
+        name.to_owned(),
+        format!("refs/{name}"),
+        format!("refs/tags/{name}"),
+        format!("refs/heads/{name}"),
+        format!("refs/remotes/{name}"),
+        format!("refs/remotes/{name}/HEAD"),
+    ] {
+        if crate::refs::read_ref(&candidate)?.is_some() {
+            return Ok(Some(candidate));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_refspecs() {
+        let spec = Refspec::parse("+refs/heads/*:refs/remotes/origin/*").unwrap();
+        assert!(spec.force && spec.is_glob() && !spec.is_delete());
+        assert_eq!(spec.src, "refs/heads/*");
+        assert_eq!(spec.dst.as_deref(), Some("refs/remotes/origin/*"));
+        assert_eq!(spec.to_string(), "+refs/heads/*:refs/remotes/origin/*");
+
+        let spec = Refspec::parse("main").unwrap();
+        assert!(!spec.force && !spec.is_glob());
+        assert_eq!((spec.src.as_str(), spec.dst), ("main", None));
+
+        let spec = Refspec::parse(":refs/heads/old").unwrap();
+        assert!(spec.is_delete());
+        assert_eq!(spec.dst.as_deref(), Some("refs/heads/old"));
+
+        for invalid in ["refs/heads/*:refs/heads/main", "a:b/*", "refs/*/*:refs/*/*"] {
+            assert!(Refspec::parse(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn maps_names() {
+        let spec = Refspec::parse("refs/heads/*:refs/remotes/origin/*").unwrap();
+        assert_eq!(
+            spec.map_src("refs/heads/feature/x").as_deref(),
+            Some("refs/remotes/origin/feature/x")
+        );
+        assert_eq!(spec.map_src("refs/tags/v1"), None);
+        assert_eq!(
+            spec.map_dst("refs/remotes/origin/main").as_deref(),
+            Some("refs/heads/main")
+        );
+
+        let spec = Refspec::parse("refs/heads/main:refs/heads/backup").unwrap();
+        assert_eq!(
+            spec.map_src("refs/heads/main").as_deref(),
+            Some("refs/heads/backup")
+        );
+        assert_eq!(spec.map_src("refs/heads/mainline"), None);
+
+        let spec = Refspec::parse("refs/heads/*-rc:refs/rc/*").unwrap();
+        assert_eq!(
+            spec.map_src("refs/heads/v2-rc").as_deref(),
+            Some("refs/rc/v2")
+        );
+        assert_eq!(spec.map_src("refs/heads/v2"), None);
+
+        assert_eq!(Refspec::parse("main").unwrap().map_src("main"), None);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::common::{read_tree, Commit, Object, ObjectType};
+use crate::error::Context;
+use crate::object_id::ObjectId;
+use crate::object_store::{self, ObjectStore};
+use crate::refs;
+use crate::refspec;
+
+/// Abbreviated object names must have at least this many hex digits.
+const MIN_ABBREV: usize = 4;
+
+/// Resolve a revision to its ref name, if it names a ref, and object hash.
+/// A revision is a ref, a full or abbreviated object name, optionally
+/// followed by `~<n>`, `^<n>` and `^{<type>}` suffixes. Like in git, a full
+/// object name is taken as is, whether or not the object exists.
+pub fn resolve(rev: &str) -> crate::Result<(Option<String>, ObjectId)> {
+    let split = rev.find(['~', '^']).unwrap_or(rev.len());
+    let (base, mut suffix) = rev.split_at(split);
+    let (mut name, mut hash) = match base.parse::<ObjectId>() {
+        Ok(object) => (None, object),
+        Err(_) => match refspec::expand_local(base)? {
+            Some(name) => {
+                let hash = refs::read_ref(&name)?.context(format!("bad revision '{rev}'"))?;
+                (Some(name), hash)
+            }
+            None => (
+                None,
+                resolve_abbrev(base)?.context(format!("bad revision '{rev}'"))?,
+            ),
+        },
+    };
+
+    while let Some(operator) = suffix.chars().next() {
+        name = None;
+        if let Some(peeled) = suffix.strip_prefix("^{") {
+            let end = peeled.find('}').context(format!("bad revision '{rev}'"))?;
+            hash = peel_to_type(&hash, &peeled[..end])?;
+            suffix = &peeled[end + 1..];
+            continue;
+        }
+        let digits = suffix[1..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(suffix.len(), |end| end + 1);
+        let count: usize = match &suffix[1..digits] {
+            "" => 1,
+            count => count.parse().context(format!("bad revision '{rev}'"))?,
+        };
+        suffix = &suffix[digits..];
+        hash = match (operator, count) {
+            (_, 0) => hash,
+            // `~<n>` follows first parents, `^<n>` selects the n-th parent.
+            ('~', count) => {
+                for _ in 0..count {
+                    hash = first_parent(&hash, rev)?;
+                }
+                hash
+            }
+            (_, count) => Commit::read(&peel(&hash)?)?
+                .parents
+                .into_iter()
+                .nth(count - 1)
+                .context(format!("bad revision '{rev}'"))?,
+        };
+    }
+
+    Ok((name, hash))
+}
+
+/// Resolve an object name: a revision, or `<rev>:<path>` for the object at
+/// `path` in the tree of `rev`.
+pub fn resolve_object(name: &str) -> crate::Result<ObjectId> {
+    let Some((rev, path)) = name.split_once(':') else {
+        return Ok(resolve(name)?.1);
+    };
+    crate::ensure!(!rev.is_empty(), "not a valid object name {name}");
+    let tree = peel_to_tree(&resolve(rev)?.1)?;
+
+    find_path(&tree, path)?.context(format!("path '{path}' does not exist in '{rev}'"))
+}
+
+/// The path of an object name of the form `<rev>:<path>`.
+pub fn object_path(name: &str) -> Option<&str> {
+    name.split_once(':').map(|(_, path)| path)
+}
+
+/// Resolve a tree-ish to a tree: an object name as for [`resolve_object`]
+/// naming a tree, or a commit or tag whose tree is taken.
+pub fn resolve_tree(treeish: &str) -> crate::Result<ObjectId> {
+    let hash = resolve_object(treeish)?;
+    if treeish.contains(':') {
+        crate::ensure!(
+            Object::read_header(&hash)?.0 == ObjectType::Tree,
+            "{treeish} is not a tree object"
+        );
+    }
+
+    peel_to_tree(&hash)
+}
+
+/// The tree `hash` names: the tree itself or the tree of a commit,
+/// following annotated tags.
+pub fn peel_to_tree(hash: &ObjectId) -> crate::Result<ObjectId> {
+    let hash = peel(hash)?;
+    match Object::read_header(&hash)?.0 {
+        ObjectType::Tree => Ok(hash),
+        ObjectType::Commit => Ok(Commit::read(&hash)?.tree),
+        ty => crate::bail!("{hash} is a {ty}, not a tree-ish"),
+    }
+}
+
+/// The object at `path` below the tree `tree`, `None` if there is none. An
+/// empty path names the tree itself.
+pub fn find_path(tree: &ObjectId, path: &str) -> crate::Result<Option<ObjectId>> {
+    let mut hash = *tree;
+    let mut ty = ObjectType::Tree;
+    for component in path.split('/').filter(|component| !component.is_empty()) {
+        // Only trees have entries, paths through blobs and submodules do not exist.
+        if ty != ObjectType::Tree {
+            return Ok(None);
+        }
+        match read_tree(&hash)?
+            .into_iter()
+            .find(|entry| entry.file == component)
+        {
+            Some(entry) => (hash, ty) = (entry.hash, entry.ty),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(hash))
+}
+
+/// The object whose name starts with the hex digits `prefix`, `None` if
+/// `prefix` is too short or no object matches.
+fn resolve_abbrev(prefix: &str) -> crate::Result<Option<ObjectId>> {
+    if prefix.len() < MIN_ABBREV || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+    let prefix = prefix.to_ascii_lowercase();
+    let matches = object_store::repository()?.find_prefix(&prefix)?;
+    crate::ensure!(
+        matches.len() <= 1,
+        AmbiguousObject,
+        "short object ID {prefix} is ambiguous"
+    );
+
+    Ok(matches.into_iter().next())
+}
+
+fn first_parent(hash: &ObjectId, rev: &str) -> crate::Result<ObjectId> {
+    Commit::read(&peel(hash)?)?
+        .parents
+        .into_iter()
+        .next()
+        .context(format!("bad revision '{rev}'"))
+}
+
+/// Peel `hash` for a `^{<ty>}` suffix: follow tags to an object of type
+/// `ty`, or to any object that is not a tag if `ty` is empty. A commit
+/// peels to its tree for `tree`.
+fn peel_to_type(hash: &ObjectId, ty: &str) -> crate::Result<ObjectId> {
+    let peeled = match ty {
+        "object" | "tag" => *hash,
+        "tree" => peel_to_tree(hash)?,
+        _ => peel(hash)?,
+    };
+    crate::ensure!(
+        matches!(ty, "" | "object") || Object::read_header(&peeled)?.0.to_string() == ty,
+        "{hash} cannot be peeled to a {ty}"
+    );
+
+    Ok(peeled)
+}
+
+/// The commit an annotated tag points to, or `hash` itself.
+fn peel(hash: &ObjectId) -> crate::Result<ObjectId> {
+    Ok(refs::peel_tag(hash)?.unwrap_or(*hash))
+}
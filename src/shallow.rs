@@ -0,0 +1,51 @@
+use crate::common::git_dir;
+use crate::error::Context;
+use crate::object_id::ObjectId;
+use std::{collections::BTreeSet, fs};
+
+/// Commits whose parents are missing from a shallow repository, listed in
+/// `shallow`. History walks treat them as having no parents.
+pub fn read() -> crate::Result<BTreeSet<ObjectId>> {
+    match fs::read_to_string(git_dir().join("shallow")) {
+        Ok(contents) => contents
+            .lines()
+            .map(|line| line.parse().context("invalid shallow file"))
+            .collect(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BTreeSet::new()),
+        Err(err) => Err(err).context("read shallow"),
+    }
+}
+
+/// Replace the list of shallow commits, removing the file if there are none.
+pub fn write(shallow: &BTreeSet<ObjectId>) -> crate::Result<()> {
+    let path = git_dir().join("shallow");
+    if shallow.is_empty() {
+        return match fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).context("remove shallow")
+            }
+            _ => Ok(()),
+        };
+    }
+    let contents: String = shallow.iter().map(|hash| format!("{hash}\n")).collect();
+    fs::write(path, contents).context("write shallow")
+}
+
+/// Add the new boundary commits `shallow` and remove `unshallow`, whose
+/// parents have been fetched.
+pub fn update(shallow: &[ObjectId], unshallow: &[ObjectId]) -> crate::Result<()> {
+    if shallow.is_empty() && unshallow.is_empty() {
+        return Ok(());
+    }
+    let mut current = read()?;
+    current.extend(shallow.iter().copied());
+    for hash in unshallow {
+        current.remove(hash);
+    }
+    write(&current)
+}
+
+/// Whether the repository has a shallow history.
+pub fn is_shallow() -> bool {
+    git_dir().join("shallow").exists()
+}
@@ -0,0 +1,1448 @@
+use crate::bundle::{self, Bundle};
+use crate::common::{find_git_dir, git_dir, is_bare};
+use crate::config::Config;
+use crate::credential::Credential;
+use crate::error::{Context, Error};
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::pack;
+use crate::pktline::{Packet, PktLineReader, PktLineWriter, SidebandReader};
+use crate::promisor::ObjectFilter;
+use crate::refs;
+use crate::shallow;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    fs,
+    io::{Read, Write},
+    net::{Shutdown, TcpStream},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+/// Default port of the git daemon.
+pub const DAEMON_PORT: u16 = 9418;
+
+/// Depth requested to fetch the complete history of a shallow repository.
+pub const INFINITE_DEPTH: usize = 0x7fffffff;
+
+/// Number of `have` lines sent per protocol v0 negotiation round.
+const HAVES_PER_ROUND: usize = 32;
+
+/// A ref advertised by a remote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteRef {
+    pub name: String,
+    pub hash: ObjectId,
+    /// Target of an annotated tag.
+    pub peeled: Option<ObjectId>,
+    /// Target of a symbolic ref such as `HEAD`.
+    pub symref_target: Option<String>,
+}
+
+/// What to ask for in a fetch.
+#[derive(Debug, Clone, Default)]
+pub struct FetchRequest {
+    pub wants: Vec<ObjectId>,
+    /// Local commits to offer, most recent first.
+    pub haves: Vec<ObjectId>,
+    /// Also send annotated tags pointing at objects in the pack.
+    pub include_tag: bool,
+    /// Commits our history is cut off at.
+    pub shallow: Vec<ObjectId>,
+    pub deepen: Deepen,
+    /// Leave out objects for a partial clone; the received pack is marked as
+    /// coming from a promisor remote.
+    pub filter: Option<ObjectFilter>,
+}
+
+/// How much history a shallow fetch asks for.
+#[derive(Debug, Clone, Default)]
+pub struct Deepen {
+    /// Number of commits below the wanted tips, or below the current shallow
+    /// commits if `relative`.
+    pub depth: Option<usize>,
+    /// Only commits newer than this time in seconds since the epoch.
+    pub since: Option<i64>,
+    /// Only commits not reachable from these remote refs.
+    pub not: Vec<String>,
+    pub relative: bool,
+}
+
+impl Deepen {
+    pub fn is_empty(&self) -> bool {
+        self.depth.is_none() && self.since.is_none() && self.not.is_empty()
+    }
+}
+
+/// The pack sent by a remote and the changes to our shallow commits that
+/// come with it.
+#[derive(Debug, Default)]
+pub struct FetchResponse {
+    pub pack: Vec<u8>,
+    /// Commits whose parents were not sent.
+    pub shallow: Vec<ObjectId>,
+    /// Previously shallow commits whose parents were sent.
+    pub unshallow: Vec<ObjectId>,
+}
+
+/// Response to ref discovery.
+#[derive(Debug, Clone)]
+pub enum Advertisement {
+    /// Protocol v0/v1: all refs, with the capabilities sent on the first one.
+    V0 {
+        refs: Vec<RemoteRef>,
+        capabilities: Vec<String>,
+    },
+    /// Protocol v2: only capabilities, refs are listed with `ls-refs`.
+    V2 { capabilities: Vec<String> },
+}
+
+impl Advertisement {
+    pub fn capabilities(&self) -> &[String] {
+        match self {
+            Advertisement::V0 { capabilities, .. } | Advertisement::V2 { capabilities } => {
+                capabilities
+            }
+        }
+    }
+
+    /// Whether `capability` was advertised, either bare or with a value.
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities().iter().any(|c| {
+            c == capability
+                || c.strip_prefix(capability)
+                    .is_some_and(|rest| rest.starts_with('='))
+        })
+    }
+
+    pub fn is_v2(&self) -> bool {
+        matches!(self, Advertisement::V2 { .. })
+    }
+
+    /// The object format the remote uses.
+    pub fn object_format(&self) -> crate::Result<ObjectFormat> {
+        object_format(self.capabilities())
+    }
+}
+
+/// The object format named by the `object-format` capability in
+/// `capabilities`, SHA-1 if it is missing.
+pub fn object_format(capabilities: &[String]) -> crate::Result<ObjectFormat> {
+    capabilities
+        .iter()
+        .find_map(|capability| capability.strip_prefix("object-format="))
+        .map_or(Ok(ObjectFormat::Sha1), str::parse)
+}
+
+/// Read a ref advertisement, skipping the `# service=...` header smart HTTP
+/// servers send before it.
+pub fn read_advertisement(reader: &mut PktLineReader<impl Read>) -> crate::Result<Advertisement> {
+    let (mut lines, _) = reader.read_lines()?;
+    if lines
+        .first()
+        .is_some_and(|line| line.starts_with("# service="))
+    {
+        // The service header is terminated by its own flush packet.
+        (lines, _) = reader.read_lines()?;
+    }
+    if let Some(error) = lines.first().and_then(|line| line.strip_prefix("ERR ")) {
+        crate::bail!(Protocol, "remote error: {error}");
+    }
+    if lines.first().is_some_and(|line| line == "version 2") {
+        lines.remove(0);
+        return Ok(Advertisement::V2 {
+            capabilities: lines,
+        });
+    }
+    if lines.first().is_some_and(|line| line == "version 1") {
+        lines.remove(0);
+    }
+
+    let mut refs: Vec<RemoteRef> = Vec::new();
+    let mut capabilities = Vec::new();
+    for (idx, line) in lines.iter().enumerate() {
+        let line = match line.split_once('\0') {
+            Some((line, caps)) if idx == 0 => {
+                capabilities = caps.split(' ').map(str::to_owned).collect();
+                line
+            }
+            _ => line.as_str(),
+        };
+        let (hash, name) = line
+            .split_once(' ')
+            .context(format!("malformed ref advertisement line {line:?}"))?;
+        let hash = hash
+            .parse()
+            .context(format!("malformed ref advertisement line {line:?}"))?;
+        if let Some(tag) = name.strip_suffix("^{}") {
+            if let Some(tag_ref) = refs.iter_mut().find(|r| r.name == tag) {
+                tag_ref.peeled = Some(hash);
+            }
+        } else {
+            refs.push(RemoteRef {
+                name: name.to_owned(),
+                hash,
+                peeled: None,
+                symref_target: None,
+            });
+        }
+    }
+    for capability in &capabilities {
+        if let Some((name, target)) = capability
+            .strip_prefix("symref=")
+            .and_then(|symref| symref.split_once(':'))
+        {
+            if let Some(symref) = refs.iter_mut().find(|r| r.name == name) {
+                symref.symref_target = Some(target.to_owned());
+            }
+        }
+    }
+
+    Ok(Advertisement::V0 { refs, capabilities })
+}
+
+/// Write a protocol v0 ref advertisement with `capabilities` on the first ref.
+/// Peeled tags are passed as refs named `<tag>^{}`.
+pub fn write_advertisement(
+    writer: &mut PktLineWriter<impl Write>,
+    refs: &[(String, ObjectId)],
+    capabilities: &[String],
+) -> crate::Result<()> {
+    let capabilities = capabilities.join(" ");
+    match refs.split_first() {
+        Some(((name, hash), rest)) => {
+            writer.write_line(&format!("{hash} {name}\0{capabilities}"))?;
+            for (name, hash) in rest {
+                writer.write_line(&format!("{hash} {name}"))?;
+            }
+        }
+        // An empty repository advertises only its capabilities.
+        None => writer.write_line(&format!(
+            "{} capabilities^{{}}\0{capabilities}",
+            ObjectFormat::current()?.null()
+        ))?,
+    }
+    writer.write_flush()
+}
+
+/// Connection to a remote for fetching, chosen by the form of its URL.
+pub enum Transport {
+    Http(HttpTransport),
+    Stream(StreamTransport),
+    Local(LocalTransport),
+    Bundle(BundleTransport),
+}
+
+impl Transport {
+    pub fn connect(url: &str) -> crate::Result<Transport> {
+        if is_ssh_url(url) || is_daemon_url(url) {
+            Ok(Transport::Stream(StreamTransport::connect(
+                url,
+                "git-upload-pack",
+            )?))
+        } else if is_bundle_url(url) {
+            Ok(Transport::Bundle(BundleTransport::open(url)?))
+        } else if is_local_url(url) {
+            Ok(Transport::Local(LocalTransport::open(url)?))
+        } else {
+            Ok(Transport::Http(HttpTransport::connect(url)?))
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        match self {
+            Transport::Http(transport) => transport.url(),
+            Transport::Stream(transport) => transport.url(),
+            Transport::Local(transport) => transport.url(),
+            Transport::Bundle(transport) => transport.url(),
+        }
+    }
+
+    /// The object format of the remote repository.
+    pub fn object_format(&self) -> crate::Result<ObjectFormat> {
+        match self {
+            Transport::Http(transport) => transport.advertisement.object_format(),
+            Transport::Stream(transport) => transport.advertisement.object_format(),
+            Transport::Local(transport) => transport.object_format(),
+            Transport::Bundle(transport) => Ok(transport.bundle.object_format),
+        }
+    }
+
+    /// List remote refs whose names start with one of `prefixes` (all refs if empty).
+    pub fn ls_refs(&mut self, prefixes: &[String]) -> crate::Result<Vec<RemoteRef>> {
+        match self {
+            Transport::Http(transport) => transport.ls_refs(prefixes),
+            Transport::Stream(transport) => transport.ls_refs(prefixes),
+            Transport::Local(transport) => transport.ls_refs(prefixes),
+            Transport::Bundle(transport) => Ok(transport.ls_refs(prefixes)),
+        }
+    }
+
+    /// Download the objects needed for `request` into the object store and
+    /// update the shallow commits. Progress messages from the remote are
+    /// passed to `progress`.
+    pub fn fetch(
+        &mut self,
+        request: &FetchRequest,
+        progress: impl FnMut(&[u8]),
+    ) -> crate::Result<()> {
+        let response = match self {
+            Transport::Http(transport) => transport.fetch(request, progress)?,
+            Transport::Stream(transport) => transport.fetch(request, progress)?,
+            Transport::Local(transport) => {
+                if !request.deepen.is_empty() {
+                    eprintln!("warning: shallow fetches from local repositories are not supported, fetching all history");
+                }
+                if request.filter.is_some() {
+                    eprintln!("warning: filtering is not supported for local repositories, fetching all objects");
+                }
+                return transport.copy_objects(false);
+            }
+            Transport::Bundle(transport) => {
+                if !request.deepen.is_empty() {
+                    eprintln!("warning: shallow fetches from bundles are not supported, fetching all history");
+                }
+                transport.fetch()?
+            }
+        };
+        let name = pack::index_pack(&response.pack).context("index received pack")?;
+        if request.filter.is_some() {
+            pack::mark_promisor(&name)?;
+        }
+        shallow::update(&response.shallow, &response.unshallow)?;
+
+        Ok(())
+    }
+}
+
+/// Fail unless the remote's object format `remote` is the repository's.
+pub fn check_object_format(remote: ObjectFormat) -> crate::Result<()> {
+    let local = ObjectFormat::current()?;
+    crate::ensure!(
+        remote == local,
+        "mismatched object format: the remote uses {remote}, the repository {local}"
+    );
+
+    Ok(())
+}
+
+/// Whether `url` names a repository on this machine: a `file://` URL or a path.
+pub fn is_local_url(url: &str) -> bool {
+    url.starts_with("file://") || (!url.contains("://") && !is_ssh_url(url))
+}
+
+/// Whether `url` is the path or `file://` URL of a bundle file.
+pub fn is_bundle_url(url: &str) -> bool {
+    is_local_url(url) && bundle::is_bundle(url.strip_prefix("file://").unwrap_or(url))
+}
+
+/// A bundle file fetched from as if it were a repository.
+pub struct BundleTransport {
+    url: String,
+    bundle: Bundle,
+}
+
+impl BundleTransport {
+    pub fn open(url: &str) -> crate::Result<BundleTransport> {
+        Ok(BundleTransport {
+            url: url.to_owned(),
+            bundle: Bundle::read(url.strip_prefix("file://").unwrap_or(url))?,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// List the bundle's refs whose names start with one of `prefixes` (all
+    /// refs if empty).
+    pub fn ls_refs(&self, prefixes: &[String]) -> Vec<RemoteRef> {
+        self.bundle
+            .refs
+            .iter()
+            .filter(|(_, name)| prefixes.is_empty() || prefixes.iter().any(|p| name.starts_with(p)))
+            .map(|(hash, name)| RemoteRef {
+                name: name.clone(),
+                hash: *hash,
+                peeled: None,
+                symref_target: None,
+            })
+            .collect()
+    }
+
+    /// Return the bundle's pack, which always contains all of its objects,
+    /// if the repository has the prerequisite commits.
+    pub fn fetch(&self) -> crate::Result<FetchResponse> {
+        let missing = self.bundle.missing_prerequisites()?;
+        crate::ensure!(
+            missing.is_empty(),
+            "Repository lacks these prerequisite commits:\n{}",
+            missing
+                .iter()
+                .map(ObjectId::to_string)
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+
+        Ok(FetchResponse {
+            pack: self.bundle.pack.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Access to a repository on the same filesystem, read and written directly.
+pub struct LocalTransport {
+    url: String,
+    git_dir: PathBuf,
+}
+
+impl LocalTransport {
+    /// Open the repository at the path or `file://` URL `url`, either a
+    /// working tree with a `.git` directory or a bare repository.
+    pub fn open(url: &str) -> crate::Result<LocalTransport> {
+        let path = Path::new(url.strip_prefix("file://").unwrap_or(url));
+        let git_dir = find_git_dir(path)?;
+
+        Ok(LocalTransport {
+            url: url.to_owned(),
+            git_dir,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    /// Whether the repository has no working tree.
+    pub fn is_bare(&self) -> bool {
+        is_bare(&self.git_dir)
+    }
+
+    pub fn object_format(&self) -> crate::Result<ObjectFormat> {
+        ObjectFormat::from_config(&Config::read_from(self.git_dir.join("config"))?)
+    }
+
+    /// List refs whose names start with one of `prefixes` (all refs if empty),
+    /// including `HEAD`.
+    pub fn ls_refs(&self, prefixes: &[String]) -> crate::Result<Vec<RemoteRef>> {
+        let mut refs = Vec::new();
+        if let Some(hash) = refs::read_ref_in(&self.git_dir, "HEAD")? {
+            refs.push(RemoteRef {
+                name: "HEAD".to_owned(),
+                hash,
+                peeled: None,
+                symref_target: refs::read_symref_in(&self.git_dir, "HEAD")?,
+            });
+        }
+        for (name, hash) in refs::list_refs_in(&self.git_dir)? {
+            refs.push(RemoteRef {
+                name,
+                hash,
+                peeled: None,
+                symref_target: None,
+            });
+        }
+        refs.retain(|r| prefixes.is_empty() || prefixes.iter().any(|p| r.name.starts_with(p)));
+
+        Ok(refs)
+    }
+
+    /// Copy the loose objects and packs missing from the local object store,
+    /// hard-linking instead of copying if `hardlink` is set and possible.
+    pub fn copy_objects(&self, hardlink: bool) -> crate::Result<()> {
+        let objects_dir = self.git_dir.join("objects");
+        for entry in fs::read_dir(&objects_dir).context("read remote object store")? {
+            let dir = entry?.path();
+            let Some(name) = dir.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let is_loose_dir = name.len() == 2 && name.chars().all(|c| c.is_ascii_hexdigit());
+            if !is_loose_dir && name != "pack" {
+                continue;
+            }
+            let mut files = fs::read_dir(&dir)?
+                .map(|entry| Ok(entry?.path()))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            // Copy packs before their indexes so no index refers to a missing pack.
+            files.sort_by_key(|file| file.extension().is_some_and(|ext| ext == "idx"));
+            for file in files {
+                let is_pack_file = file
+                    .extension()
+                    .is_some_and(|ext| ext == "pack" || ext == "idx");
+                if name == "pack" && !is_pack_file {
+                    continue;
+                }
+                let target = git_dir()
+                    .join("objects")
+                    .join(name)
+                    .join(file.file_name().context("object file name")?);
+                if target.exists() {
+                    continue;
+                }
+                fs::create_dir_all(target.parent().context("object dir")?)?;
+                // Hard links fail across filesystems, fall back to copying.
+                if !hardlink || fs::hard_link(&file, &target).is_err() {
+                    fs::copy(&file, &target).context(format!("copy {file:?}"))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// HTTP client for the repository at a URL. Requests carry the
+/// `http.extraHeader` headers and any credentials embedded in the URL; on
+/// `401 Unauthorized` the credential helpers or the user are asked for a
+/// username and password and the request is retried.
+pub struct HttpClient {
+    client: reqwest::blocking::Client,
+    /// The repository URL without credentials.
+    url: String,
+    extra_headers: Vec<(String, String)>,
+    credential: RefCell<Credential>,
+    /// Whether the helpers were told the credential works.
+    approved: Cell<bool>,
+}
+
+impl HttpClient {
+    pub fn new(url: &str) -> crate::Result<HttpClient> {
+        let mut parsed = reqwest::Url::parse(url)
+            .map_err(|err| Error::Other(Box::new(err)))
+            .context(format!("invalid URL {url}"))?;
+        let credential = Credential::from_url(&parsed);
+        // Both only fail for URLs that cannot carry credentials anyway.
+        let _ = parsed.set_username("");
+        let _ = parsed.set_password(None);
+        let mut extra_headers = Vec::new();
+        for header in Config::read_all()?.get_all("http.extraheader") {
+            // An empty value clears the headers configured before it.
+            if header.is_empty() {
+                extra_headers.clear();
+                continue;
+            }
+            let (name, value) = header
+                .split_once(':')
+                .context(format!("invalid http.extraHeader {header:?}"))?;
+            extra_headers.push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+
+        Ok(HttpClient {
+            client: reqwest::blocking::Client::new(),
+            url: parsed.as_str().trim_end_matches('/').to_owned(),
+            extra_headers,
+            credential: RefCell::new(credential),
+            approved: Cell::new(false),
+        })
+    }
+
+    /// The repository URL without credentials.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// GET `path` below the repository URL.
+    pub fn get(
+        &self,
+        path: &str,
+        headers: &[(&str, &str)],
+    ) -> crate::Result<reqwest::blocking::Response> {
+        self.send(reqwest::Method::GET, path, headers, None)
+    }
+
+    /// POST `body` to `path` below the repository URL.
+    pub fn post(
+        &self,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> crate::Result<reqwest::blocking::Response> {
+        self.send(reqwest::Method::POST, path, headers, Some(body))
+    }
+
+    fn send(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+    ) -> crate::Result<reqwest::blocking::Response> {
+        let attempt = || {
+            let mut request = self
+                .client
+                .request(method.clone(), format!("{}/{path}", self.url));
+            for (name, value) in &self.extra_headers {
+                request = request.header(name, value);
+            }
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            let credential = self.credential.borrow();
+            if let (Some(username), Some(password)) = (&credential.username, &credential.password) {
+                request = request.basic_auth(username, Some(password));
+            }
+            if let Some(body) = body {
+                request = request.body(body.to_vec());
+            }
+            request
+                .send()
+                .context(format!("request {}/{path}", self.url))
+        };
+
+        let mut response = attempt()?;
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            if !self.credential.borrow().is_complete() {
+                let mut credential = self.credential.borrow().clone();
+                credential.fill()?;
+                *self.credential.borrow_mut() = credential;
+                response = attempt()?;
+            }
+            if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+                self.credential.borrow().reject()?;
+                crate::bail!("Authentication failed for '{}'", self.url);
+            }
+        }
+        if self.credential.borrow().is_complete() && !self.approved.replace(true) {
+            self.credential.borrow().approve()?;
+        }
+
+        Ok(response)
+    }
+}
+
+/// Connection to a remote's upload-pack service over smart HTTP.
+/// Protocol v2 is requested; v0/v1 is used if the remote does not offer it.
+pub struct HttpTransport {
+    client: HttpClient,
+    advertisement: Advertisement,
+}
+
+impl HttpTransport {
+    /// Fetch the ref or capability advertisement of the repository at `url`.
+    pub fn connect(url: &str) -> crate::Result<HttpTransport> {
+        let client = HttpClient::new(url)?;
+        let response = client
+            .get(
+                "info/refs?service=git-upload-pack",
+                &[("Git-Protocol", "version=2")],
+            )
+            .context("request ref advertisement")?;
+        crate::ensure!(
+            response.status().is_success(),
+            "ref discovery failed: {}",
+            response.status()
+        );
+        let advertisement = read_advertisement(&mut PktLineReader::new(response))?;
+        log_protocol(&advertisement);
+
+        Ok(HttpTransport {
+            client,
+            advertisement,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        self.client.url()
+    }
+
+    /// Whether the remote speaks protocol v2.
+    pub fn is_v2(&self) -> bool {
+        self.advertisement.is_v2()
+    }
+
+    /// Whether the remote advertised `capability`, either bare or with a value.
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.advertisement.has_capability(capability)
+    }
+
+    /// List remote refs whose names start with one of `prefixes` (all refs if empty).
+    pub fn ls_refs(&self, prefixes: &[String]) -> crate::Result<Vec<RemoteRef>> {
+        if let Advertisement::V0 { refs, .. } = &self.advertisement {
+            return Ok(filter_refs(refs, prefixes));
+        }
+        let response = self.command("ls-refs", &ls_refs_args(prefixes))?;
+
+        read_ls_refs(&mut PktLineReader::new(response))
+    }
+
+    /// Negotiate and return the pack the remote sends.
+    /// Progress messages from the remote are passed to `progress`.
+    pub fn fetch(
+        &self,
+        request: &FetchRequest,
+        progress: impl FnMut(&[u8]),
+    ) -> crate::Result<FetchResponse> {
+        check_shallow_support(&self.advertisement, request)?;
+        let request = &without_unsupported_filter(&self.advertisement, request);
+        if self.is_v2() {
+            let response = self.command("fetch", &fetch_args(request))?;
+            read_packfile_section(PktLineReader::new(response), progress)
+        } else {
+            self.fetch_v0(request, progress)
+        }
+    }
+
+    /// Protocol v0 fetch using `multi_ack_detailed`. HTTP is stateless, so each
+    /// round repeats the wants and the haves acknowledged as common so far.
+    fn fetch_v0(
+        &self,
+        request: &FetchRequest,
+        progress: impl FnMut(&[u8]),
+    ) -> crate::Result<FetchResponse> {
+        let capabilities = v0_capabilities(&self.advertisement, request)?;
+        let multi_ack = self.has_capability("multi_ack_detailed");
+        let mut response = FetchResponse::default();
+
+        let mut common: Vec<ObjectId> = Vec::new();
+        let mut pending = request.haves.as_slice();
+        // Without multi_ack_detailed we cannot learn which haves are common,
+        // so they are all sent with `done`.
+        while multi_ack && !pending.is_empty() {
+            let (batch, rest) = pending.split_at(pending.len().min(HAVES_PER_ROUND));
+            pending = rest;
+            let mut writer = PktLineWriter::new(Vec::new());
+            write_wants(&mut writer, request, &capabilities)?;
+            write_haves(&mut writer, common.iter().chain(batch), false)?;
+            let mut reader = PktLineReader::new(self.post_upload_pack(writer.into_inner())?);
+            // Every round repeats the shallow info; only the last one counts.
+            if !request.deepen.is_empty() {
+                read_shallow_info(&mut reader, &mut FetchResponse::default())?;
+            }
+            if read_acks(&mut reader, &mut common)? {
+                break;
+            }
+        }
+        let haves = if multi_ack { &common } else { &request.haves };
+
+        let mut writer = PktLineWriter::new(Vec::new());
+        write_wants(&mut writer, request, &capabilities)?;
+        write_haves(&mut writer, haves, true)?;
+        let mut reader = PktLineReader::new(self.post_upload_pack(writer.into_inner())?);
+        if !request.deepen.is_empty() {
+            read_shallow_info(&mut reader, &mut response)?;
+        }
+        response.pack = read_pack_v0(reader, self.has_capability("side-band-64k"), progress)?;
+
+        Ok(response)
+    }
+
+    /// Send a protocol v2 `command` with `args` and return the response stream.
+    fn command(
+        &self,
+        command: &str,
+        args: &[String],
+    ) -> crate::Result<reqwest::blocking::Response> {
+        let mut writer = PktLineWriter::new(Vec::new());
+        let capabilities = v2_capabilities(&self.advertisement)?;
+        write_command(&mut writer, command, &capabilities, args)?;
+
+        self.post_upload_pack(writer.into_inner())
+            .context(format!("{command} request"))
+    }
+
+    fn post_upload_pack(&self, body: Vec<u8>) -> crate::Result<reqwest::blocking::Response> {
+        let mut headers = vec![("Content-Type", "application/x-git-upload-pack-request")];
+        if self.is_v2() {
+            headers.push(("Git-Protocol", "version=2"));
+        }
+        let response = self
+            .client
+            .post("git-upload-pack", &headers, &body)
+            .context("send upload-pack request")?;
+        crate::ensure!(
+            response.status().is_success(),
+            "upload-pack failed: {}",
+            response.status()
+        );
+
+        Ok(response)
+    }
+}
+
+/// Whether `url` is an `ssh://` URL or in the scp-like `[user@]host:path` form.
+pub fn is_ssh_url(url: &str) -> bool {
+    if url.starts_with("ssh://") || url.starts_with("git+ssh://") {
+        return true;
+    }
+    // A colon before the first slash means scp-like syntax rather than a path.
+    !url.contains("://")
+        && url
+            .find(':')
+            .is_some_and(|colon| url.find('/').is_none_or(|slash| colon < slash))
+}
+
+/// Whether `url` is a `git://` URL served by a git daemon.
+pub fn is_daemon_url(url: &str) -> bool {
+    url.starts_with("git://")
+}
+
+/// Connection to a git service on another host over a bidirectional stream:
+/// the stdin and stdout of a command spawned through ssh, or a TCP connection
+/// to a git daemon.
+pub struct StreamTransport {
+    url: String,
+    /// The ssh command, if any.
+    child: Option<Child>,
+    writer: Option<Box<dyn Write>>,
+    reader: Box<dyn Read>,
+    advertisement: Advertisement,
+    /// No request has been written yet.
+    idle: bool,
+}
+
+impl StreamTransport {
+    /// Start `service` (`git-upload-pack` or `git-receive-pack`) for the
+    /// repository at the ssh or `git://` URL `url` and read its advertisement.
+    pub fn connect(url: &str, service: &str) -> crate::Result<StreamTransport> {
+        let (child, writer, mut reader): (_, Box<dyn Write>, Box<dyn Read>) = if is_daemon_url(url)
+        {
+            let (writer, reader) = connect_daemon(url, service)?;
+            (None, Box::new(writer), Box::new(reader))
+        } else {
+            let (child, stdin, stdout) = spawn_ssh(url, service)?;
+            (Some(child), Box::new(stdin), Box::new(stdout))
+        };
+        let advertisement = read_advertisement(&mut PktLineReader::new(&mut reader))
+            .context(format!("read advertisement from {url}"))?;
+        log_protocol(&advertisement);
+
+        Ok(StreamTransport {
+            url: url.to_owned(),
+            child,
+            writer: Some(writer),
+            reader,
+            advertisement,
+            idle: true,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn advertisement(&self) -> &Advertisement {
+        &self.advertisement
+    }
+
+    /// List remote refs whose names start with one of `prefixes` (all refs if empty).
+    pub fn ls_refs(&mut self, prefixes: &[String]) -> crate::Result<Vec<RemoteRef>> {
+        if let Advertisement::V0 { refs, .. } = &self.advertisement {
+            return Ok(filter_refs(refs, prefixes));
+        }
+        let capabilities = v2_capabilities(&self.advertisement)?;
+        write_command(
+            &mut self.writer()?,
+            "ls-refs",
+            &capabilities,
+            &ls_refs_args(prefixes),
+        )?;
+
+        read_ls_refs(&mut self.reader())
+    }
+
+    /// Negotiate and return the pack the remote sends.
+    /// Progress messages from the remote are passed to `progress`.
+    pub fn fetch(
+        &mut self,
+        request: &FetchRequest,
+        progress: impl FnMut(&[u8]),
+    ) -> crate::Result<FetchResponse> {
+        check_shallow_support(&self.advertisement, request)?;
+        let request = &without_unsupported_filter(&self.advertisement, request);
+        if self.advertisement.is_v2() {
+            let capabilities = v2_capabilities(&self.advertisement)?;
+            write_command(
+                &mut self.writer()?,
+                "fetch",
+                &capabilities,
+                &fetch_args(request),
+            )?;
+            return read_packfile_section(self.reader(), progress);
+        }
+
+        // Protocol v0 over a stream is stateful: wants are sent once and each
+        // round only adds haves.
+        let capabilities = v0_capabilities(&self.advertisement, request)?;
+        let multi_ack = self.advertisement.has_capability("multi_ack_detailed");
+        write_wants(&mut self.writer()?, request, &capabilities)?;
+        let mut response = FetchResponse::default();
+        if !request.deepen.is_empty() {
+            read_shallow_info(&mut self.reader(), &mut response)?;
+        }
+        let mut common = Vec::new();
+        let mut pending = request.haves.as_slice();
+        while multi_ack && !pending.is_empty() {
+            let (batch, rest) = pending.split_at(pending.len().min(HAVES_PER_ROUND));
+            pending = rest;
+            write_haves(&mut self.writer()?, batch, false)?;
+            if read_acks(&mut self.reader(), &mut common)? {
+                break;
+            }
+        }
+        let haves: &[ObjectId] = if multi_ack { &[] } else { &request.haves };
+        write_haves(&mut self.writer()?, haves, true)?;
+        let sideband = self.advertisement.has_capability("side-band-64k");
+        response.pack = read_pack_v0(self.reader(), sideband, progress)?;
+
+        Ok(response)
+    }
+
+    /// Writer for requests to the remote service.
+    pub fn writer(&mut self) -> crate::Result<PktLineWriter<&mut dyn Write>> {
+        let writer = self
+            .writer
+            .as_deref_mut()
+            .context("connection already closed")?;
+        self.idle = false;
+        Ok(PktLineWriter::new(writer))
+    }
+
+    /// Reader for responses from the remote service.
+    pub fn reader(&mut self) -> PktLineReader<&mut dyn Read> {
+        PktLineReader::new(self.reader.as_mut())
+    }
+
+    /// Close our side of the stream, telling the remote we are done.
+    pub fn close(&mut self) {
+        // A protocol v0 server expects a flush if we end without a request.
+        if self.idle && !self.advertisement.is_v2() {
+            if let Ok(mut writer) = self.writer() {
+                let _ = writer.write_flush();
+            }
+        }
+        self.writer = None;
+    }
+}
+
+impl Drop for StreamTransport {
+    fn drop(&mut self) {
+        self.close();
+        if let Some(child) = &mut self.child {
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Spawn ssh to run `service` for the repository at `url`. The command is
+/// taken from `GIT_SSH_COMMAND`, `core.sshCommand` or `GIT_SSH`, falling
+/// back to `ssh`.
+fn spawn_ssh(url: &str, service: &str) -> crate::Result<(Child, ChildStdin, ChildStdout)> {
+    let (host, port, path) = parse_ssh_url(url)?;
+    // ssh would take a host or user name starting with `-` as an option.
+    let (user, hostname) = host.rsplit_once('@').unwrap_or(("", &host));
+    crate::ensure!(
+        !user.starts_with('-') && !hostname.starts_with('-'),
+        "strange hostname '{host}' blocked"
+    );
+    crate::ensure!(
+        port.as_ref().is_none_or(|port| !port.starts_with('-')),
+        "strange port '{}' blocked",
+        port.as_deref().unwrap_or_default()
+    );
+    let config = Config::read_all()?;
+    let (program, use_shell) = match std::env::var("GIT_SSH_COMMAND") {
+        Ok(command) => (command, true),
+        Err(_) => match config.get("core.sshcommand") {
+            Some(command) => (command.to_owned(), true),
+            None => (std::env::var("GIT_SSH").unwrap_or("ssh".to_owned()), false),
+        },
+    };
+    // Only upload-pack speaks protocol v2.
+    let want_v2 = service == "git-upload-pack";
+
+    let mut args = Vec::new();
+    let is_openssh = program
+        .split_whitespace()
+        .next()
+        .and_then(|program| Path::new(program).file_name())
+        .is_some_and(|name| name == "ssh");
+    if is_openssh && want_v2 {
+        args.extend(["-o".to_owned(), "SendEnv=GIT_PROTOCOL".to_owned()]);
+    }
+    if let Some(port) = port {
+        args.extend(["-p".to_owned(), port]);
+    }
+    args.push(host);
+    args.push(format!("{service} {}", shell_quote(&path)));
+
+    let mut command = if use_shell {
+        // Like git, let the shell split the command and append our arguments.
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("{program} \"$@\""))
+            .arg(&program);
+        command
+    } else {
+        Command::new(&program)
+    };
+    command
+        .args(&args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped());
+    if want_v2 {
+        command.env("GIT_PROTOCOL", "version=2");
+    }
+    log::debug!("Running {program} {args:?}");
+    let mut child = command.spawn().context(format!("run {program}"))?;
+    let stdin = child.stdin.take().context("ssh stdin")?;
+    let stdout = child.stdout.take().context("ssh stdout")?;
+
+    Ok((child, stdin, stdout))
+}
+
+/// Connect to the git daemon serving the `git://` URL `url` and request `service`.
+fn connect_daemon(url: &str, service: &str) -> crate::Result<(DaemonWriter, TcpStream)> {
+    let rest = url.strip_prefix("git://").context("not a git:// url")?;
+    let (authority, path) = rest
+        .find('/')
+        .map(|slash| rest.split_at(slash))
+        .context(format!("invalid git url {url}"))?;
+    crate::ensure!(!authority.is_empty(), "invalid git url {url}");
+    let addr = if authority.contains(':') {
+        authority.to_owned()
+    } else {
+        format!("{authority}:{DAEMON_PORT}")
+    };
+    log::debug!("Connecting to {addr}");
+    let stream = TcpStream::connect(&addr).context(format!("connect to {addr}"))?;
+
+    let mut writer = PktLineWriter::new(&stream);
+    let mut request = format!("{service} {path}\0host={authority}\0");
+    // Extra parameters follow an empty field. Only upload-pack speaks protocol v2.
+    if service == "git-upload-pack" {
+        request.push_str("\0version=2\0");
+    }
+    writer.write_data(request.as_bytes())?;
+
+    Ok((DaemonWriter(stream.try_clone()?), stream))
+}
+
+/// Write half of a git daemon connection, shut down when dropped so the
+/// daemon sees the end of our requests.
+struct DaemonWriter(TcpStream);
+
+impl Write for DaemonWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Drop for DaemonWriter {
+    fn drop(&mut self) {
+        let _ = self.0.shutdown(Shutdown::Write);
+    }
+}
+
+/// Split an ssh URL into the `[user@]host` to connect to, the port and the
+/// repository path.
+fn parse_ssh_url(url: &str) -> crate::Result<(String, Option<String>, String)> {
+    let Some(rest) = url
+        .strip_prefix("ssh://")
+        .or(url.strip_prefix("git+ssh://"))
+    else {
+        let (host, path) = url
+            .split_once(':')
+            .context(format!("invalid ssh url {url}"))?;
+        return Ok((host.to_owned(), None, path.to_owned()));
+    };
+    let (authority, path) = rest
+        .find('/')
+        .map(|slash| rest.split_at(slash))
+        .context(format!("invalid ssh url {url}"))?;
+    // `ssh://host/~user/repo` is relative to a home directory.
+    let path = path
+        .strip_prefix('/')
+        .filter(|path| path.starts_with('~'))
+        .unwrap_or(path);
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port.to_owned())),
+        None => (authority, None),
+    };
+    crate::ensure!(!host.is_empty(), "invalid ssh url {url}");
+
+    Ok((host.to_owned(), port, path.to_owned()))
+}
+
+/// Quote `arg` for the remote shell.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+fn log_protocol(advertisement: &Advertisement) {
+    log::debug!(
+        "Remote speaks protocol {}",
+        if advertisement.is_v2() { "v2" } else { "v0" }
+    );
+}
+
+fn filter_refs(refs: &[RemoteRef], prefixes: &[String]) -> Vec<RemoteRef> {
+    refs.iter()
+        .filter(|r| prefixes.is_empty() || prefixes.iter().any(|p| r.name.starts_with(p)))
+        .cloned()
+        .collect()
+}
+
+/// Capabilities to send with each protocol v2 command.
+fn v2_capabilities(advertisement: &Advertisement) -> crate::Result<Vec<String>> {
+    let mut capabilities = vec!["agent=mygit/0.1.0".to_owned()];
+    if advertisement.has_capability("object-format") {
+        capabilities.push(format!("object-format={}", ObjectFormat::current()?));
+    }
+    Ok(capabilities)
+}
+
+/// Write a protocol v2 `command` request with `capabilities` and `args`.
+fn write_command(
+    writer: &mut PktLineWriter<impl Write>,
+    command: &str,
+    capabilities: &[String],
+    args: &[String],
+) -> crate::Result<()> {
+    writer.write_line(&format!("command={command}"))?;
+    for capability in capabilities {
+        writer.write_line(capability)?;
+    }
+    writer.write_delimiter()?;
+    for arg in args {
+        writer.write_line(arg)?;
+    }
+    writer.write_flush()
+}
+
+fn ls_refs_args(prefixes: &[String]) -> Vec<String> {
+    let mut args = vec!["peel".to_owned(), "symrefs".to_owned()];
+    args.extend(prefixes.iter().map(|prefix| format!("ref-prefix {prefix}")));
+    args
+}
+
+/// Parse a protocol v2 `ls-refs` response.
+fn read_ls_refs(reader: &mut PktLineReader<impl Read>) -> crate::Result<Vec<RemoteRef>> {
+    let (lines, _) = reader.read_lines()?;
+    let mut refs = Vec::new();
+    for line in lines {
+        let mut fields = line.split(' ');
+        let (Some(hash), Some(name)) = (fields.next(), fields.next()) else {
+            crate::bail!(Protocol, "malformed ls-refs line {line:?}");
+        };
+        let mut remote_ref = RemoteRef {
+            name: name.to_owned(),
+            hash: hash.parse()?,
+            peeled: None,
+            symref_target: None,
+        };
+        for attribute in fields {
+            if let Some(peeled) = attribute.strip_prefix("peeled:") {
+                remote_ref.peeled = Some(peeled.parse()?);
+            } else if let Some(target) = attribute.strip_prefix("symref-target:") {
+                remote_ref.symref_target = Some(target.to_owned());
+            }
+        }
+        refs.push(remote_ref);
+    }
+
+    Ok(refs)
+}
+
+/// Arguments of a protocol v2 `fetch` sending all haves in a single round.
+fn fetch_args(request: &FetchRequest) -> Vec<String> {
+    let mut args = vec!["ofs-delta".to_owned()];
+    if request.include_tag {
+        args.push("include-tag".to_owned());
+    }
+    args.extend(request.wants.iter().map(|hash| format!("want {hash}")));
+    args.extend(request.haves.iter().map(|hash| format!("have {hash}")));
+    args.extend(deepen_lines(request));
+    // Protocol v0 sends this as a capability instead.
+    if request.deepen.relative {
+        args.push("deepen-relative".to_owned());
+    }
+    if let Some(filter) = &request.filter {
+        args.push(format!("filter {filter}"));
+    }
+    args.push("done".to_owned());
+    args
+}
+
+/// `shallow` lines for our shallow commits and `deepen` lines for `request.deepen`.
+fn deepen_lines(request: &FetchRequest) -> Vec<String> {
+    let deepen = &request.deepen;
+    let mut lines: Vec<String> = request
+        .shallow
+        .iter()
+        .map(|hash| format!("shallow {hash}"))
+        .collect();
+    if let Some(depth) = deepen.depth {
+        lines.push(format!("deepen {depth}"));
+    }
+    if let Some(since) = deepen.since {
+        lines.push(format!("deepen-since {since}"));
+    }
+    lines.extend(deepen.not.iter().map(|name| format!("deepen-not {name}")));
+    lines
+}
+
+/// Fail unless the remote supports the shallow features `request` needs.
+fn check_shallow_support(
+    advertisement: &Advertisement,
+    request: &FetchRequest,
+) -> crate::Result<()> {
+    if request.shallow.is_empty() && request.deepen.is_empty() {
+        return Ok(());
+    }
+    if advertisement.is_v2() {
+        // Protocol v2 offers all deepening options with the `shallow` feature.
+        let supported = advertisement
+            .capabilities()
+            .iter()
+            .filter_map(|capability| capability.strip_prefix("fetch="))
+            .any(|features| features.split(' ').any(|feature| feature == "shallow"));
+        crate::ensure!(supported, "Server does not support shallow clients");
+        return Ok(());
+    }
+    crate::ensure!(
+        advertisement.has_capability("shallow"),
+        "Server does not support shallow clients"
+    );
+    let deepen = &request.deepen;
+    for (requested, capability, option) in [
+        (deepen.since.is_some(), "deepen-since", "--shallow-since"),
+        (!deepen.not.is_empty(), "deepen-not", "--shallow-exclude"),
+        (deepen.relative, "deepen-relative", "--deepen"),
+    ] {
+        crate::ensure!(
+            !requested || advertisement.has_capability(capability),
+            "Server does not support {option}"
+        );
+    }
+
+    Ok(())
+}
+
+/// `request` without its filter if the remote cannot filter objects, in which
+/// case it sends all of them.
+fn without_unsupported_filter(
+    advertisement: &Advertisement,
+    request: &FetchRequest,
+) -> FetchRequest {
+    let mut request = request.clone();
+    let supported = if advertisement.is_v2() {
+        advertisement
+            .capabilities()
+            .iter()
+            .filter_map(|capability| capability.strip_prefix("fetch="))
+            .any(|features| features.split(' ').any(|feature| feature == "filter"))
+    } else {
+        advertisement.has_capability("filter")
+    };
+    if request.filter.is_some() && !supported {
+        eprintln!("warning: filtering not recognized by server, ignoring");
+        request.filter = None;
+    }
+    request
+}
+
+/// Read the `shallow`/`unshallow` lines sent in response to a deepening request.
+fn read_shallow_info(
+    reader: &mut PktLineReader<impl Read>,
+    response: &mut FetchResponse,
+) -> crate::Result<()> {
+    let (lines, _) = reader.read_lines()?;
+    for line in lines {
+        parse_shallow_line(&line, response)?;
+    }
+
+    Ok(())
+}
+
+fn parse_shallow_line(line: &str, response: &mut FetchResponse) -> crate::Result<()> {
+    match line.split_once(' ') {
+        Some(("shallow", hash)) => response.shallow.push(hash.parse()?),
+        Some(("unshallow", hash)) => response.unshallow.push(hash.parse()?),
+        Some(("ERR", error)) => crate::bail!(Protocol, "remote error: {error}"),
+        _ => crate::bail!(Protocol, "unexpected shallow info {line:?}"),
+    }
+
+    Ok(())
+}
+
+/// Read the sections of a protocol v2 `fetch` response up to the packfile,
+/// keeping `shallow-info` and skipping others such as `acknowledgments`.
+fn read_packfile_section(
+    mut reader: PktLineReader<impl Read>,
+    progress: impl FnMut(&[u8]),
+) -> crate::Result<FetchResponse> {
+    let mut response = FetchResponse::default();
+    let mut section = String::new();
+    loop {
+        match reader.read_packet()? {
+            Some(packet @ Packet::Data(_)) => {
+                let line = packet.as_line().context("invalid fetch response")?;
+                if line == "packfile" {
+                    break;
+                }
+                if section.is_empty() {
+                    section = line.to_owned();
+                } else if section == "shallow-info" {
+                    parse_shallow_line(line, &mut response)?;
+                }
+            }
+            Some(Packet::Delimiter) => section.clear(),
+            Some(Packet::Flush) | Some(Packet::ResponseEnd) | None => {
+                crate::bail!(Protocol, "remote sent no packfile")
+            }
+        }
+    }
+    SidebandReader::new(reader, progress)
+        .read_to_end(&mut response.pack)
+        .context("read packfile")?;
+
+    Ok(response)
+}
+
+/// Capabilities to request in a protocol v0 fetch, as far as the remote supports them.
+fn v0_capabilities(
+    advertisement: &Advertisement,
+    request: &FetchRequest,
+) -> crate::Result<Vec<String>> {
+    let mut capabilities = Vec::new();
+    for capability in ["multi_ack_detailed", "side-band-64k", "ofs-delta"] {
+        if advertisement.has_capability(capability) {
+            capabilities.push(capability.to_owned());
+        }
+    }
+    if request.include_tag && advertisement.has_capability("include-tag") {
+        capabilities.push("include-tag".to_owned());
+    }
+    let deepen = &request.deepen;
+    if deepen.since.is_some() {
+        capabilities.push("deepen-since".to_owned());
+    }
+    if !deepen.not.is_empty() {
+        capabilities.push("deepen-not".to_owned());
+    }
+    if deepen.relative {
+        capabilities.push("deepen-relative".to_owned());
+    }
+    if request.filter.is_some() {
+        capabilities.push("filter".to_owned());
+    }
+    if advertisement.has_capability("object-format") {
+        capabilities.push(format!("object-format={}", ObjectFormat::current()?));
+    }
+    capabilities.push("agent=mygit/0.1.0".to_owned());
+    Ok(capabilities)
+}
+
+/// Write the `want` lines of a protocol v0 request, with the capabilities on
+/// the first, followed by the shallow, deepen and filter lines.
+fn write_wants(
+    writer: &mut PktLineWriter<impl Write>,
+    request: &FetchRequest,
+    capabilities: &[String],
+) -> crate::Result<()> {
+    for (idx, want) in request.wants.iter().enumerate() {
+        if idx == 0 {
+            writer.write_line(&format!("want {want} {}", capabilities.join(" ")))?;
+        } else {
+            writer.write_line(&format!("want {want}"))?;
+        }
+    }
+    for line in deepen_lines(request) {
+        writer.write_line(&line)?;
+    }
+    if let Some(filter) = &request.filter {
+        writer.write_line(&format!("filter {filter}"))?;
+    }
+    writer.write_flush()
+}
+
+/// Write `have` lines, ended by `done` or by a flush to ask for acknowledgments.
+fn write_haves<'a>(
+    writer: &mut PktLineWriter<impl Write>,
+    haves: impl IntoIterator<Item = &'a ObjectId>,
+    done: bool,
+) -> crate::Result<()> {
+    for have in haves {
+        writer.write_line(&format!("have {have}"))?;
+    }
+    if done {
+        writer.write_line("done")
+    } else {
+        writer.write_flush()
+    }
+}
+
+/// Read the acknowledgments of one negotiation round up to `NAK`, adding newly
+/// common commits to `common`. Returns whether the remote is ready to send a pack.
+fn read_acks(
+    reader: &mut PktLineReader<impl Read>,
+    common: &mut Vec<ObjectId>,
+) -> crate::Result<bool> {
+    let mut known: HashSet<ObjectId> = common.iter().copied().collect();
+    let mut ready = false;
+    while let Some(packet) = reader.read_packet()? {
+        let Some(line) = packet.as_line() else {
+            continue;
+        };
+        let mut fields = line.split(' ');
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some("ACK"), Some(hash), Some(status)) => {
+                let hash = hash.parse()?;
+                if known.insert(hash) {
+                    common.push(hash);
+                }
+                ready |= status == "ready";
+            }
+            (Some("NAK"), None, None) => break,
+            (Some("ERR"), ..) => crate::bail!(Protocol, "remote error: {}", &line[4..]),
+            _ => crate::bail!(Protocol, "unexpected negotiation response {line:?}"),
+        }
+    }
+
+    Ok(ready)
+}
+
+/// Read the final acknowledgment of a protocol v0 fetch and the pack after it.
+fn read_pack_v0(
+    mut reader: PktLineReader<impl Read>,
+    sideband: bool,
+    progress: impl FnMut(&[u8]),
+) -> crate::Result<Vec<u8>> {
+    // The pack follows `NAK` or a final `ACK <hash>` without a status.
+    loop {
+        let packet = reader.read_packet()?.context("remote sent no packfile")?;
+        let Some(line) = packet.as_line() else {
+            continue;
+        };
+        if line == "NAK" || (line.starts_with("ACK ") && line.split(' ').count() == 2) {
+            break;
+        }
+        if let Some(error) = line.strip_prefix("ERR ") {
+            crate::bail!(Protocol, "remote error: {error}");
+        }
+    }
+
+    let mut pack = Vec::new();
+    if sideband {
+        SidebandReader::new(reader, progress)
+            .read_to_end(&mut pack)
+            .context("read packfile")?;
+    } else {
+        reader
+            .into_inner()
+            .read_to_end(&mut pack)
+            .context("read packfile")?;
+    }
+
+    Ok(pack)
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use common::{git, test_dir};
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+/// Abbreviated names matching several objects, loose or packed, are
+/// reported as ambiguous rather than missing.
+#[test]
+fn batch_check_reports_ambiguous_names() {
+    let dir = test_dir("batch-ambiguous");
+    git(&dir, &["init", "-q", "repo"]);
+    let repo = dir.join("repo");
+    // Among this many objects some share their first four digits.
+    let files: Vec<String> = (0..1000).map(|n| format!("file{n}")).collect();
+    for (n, file) in files.iter().enumerate() {
+        fs::write(repo.join(file), format!("{n}\n")).unwrap();
+    }
+    let mut args = vec!["hash-object", "-w"];
+    args.extend(files.iter().map(String::as_str));
+    let hashes = git(&repo, &args);
+    let hashes: Vec<&str> = hashes.lines().collect();
+    let mut by_prefix: HashMap<&str, usize> = HashMap::new();
+    for hash in &hashes {
+        *by_prefix.entry(&hash[..4]).or_default() += 1;
+    }
+    let (ambiguous, _) = by_prefix.iter().find(|(_, count)| **count > 1).unwrap();
+    let unique = hashes
+        .iter()
+        .find(|hash| by_prefix[&hash[..4]] == 1)
+        .unwrap();
+
+    let input = format!("{ambiguous}\n{}\nfeedbeef\n", &unique[..7]);
+    let size = git(&repo, &["cat-file", "-s", unique]);
+    let expected = format!(
+        "{ambiguous} ambiguous\n{unique} blob {}\nfeedbeef missing\n",
+        size.trim()
+    );
+    assert_eq!(batch_check(&repo, &input), expected);
+
+    git(&repo, &["add", "."]);
+    git(&repo, &["commit", "-q", "-m", "files"]);
+    git(&repo, &["repack", "-adq"]);
+    git(&repo, &["prune-packed"]);
+    assert_eq!(batch_check(&repo, &input), expected);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+fn batch_check(repo: &Path, input: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mygit"))
+        .current_dir(repo)
+        .args(["cat-file", "--batch-check"])
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("run mygit");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    String::from_utf8(output.stdout).unwrap()
+}
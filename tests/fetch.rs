@@ -0,0 +1,77 @@
+mod common;
+
+use common::{git, mygit, test_dir};
+use std::{fs, process::Command};
+
+/// Ref names advertised by a remote that would address files outside
+/// `refs/` once mapped to local refs are ignored.
+#[test]
+fn ignore_advertised_refs_with_broken_names() {
+    let dir = test_dir("broken-names");
+    git(&dir, &["init", "-q", "server"]);
+    let server = dir.join("server");
+    fs::write(server.join("file"), "contents\n").unwrap();
+    git(&server, &["add", "file"]);
+    git(&server, &["commit", "-q", "-m", "initial"]);
+    git(&server, &["tag", "-a", "-m", "tag", "v1"]);
+    git(&server, &["pack-refs", "--all"]);
+    let commit = git(&server, &["rev-parse", "HEAD"]);
+    let tag = git(&server, &["rev-parse", "v1"]);
+    let mut packed = fs::read_to_string(server.join(".git/packed-refs")).unwrap();
+    packed.push_str(&format!("{} refs/heads/../../../config\n", commit.trim()));
+    packed.push_str(&format!("{} refs/tags/../../../../escaped\n", tag.trim()));
+    fs::write(server.join(".git/packed-refs"), packed).unwrap();
+
+    let output = mygit(&dir, &["clone", "server", "clone"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(
+        stderr.contains("warning: ignoring ref with broken name refs/heads/../../../config"),
+        "{stderr}"
+    );
+    let clone = dir.join("clone");
+    let output = mygit(&clone, &["fetch", "--tags"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let config = fs::read_to_string(clone.join(".git/config")).unwrap();
+    assert!(config.contains("[remote \"origin\"]"), "{config}");
+    assert!(!dir.join("escaped").exists());
+    assert_eq!(
+        git(&clone, &["rev-parse", "refs/tags/v1"]),
+        tag,
+        "valid refs are still fetched"
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Hosts and user names in ssh URLs that ssh would take as options are
+/// refused before running ssh.
+#[test]
+fn refuse_ssh_hosts_looking_like_options() {
+    let dir = test_dir("ssh-options");
+    for url in [
+        "ssh://-oProxyCommand=false/repo",
+        "ssh://-oProxyCommand=false@host/repo",
+        "ssh://host:-1/repo",
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_mygit"))
+            .current_dir(&dir)
+            .args(["clone", url, "clone"])
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_SSH_COMMAND", "echo ran ssh >&2; false")
+            .output()
+            .expect("run mygit");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(stderr.contains("blocked"), "{url}: {stderr}");
+        assert!(!stderr.contains("ran ssh"), "{url}: {stderr}");
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use common::{git, mygit, test_dir};
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+/// Unreachable objects in the packs gc replaces are kept until they expire.
+#[test]
+fn gc_keeps_unreachable_packed_objects_until_expired() {
+    let dir = test_dir("gc-unreachable");
+    git(&dir, &["init", "-q", "repo"]);
+    let repo = dir.join("repo");
+    fs::write(repo.join("file"), "contents\n").unwrap();
+    git(&repo, &["add", "file"]);
+    git(&repo, &["commit", "-q", "-m", "initial"]);
+    git(&repo, &["repack", "-q", "-a", "-d"]);
+
+    // Pack an unreachable blob on its own.
+    let blob = git(&repo, &["hash-object", "-w", "--stdin"]);
+    let blob = blob.trim();
+    let mut pack_objects = Command::new("git")
+        .current_dir(&repo)
+        .args(["pack-objects", "-q", ".git/objects/pack/pack"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    writeln!(pack_objects.stdin.take().unwrap(), "{blob}").unwrap();
+    assert!(pack_objects.wait().unwrap().success());
+    git(&repo, &["prune-packed"]);
+
+    for prune in ["never", "2.weeks.ago"] {
+        let output = mygit(&repo, &["gc", &format!("--prune={prune}")]);
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        git(&repo, &["cat-file", "-e", blob]);
+    }
+    git(&repo, &["fsck", "--strict"]);
+
+    let output = mygit(&repo, &["gc", "--prune=now"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let missing = Command::new("git")
+        .current_dir(&repo)
+        .args(["cat-file", "-e", blob])
+        .status()
+        .unwrap();
+    assert!(!missing.success(), "unreachable blob was not pruned");
+    git(&repo, &["fsck", "--strict"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use common::{git, test_dir};
+use sha1::{Digest, Sha1};
+use std::{fs, io::Write, process::Command};
+
+/// git pushes thin packs, with deltas against objects the server already
+/// has. The stored pack must contain those bases to be readable by git.
+#[test]
+fn push_thin_pack_with_delta_against_existing_base() {
+    let dir = test_dir("thin-push");
+    git(&dir, &["init", "-q", "--bare", "server.git"]);
+    git(&dir, &["init", "-q", "work"]);
+    let work = dir.join("work");
+    let lines: String = (1..=5000).map(|line| format!("{line}\n")).collect();
+    fs::write(work.join("file"), &lines).unwrap();
+    git(&work, &["add", "file"]);
+    git(&work, &["commit", "-q", "-m", "base"]);
+    git(
+        &work,
+        &["push", "-q", "../server.git", "HEAD:refs/heads/main"],
+    );
+
+    // A small change to a large file is sent as a delta against the old blob.
+    fs::write(work.join("file"), format!("{lines}5001\n")).unwrap();
+    git(&work, &["commit", "-q", "-a", "-m", "change"]);
+    let receive_pack = format!("{} receive-pack", env!("CARGO_BIN_EXE_mygit"));
+    git(
+        &work,
+        &[
+            "push",
+            "-q",
+            "--thin",
+            &format!("--receive-pack={receive_pack}"),
+            "../server.git",
+            "HEAD:refs/heads/main",
+        ],
+    );
+
+    let server = dir.join("server.git");
+    assert_eq!(
+        git(&server, &["rev-parse", "refs/heads/main"]),
+        git(&work, &["rev-parse", "HEAD"])
+    );
+    git(&server, &["fsck", "--strict"]);
+    for pack in fs::read_dir(server.join("objects/pack")).unwrap() {
+        let pack = pack.unwrap().path();
+        if pack.extension().is_some_and(|ext| ext == "pack") {
+            git(&server, &["verify-pack", pack.to_str().unwrap()]);
+        }
+    }
+    git(&server, &["log", "--oneline", "main"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Ref names that would address files outside `refs/` are refused.
+#[test]
+fn refuse_funny_ref_names() {
+    let dir = test_dir("funny-refname");
+    git(&dir, &["init", "-q", "--bare", "server.git"]);
+    let server = dir.join("server.git");
+    let blob = git(&server, &["hash-object", "-w", "--stdin"]);
+    let null = "0".repeat(40);
+    let mut request = Vec::new();
+    for (idx, name) in ["refs/../../hooks/x", "refs/heads/../../../escaped"]
+        .iter()
+        .enumerate()
+    {
+        let caps = if idx == 0 { "\0report-status" } else { "" };
+        let line = format!("{null} {} {name}{caps}\n", blob.trim());
+        request.extend(format!("{:04x}{line}", line.len() + 4).into_bytes());
+    }
+    request.extend(b"0000");
+    // An empty pack.
+    let mut pack = b"PACK\0\0\0\x02\0\0\0\0".to_vec();
+    pack.extend(Sha1::digest(&pack));
+    request.extend(pack);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mygit"))
+        .args(["receive-pack", "--stateless-rpc"])
+        .arg(&server)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&request).unwrap();
+    let output = child.wait_with_output().unwrap();
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        report.contains("ng refs/../../hooks/x funny refname"),
+        "{report}"
+    );
+    assert!(
+        report.contains("ng refs/heads/../../../escaped funny refname"),
+        "{report}"
+    );
+    assert!(!server.join("hooks/x").exists());
+    assert!(!dir.join("escaped").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::{git, test_dir};
+use std::{fs, process::Command};
+
+/// Objects can be fetched by id only if a ref leads to them.
+#[test]
+fn refuse_wants_not_reachable_from_refs() {
+    let dir = test_dir("unreachable-wants");
+    git(&dir, &["init", "-q", "server"]);
+    let server = dir.join("server");
+    fs::write(server.join("file"), "contents\n").unwrap();
+    git(&server, &["add", "file"]);
+    git(&server, &["commit", "-q", "-m", "initial"]);
+    let reachable = git(&server, &["rev-parse", "HEAD:file"]);
+    fs::write(dir.join("secret"), "secret\n").unwrap();
+    let secret = git(&server, &["hash-object", "-w", "../secret"]);
+
+    let upload_pack = format!("{} upload-pack", env!("CARGO_BIN_EXE_mygit"));
+    let url = format!("file://{}", server.display());
+    git(
+        &dir,
+        &["clone", "-q", "--upload-pack", &upload_pack, &url, "clone"],
+    );
+    let clone = dir.join("clone");
+    let fetch = |want: &str| {
+        Command::new("git")
+            .current_dir(&clone)
+            .args(["fetch", "-q", "--upload-pack", &upload_pack, "origin"])
+            .arg(want.trim())
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .output()
+            .expect("run git")
+    };
+
+    let output = fetch(&secret);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains(&format!("not our ref {}", secret.trim())),
+        "{stderr}"
+    );
+    let output = fetch(&reachable);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}